@@ -0,0 +1,364 @@
+use super::*;
+
+// Set-associative instruction and data caches driven by the Effects stream:
+// every step fetches its instruction through the I-cache, and any memory read
+// or write it made goes through the D-cache.
+//
+// A cache is configured with a comma-separated list of settings, e.g.,
+//
+//   size=4k,ways=2,line=32,replace=lru,write=back
+//
+// replace is lru, fifo, or random; write is back (write-back with write
+// allocate) or through (write-through without write allocate).
+
+#[derive(Clone, Copy, PartialEq)]
+enum Replacement {
+    Lru,
+    Fifo,
+    Random,
+}
+
+#[derive(Clone, Copy)]
+pub struct CacheConfig {
+    size: usize,
+    ways: usize,
+    line: usize,
+    replacement: Replacement,
+    write_back: bool,
+}
+
+impl CacheConfig {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut config = CacheConfig { size: 4096, ways: 1, line: 32, replacement: Replacement::Lru, write_back: true };
+        for setting in spec.split(',').filter(|s| !s.is_empty()) {
+            let Some((key, value)) = setting.split_once('=') else {
+                return Err(format!("cache setting {setting} should be key=value"));
+            };
+            match key {
+                "size" => config.size = parse_size(key, value)?,
+                "ways" => config.ways = parse_size(key, value)?,
+                "line" => config.line = parse_size(key, value)?,
+                "replace" => {
+                    config.replacement = match value {
+                        "lru" => Replacement::Lru,
+                        "fifo" => Replacement::Fifo,
+                        "random" => Replacement::Random,
+                        _ => return Err(format!("unknown cache replacement policy {value}")),
+                    }
+                }
+                "write" => {
+                    config.write_back = match value {
+                        "back" => true,
+                        "through" => false,
+                        _ => return Err(format!("unknown cache write policy {value}")),
+                    }
+                }
+                _ => return Err(format!("unknown cache setting {key}")),
+            }
+        }
+
+        if config.ways == 0 {
+            return Err("cache ways must be at least 1".to_string());
+        }
+        if !config.size.is_power_of_two() || !config.line.is_power_of_two() {
+            return Err("cache size and line size must be powers of two".to_string());
+        }
+        if config.size < config.line * config.ways || !(config.size / config.line).is_multiple_of(config.ways) {
+            return Err("cache size must hold a whole number of sets".to_string());
+        }
+        Ok(config)
+    }
+}
+
+// a count or size, with k for 1024; zero is left for the caller to reject
+fn parse_size(key: &str, s: &str) -> Result<usize, String> {
+    let (digits, scale) = match s.strip_suffix(['k', 'K']) {
+        Some(digits) => (digits, 1024),
+        None => (s, 1),
+    };
+    match digits.parse::<usize>().ok().and_then(|n| n.checked_mul(scale)) {
+        Some(n) => Ok(n),
+        None => Err(format!("invalid cache {key} {s}")),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Line {
+    tag: i64,
+    dirty: bool,
+    last_used: u64,
+    inserted: u64,
+}
+
+#[derive(Clone)]
+pub struct Cache {
+    config: CacheConfig,
+    sets: Vec<Vec<Line>>,
+    clock: u64,
+    rng: u64,
+    hits: usize,
+    misses: usize,
+    writebacks: usize,    // dirty lines evicted (write-back)
+    memory_writes: usize, // stores sent to memory (write-through)
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        let sets = config.size / config.line / config.ways;
+        Cache {
+            config,
+            sets: vec![Vec::with_capacity(config.ways); sets],
+            clock: 0,
+            rng: 0x2545f4914f6cdd1d,
+            hits: 0,
+            misses: 0,
+            writebacks: 0,
+            memory_writes: 0,
+        }
+    }
+
+    // access every line touched by [addr, addr+size); a hit only if all of them hit
+    pub fn access(&mut self, addr: i64, size: usize, is_write: bool) -> bool {
+        let line_size = self.config.line as i64;
+        let first = addr.div_euclid(line_size);
+        let last = (addr + size.max(1) as i64 - 1).div_euclid(line_size);
+        let mut hit = true;
+        for line in first..=last {
+            hit &= self.access_line(line, is_write);
+        }
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        if is_write && !self.config.write_back {
+            self.memory_writes += 1;
+        }
+        hit
+    }
+
+    fn access_line(&mut self, line: i64, is_write: bool) -> bool {
+        self.clock += 1;
+        let set_count = self.sets.len() as i64;
+        let set = &mut self.sets[line.rem_euclid(set_count) as usize];
+        let tag = line.div_euclid(set_count);
+
+        if let Some(entry) = set.iter_mut().find(|entry| entry.tag == tag) {
+            entry.last_used = self.clock;
+            entry.dirty |= is_write && self.config.write_back;
+            return true;
+        }
+
+        // write-through caches do not allocate on a write miss
+        if is_write && !self.config.write_back {
+            return false;
+        }
+
+        let entry = Line { tag, dirty: is_write, last_used: self.clock, inserted: self.clock };
+        if set.len() < self.config.ways {
+            set.push(entry);
+            return false;
+        }
+        let victim = match self.config.replacement {
+            Replacement::Lru => (0..set.len()).min_by_key(|&i| set[i].last_used).unwrap(),
+            Replacement::Fifo => (0..set.len()).min_by_key(|&i| set[i].inserted).unwrap(),
+            Replacement::Random => {
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                (self.rng % set.len() as u64) as usize
+            }
+        };
+        if set[victim].dirty {
+            self.writebacks += 1;
+        }
+        set[victim] = entry;
+        false
+    }
+
+    fn describe(&self, name: &str, writes: bool) -> String {
+        let config = &self.config;
+        let replacement = match config.replacement {
+            Replacement::Lru => "lru",
+            Replacement::Fifo => "fifo",
+            Replacement::Random => "random",
+        };
+        let accesses = self.hits + self.misses;
+        let rate = if accesses > 0 { self.hits as f64 * 100.0 / accesses as f64 } else { 0.0 };
+        let mut out = format!(
+            "{}: {} bytes, {}-way, {}-byte lines, {}: {} accesses, {} hits, {} misses ({:.2}% hits)",
+            name, config.size, config.ways, config.line, replacement, accesses, self.hits, self.misses, rate
+        );
+        if writes && config.write_back {
+            write!(out, ", write-back, {} writebacks", self.writebacks).unwrap();
+        } else if writes {
+            write!(out, ", write-through, {} memory writes", self.memory_writes).unwrap();
+        }
+        out
+    }
+}
+
+#[derive(Clone)]
+pub struct Caches {
+    pub icache: Option<Cache>,
+    pub dcache: Option<Cache>,
+}
+
+// the outcome of one step: (fetch hit, data access hit if it touched memory)
+pub type Hits = (Option<bool>, Option<bool>);
+
+impl Caches {
+    pub fn is_enabled(&self) -> bool {
+        self.icache.is_some() || self.dcache.is_some()
+    }
+
+    // simulate one step
+    pub fn step(&mut self, effects: &Effects) -> Hits {
+        let fetch = self
+            .icache
+            .as_mut()
+            .map(|icache| icache.access(effects.instruction.address, effects.instruction.length as usize, false));
+
+        let data = self.dcache.as_mut().and_then(|dcache| {
+            let read = effects.mem_read.as_ref().map(|read| dcache.access(read.address, read.value.len(), false));
+            let write =
+                effects.mem_write.as_ref().map(|(_, write)| dcache.access(write.address, write.value.len(), true));
+            match (read, write) {
+                (Some(read), Some(write)) => Some(read && write),
+                (read, write) => read.or(write),
+            }
+        });
+
+        (fetch, data)
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    hits: usize,
+    misses: usize,
+}
+
+impl Counts {
+    fn add(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+}
+
+pub struct CacheStats {
+    caches: Caches,
+    functions: Symbols,
+    objects: Symbols,
+    text: (i64, i64),
+    stack_start: i64,
+    by_function: HashMap<String, (Counts, Counts)>, // (fetches, data accesses)
+    by_symbol: HashMap<String, Counts>,
+}
+
+impl CacheStats {
+    pub fn new(m: &Machine, caches: Caches) -> Self {
+        let (functions, objects) = m.named_symbols();
+        CacheStats {
+            caches,
+            functions,
+            objects,
+            text: (m.text_start, m.text_end),
+            stack_start: m.harts.iter().map(|hart| hart.stack_start).min().unwrap(),
+            by_function: HashMap::new(),
+            by_symbol: HashMap::new(),
+        }
+    }
+
+    fn data_symbol(&self, address: i64) -> String {
+        if address >= self.stack_start {
+            return "[stack]".to_string();
+        }
+        if self.text.0 <= address && address < self.text.1 {
+            return "[text]".to_string();
+        }
+        match nearest_symbol(&self.objects, address) {
+            Some(name) => name.to_string(),
+            None => "[data]".to_string(),
+        }
+    }
+}
+
+impl Analysis for CacheStats {
+    fn step(&mut self, effects: &Effects) {
+        let (fetch, data) = self.caches.step(effects);
+        let function = nearest_symbol(&self.functions, effects.instruction.address).unwrap_or("[unknown]").to_string();
+        let (fetches, accesses) = self.by_function.entry(function).or_default();
+        if let Some(hit) = fetch {
+            fetches.add(hit);
+        }
+        if let Some(hit) = data {
+            accesses.add(hit);
+            let address = match (&effects.mem_read, &effects.mem_write) {
+                (Some(read), _) => read.address,
+                (None, Some((_, write))) => write.address,
+                (None, None) => unreachable!(),
+            };
+            let symbol = self.data_symbol(address);
+            self.by_symbol.entry(symbol).or_default().add(hit);
+        }
+    }
+
+    fn report(&self, _m: &Machine) -> String {
+        let mut out = String::new();
+        if let Some(icache) = &self.caches.icache {
+            writeln!(out, "{}", icache.describe("icache", false)).unwrap();
+        }
+        if let Some(dcache) = &self.caches.dcache {
+            writeln!(out, "{}", dcache.describe("dcache", true)).unwrap();
+        }
+
+        let mut functions: Vec<_> = self.by_function.iter().collect();
+        functions
+            .sort_by_key(|(name, (fetches, accesses))| (std::cmp::Reverse(fetches.misses + accesses.misses), *name));
+        writeln!(out, "  by function (fetch hits/misses, data hits/misses):").unwrap();
+        for (name, (fetches, accesses)) in functions {
+            writeln!(
+                out,
+                "    {:<24} {:>10} {:>10} {:>10} {:>10}",
+                name, fetches.hits, fetches.misses, accesses.hits, accesses.misses
+            )
+            .unwrap();
+        }
+
+        if !self.by_symbol.is_empty() {
+            let mut symbols: Vec<_> = self.by_symbol.iter().collect();
+            symbols.sort_by_key(|(name, counts)| (std::cmp::Reverse(counts.misses), *name));
+            writeln!(out, "  by data symbol (hits/misses):").unwrap();
+            for (name, counts) in symbols {
+                writeln!(out, "    {:<24} {:>10} {:>10}", name, counts.hits, counts.misses).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(spec: &str) -> String {
+        match CacheConfig::parse(spec) {
+            Ok(_) => panic!("{} should not parse", spec),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn cache_settings_are_checked_one_by_one() {
+        assert!(CacheConfig::parse("size=4k,ways=2,line=64").is_ok());
+        assert_eq!(error("ways=0"), "cache ways must be at least 1");
+        assert_eq!(error("ways=two"), "invalid cache ways two");
+        assert_eq!(error("size=3k"), "cache size and line size must be powers of two");
+        assert_eq!(error("line=0"), "cache size and line size must be powers of two");
+        assert_eq!(error("size=64,line=32,ways=4"), "cache size must hold a whole number of sets");
+    }
+}
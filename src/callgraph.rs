@@ -0,0 +1,148 @@
+use super::*;
+
+// A static report of the function table and who calls whom, from the code
+// alone: calls whose target is known (jal and call), jumps and tail calls to
+// the start of another function, and calls through a register, which can
+// only be counted. Functions that nothing reaches from the entry point by a
+// direct call, jump, branch, or running off the end of the previous function
+// are listed at the end, since they are either dead or only called
+// indirectly.
+
+#[derive(Default)]
+struct Sites {
+    calls: usize,
+    tail: usize,
+}
+
+pub fn callgraph_report(m: &Machine, instructions: &[Rc<Instruction>]) -> String {
+    let functions = &m.functions;
+    let containing = |address: i64| {
+        let i = functions.partition_point(|f| f.start <= address);
+        (i > 0 && address < functions[i - 1].end).then(|| i - 1)
+    };
+
+    let mut edges: BTreeMap<(usize, usize), Sites> = BTreeMap::new();
+    let mut indirect = vec![0; functions.len()];
+    let mut flows = HashSet::new(); // (from, to) for branches and falling through, which are not calls
+    for line in instructions.chunk_by(|a, b| a.pseudo_index == b.pseudo_index) {
+        let last = line.last().unwrap();
+        let Some(caller) = containing(line[0].address) else { continue };
+        let next = last.address + last.length;
+        if let Some(to) = containing(next)
+            && to != caller
+            && (!is_jump(&last.op) || is_call(&last.op))
+            && last.op != Op::Ecall
+        {
+            flows.insert((caller, to));
+        }
+        if let Some(target) = last.op.branch_target(last.address)
+            && let Some(to) = containing(target)
+            && to != caller
+        {
+            flows.insert((caller, to));
+        }
+        if !is_jump(&last.op) || is_return(&last.op) {
+            continue;
+        }
+        match (static_target(line), is_call(&last.op)) {
+            (Some(target), true) => {
+                if let Some(callee) = containing(target) {
+                    edges.entry((caller, callee)).or_default().calls += 1;
+                }
+            }
+            (Some(target), false) => {
+                if let Some(callee) = containing(target)
+                    && callee != caller
+                    && functions[callee].start == target
+                {
+                    edges.entry((caller, callee)).or_default().tail += 1;
+                }
+            }
+            (None, true) => indirect[caller] += 1,
+            (None, false) => {}
+        }
+    }
+
+    // functions reachable from a function by calls, or also by branches and falling through
+    let reachable = |from: usize, with_flows: bool| {
+        let mut seen = vec![false; functions.len()];
+        let mut pending = vec![from];
+        while let Some(f) = pending.pop() {
+            let flows = flows.iter().filter(|_| with_flows);
+            for &(_, callee) in edges.keys().chain(flows).filter(|(caller, _)| *caller == f) {
+                if !seen[callee] {
+                    seen[callee] = true;
+                    pending.push(callee);
+                }
+            }
+        }
+        seen
+    };
+
+    let mut out = String::new();
+    writeln!(out, "functions:").unwrap();
+    writeln!(out, "  {:>10} {:>10} {:>6}  name", "start", "end", "size").unwrap();
+    for function in functions {
+        writeln!(
+            out,
+            "  {:>10} {:>10} {:>6}  {}",
+            format!("0x{:x}", function.start),
+            format!("0x{:x}", function.end),
+            function.end - function.start,
+            function.name
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "call graph (call sites):").unwrap();
+    let list = |pairs: Vec<(usize, &Sites)>| {
+        pairs
+            .into_iter()
+            .map(|(f, sites)| match (sites.calls, sites.tail) {
+                (calls, 0) => format!("{} ({})", functions[f].name, calls),
+                (0, tail) => format!("{} ({} tail)", functions[f].name, tail),
+                (calls, tail) => format!("{} ({}, {} tail)", functions[f].name, calls, tail),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    for (f, function) in functions.iter().enumerate() {
+        let callers: Vec<_> = edges.iter().filter(|((_, callee), _)| *callee == f).map(|(&(g, _), s)| (g, s)).collect();
+        let callees: Vec<_> = edges.iter().filter(|((caller, _), _)| *caller == f).map(|(&(_, g), s)| (g, s)).collect();
+        if callers.is_empty() && callees.is_empty() && indirect[f] == 0 {
+            continue;
+        }
+        let recursive = if reachable(f, false)[f] { " (recursive)" } else { "" };
+        writeln!(out, "  {}{}", function.name, recursive).unwrap();
+        if !callers.is_empty() {
+            writeln!(out, "      called by: {}", list(callers)).unwrap();
+        }
+        let mut calls = list(callees);
+        if indirect[f] > 0 {
+            if !calls.is_empty() {
+                calls.push_str(", ");
+            }
+            write!(calls, "{} through a register", indirect[f]).unwrap();
+        }
+        if !calls.is_empty() {
+            writeln!(out, "      calls:     {}", calls).unwrap();
+        }
+    }
+
+    if let Some(entry) = containing(m.pc_start) {
+        let mut seen = reachable(entry, true);
+        seen[entry] = true;
+        let unreached: Vec<&str> =
+            functions.iter().zip(seen).filter(|(_, seen)| !seen).map(|(f, _)| f.name.as_str()).collect();
+        if !unreached.is_empty() {
+            writeln!(
+                out,
+                "not reached from {} except maybe through a register: {}",
+                functions[entry].name,
+                unreached.join(", ")
+            )
+            .unwrap();
+        }
+    }
+    out
+}
@@ -0,0 +1,292 @@
+use super::*;
+
+// Control-flow graphs in Graphviz DOT format, one cluster per function.
+// Basic blocks start at each function symbol, at each branch or jump target,
+// and after each conditional branch, jump, or return; they are built from
+// whole lines of the pseudo-instruction listing, so a call or tail sequence
+// stays in one block. Calls do not end a block; they add a dashed edge to the
+// entry of the function they call.
+//
+// -m cfg prints the graph from the code alone. --cfg <file> saves it after a
+// run, with each block and edge labeled by how many times it ran, plus edges
+// for indirect jumps and calls that the code alone does not reveal. Returns
+// never get edges.
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdgeKind {
+    Taken,
+    Fallthrough,
+    Jump,
+    Call,
+}
+
+pub struct Block {
+    pub function: usize, // index into the function table, or past its end for code outside any function
+    pub lines: std::ops::Range<usize>,
+}
+
+pub struct ControlFlowGraph {
+    lines: Vec<Vec<Rc<Instruction>>>,
+    executable: String,
+    cfg_file: Option<String>,
+    counts: HashMap<i64, usize>,             // address => times run
+    transitions: HashMap<(i64, i64), usize>, // (jump or branch address, next pc) => times
+}
+
+pub fn is_jump(op: &Op) -> bool {
+    matches!(op, Op::Jal { .. } | Op::Jalr { .. })
+}
+
+pub fn is_call(op: &Op) -> bool {
+    link_register(op).is_some()
+}
+
+pub fn is_return(op: &Op) -> bool {
+    matches!(op, Op::Jalr { rd: ZERO, rs1: RA, .. })
+}
+
+// where the jump or branch ending a line goes, if the code alone says
+pub fn static_target(line: &[Rc<Instruction>]) -> Option<i64> {
+    let last = line.last().unwrap();
+    match (&line[0].op, &last.op) {
+        (_, Op::Jal { offset, .. }) => Some(last.address + offset),
+        (Op::Auipc { rd, imm }, Op::Jalr { rs1, offset, .. }) if line.len() == 2 && rd == rs1 => {
+            Some(line[0].address + imm + offset)
+        }
+        _ => last.op.branch_target(last.address),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl ControlFlowGraph {
+    pub fn new(instructions: &[Rc<Instruction>], executable: &str, cfg_file: Option<String>) -> Self {
+        ControlFlowGraph {
+            lines: instructions.chunk_by(|a, b| a.pseudo_index == b.pseudo_index).map(|line| line.to_vec()).collect(),
+            executable: executable.to_string(),
+            cfg_file,
+            counts: HashMap::new(),
+            transitions: HashMap::new(),
+        }
+    }
+
+    // the pseudo-instruction lines of a block, each holding its real instructions
+    pub fn lines(&self, block: &Block) -> &[Vec<Rc<Instruction>>] {
+        &self.lines[block.lines.clone()]
+    }
+
+    fn line_of(&self, address: i64) -> Option<usize> {
+        let i = self.lines.partition_point(|line| line[0].address <= address);
+        if i == 0 {
+            return None;
+        }
+        let last = self.lines[i - 1].last().unwrap();
+        if address < last.address + last.length { Some(i - 1) } else { None }
+    }
+
+    pub fn blocks(&self, functions: &Symbols) -> Vec<Block> {
+        let function_of = |address: i64| match functions.partition_point(|(start, _)| *start <= address) {
+            0 => functions.len(),
+            i => i - 1,
+        };
+
+        let mut leaders = HashSet::new();
+        for (address, _) in functions {
+            leaders.extend(self.line_of(*address));
+        }
+        for (n, line) in self.lines.iter().enumerate() {
+            let last = line.last().unwrap();
+            if let Some(target) = static_target(line)
+                && !is_call(&last.op)
+            {
+                leaders.extend(self.line_of(target));
+            }
+            if is_conditional_branch(&last.op) || (is_jump(&last.op) && !is_call(&last.op)) {
+                leaders.insert(n + 1);
+            }
+        }
+        for &(from, to) in self.transitions.keys() {
+            let Some(n) = self.line_of(from) else { continue };
+            if !is_call(&self.lines[n].last().unwrap().op) {
+                leaders.insert(n + 1);
+                leaders.extend(self.line_of(to));
+            }
+        }
+
+        let mut blocks: Vec<Block> = Vec::new();
+        for n in 0..self.lines.len() {
+            let function = function_of(self.lines[n][0].address);
+            match blocks.last_mut() {
+                Some(block) if !leaders.contains(&n) && block.function == function => block.lines.end = n + 1,
+                _ => blocks.push(Block { function, lines: n..n + 1 }),
+            }
+        }
+        blocks
+    }
+
+    // times a block ran; a jump may land in the middle of its first line
+    fn block_count(&self, block: &Block) -> usize {
+        self.lines[block.lines.start]
+            .iter()
+            .filter_map(|inst| self.counts.get(&inst.address))
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn edges(&self, blocks: &[Block]) -> BTreeMap<(usize, usize, EdgeKind), usize> {
+        let block_of = |address: i64| {
+            let n = self.line_of(address)?;
+            Some(blocks.partition_point(|block| block.lines.end <= n))
+        };
+        // a system call running into the next function is usually exit
+        let fallthrough_of = |b: usize, line: &[Rc<Instruction>], address: i64| {
+            block_of(address).filter(|&to| blocks[to].function == blocks[b].function || line[0].op != Op::Ecall)
+        };
+        let mut edges = BTreeMap::new();
+        for (b, block) in blocks.iter().enumerate() {
+            for n in block.lines.clone() {
+                let line = &self.lines[n];
+                let last = line.last().unwrap();
+                let next = last.address + last.length;
+                let is_end = n + 1 == block.lines.end;
+                if !is_jump(&last.op) && !is_conditional_branch(&last.op) {
+                    if is_end && let Some(to) = fallthrough_of(b, line, next) {
+                        *edges.entry((b, to, EdgeKind::Fallthrough)).or_default() +=
+                            self.counts.get(&last.address).copied().unwrap_or(0);
+                    }
+                    continue;
+                }
+                if !is_call(&last.op) && !is_end {
+                    continue;
+                }
+
+                // targets known from the code plus any seen during the run
+                let mut targets: BTreeMap<i64, usize> = BTreeMap::new();
+                if let Some(target) = static_target(line) {
+                    targets.insert(target, 0);
+                }
+                if is_conditional_branch(&last.op) {
+                    targets.insert(next, 0);
+                }
+                for (&(from, to), &times) in &self.transitions {
+                    if from == last.address && !(is_call(&last.op) && to == next) {
+                        *targets.entry(to).or_default() += times;
+                    }
+                }
+                for (target, times) in targets {
+                    let Some(to) = block_of(target) else { continue };
+                    let kind = if is_call(&last.op) {
+                        EdgeKind::Call
+                    } else if !is_conditional_branch(&last.op) {
+                        EdgeKind::Jump
+                    } else if target == next {
+                        EdgeKind::Fallthrough
+                    } else {
+                        EdgeKind::Taken
+                    };
+                    *edges.entry((b, to, kind)).or_default() += times;
+                }
+                if is_call(&last.op)
+                    && is_end
+                    && let Some(to) = block_of(next)
+                {
+                    *edges.entry((b, to, EdgeKind::Fallthrough)).or_default() +=
+                        self.counts.get(&last.address).copied().unwrap_or(0);
+                }
+            }
+        }
+        edges
+    }
+
+    pub fn dot(&self, m: &Machine) -> String {
+        let (functions, _) = m.named_symbols();
+        let blocks = self.blocks(&functions);
+        let edges = self.edges(&blocks);
+        let weighted = !self.counts.is_empty();
+        let id = |block: &Block| format!("b{:x}", self.lines[block.lines.start][0].address);
+
+        let mut out = String::new();
+        writeln!(out, "digraph \"{}\" {{", escape(&self.executable)).unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for (function, group) in blocks.chunk_by(|a, b| a.function == b.function).map(|g| (g[0].function, g)) {
+            let name = functions.get(function).map_or("[unknown]", |(_, name)| name);
+            writeln!(out, "    subgraph \"cluster_{:x}\" {{", self.lines[group[0].lines.start][0].address).unwrap();
+            writeln!(out, "        label=\"{}\";", escape(name)).unwrap();
+            for block in group {
+                let mut label = String::new();
+                let start = self.lines[block.lines.start][0].address;
+                match m.address_symbols.get(&start) {
+                    Some(symbol) => write!(label, "0x{:x} {}:", start, symbol).unwrap(),
+                    None => write!(label, "0x{:x}", start).unwrap(),
+                }
+                if weighted {
+                    write!(label, " (ran {})", self.block_count(block)).unwrap();
+                }
+                label.push_str("\\l");
+                for line in &self.lines[block.lines.clone()] {
+                    let inst = &line[0];
+                    let text = fields_to_disassembly(
+                        &inst.pseudo_fields,
+                        inst.address,
+                        m.global_pointer,
+                        inst.length == 2,
+                        false,
+                        false,
+                        m.style.registers,
+                        &m.address_symbols,
+                    );
+                    write!(label, "    {}\\l", escape(&text)).unwrap();
+                }
+                let style = if weighted && self.block_count(block) == 0 { ", style=dashed, color=gray" } else { "" };
+                writeln!(out, "        {} [label=\"{}\"{}];", id(block), label, style).unwrap();
+            }
+            writeln!(out, "    }}").unwrap();
+        }
+
+        let most = edges.values().copied().max().unwrap_or(0).max(1);
+        for (&(from, to, kind), &times) in &edges {
+            let mut attributes = match kind {
+                EdgeKind::Taken => vec!["color=darkgreen".to_string()],
+                EdgeKind::Fallthrough | EdgeKind::Jump => Vec::new(),
+                EdgeKind::Call => vec!["style=dashed".to_string(), "color=blue".to_string()],
+            };
+            if weighted {
+                attributes.push(format!("label=\"{}\"", times));
+                attributes.push(format!("penwidth={:.1}", 1.0 + 4.0 * times as f64 / most as f64));
+            }
+            let attributes =
+                if attributes.is_empty() { String::new() } else { format!(" [{}]", attributes.join(", ")) };
+            writeln!(out, "    {} -> {}{};", id(&blocks[from]), id(&blocks[to]), attributes).unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+impl Analysis for ControlFlowGraph {
+    fn step(&mut self, effects: &Effects) {
+        let inst = &effects.instruction;
+        *self.counts.entry(inst.address).or_default() += 1;
+        if (is_jump(&inst.op) && !is_return(&inst.op)) || is_conditional_branch(&inst.op) {
+            let (_, new_pc) = effects.pc;
+            *self.transitions.entry((inst.address, new_pc)).or_default() += 1;
+        }
+    }
+
+    fn report(&self, m: &Machine) -> String {
+        let (functions, _) = m.named_symbols();
+        let blocks = self.blocks(&functions);
+        let executed = blocks.iter().filter(|block| self.block_count(block) > 0).count();
+        format!("cfg: {} of {} basic blocks executed\n", executed, blocks.len())
+    }
+
+    fn save(&self, m: &Machine) -> Result<(), String> {
+        let Some(filename) = &self.cfg_file else {
+            return Ok(());
+        };
+        std::fs::write(filename, self.dot(m)).map_err(|e| format!("writing {}: {}", filename, e))
+    }
+}
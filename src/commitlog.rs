@@ -0,0 +1,185 @@
+use super::*;
+
+// Commit logs in the format of Spike's --log-commits, for differential testing
+// against the reference simulator. Each step is one line:
+//
+//   core   0: 0 0x0000000000010078 (0x00a00513) x10 0x000000000000000a
+//   core   0: 0 0x000000000001007c (0x0005b503) x10 0x0000000000000003 mem 0x0000000000011000
+//   core   0: 0 0x0000000000010080 (0x00a5b023) mem 0x0000000000011000 0x0000000000000003
+//
+// with the hart, privilege level (risclet always runs in user mode), pc, and
+// instruction bits (4 hex digits if compressed), followed by the register
+// written, then the address of any load, then the address and value of any store.
+//
+// ecall and ebreak are logged without their effects: under Spike the system
+// call is carried out by the proxy kernel's own instructions, so when checking
+// a log those are skipped until execution returns to the next instruction.
+
+// one committed instruction
+struct Commit {
+    hart: usize,
+    pc: u64,
+    bits: u64,
+    reg_writes: Vec<(String, u64)>,
+    loads: Vec<u64>,
+    stores: Vec<(u64, u64, usize)>, // (address, value, hex digits in the value)
+}
+
+impl Commit {
+    fn from_effects(m: &Machine, effects: &Effects) -> Self {
+        let instruction = &effects.instruction;
+        let bits = match m.load_instruction(instruction.address) {
+            Ok((inst, 2)) => inst as u64 & 0xffff,
+            Ok((inst, _)) => inst as u32 as u64,
+            Err(_) => 0,
+        };
+        let mut commit = Commit {
+            hart: effects.hart,
+            pc: effects.pc.0 as u64,
+            bits,
+            reg_writes: vec![],
+            loads: vec![],
+            stores: vec![],
+        };
+        if matches!(instruction.op, Op::Ecall | Op::Ebreak) {
+            return commit;
+        }
+
+        if let Some((_, new)) = &effects.reg_write
+            && new.register != ZERO
+        {
+            commit.reg_writes.push((format!("x{}", new.register), new.value as u64));
+        }
+        if let Some(read) = &effects.mem_read {
+            commit.loads.push(read.address as u64);
+        }
+        if let Some((_, new)) = &effects.mem_write {
+            let value = new.value.iter().rev().fold(0, |value, &b| (value << 8) | b as u64);
+            commit.stores.push((new.address as u64, value, new.value.len() * 2));
+        }
+        commit
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        // core <n>: <priv> <pc> (<bits>) [writes...]
+        let rest = line.strip_prefix("core")?;
+        let (hart, rest) = rest.split_once(':')?;
+        let mut tokens = rest.split_whitespace().peekable();
+        let hart = hart.trim().parse().ok()?;
+        let privilege = tokens.next()?;
+        if privilege.len() != 1 || !privilege.starts_with(|ch: char| ch.is_ascii_digit()) {
+            // an instruction trace line from spike -l rather than a commit
+            return None;
+        }
+        let pc = parse_hex(tokens.next()?)?;
+        let bits = parse_hex(tokens.next()?.strip_prefix('(')?.strip_suffix(')')?)?;
+
+        let mut commit = Commit { hart, pc, bits, reg_writes: vec![], loads: vec![], stores: vec![] };
+        while let Some(token) = tokens.next() {
+            if token == "mem" {
+                let address = parse_hex(tokens.next()?)?;
+                match tokens.peek().and_then(|value| Some((parse_hex(value)?, value.len() - 2))) {
+                    Some((value, digits)) => {
+                        tokens.next();
+                        commit.stores.push((address, value, digits));
+                    }
+                    None => commit.loads.push(address),
+                }
+            } else {
+                // registers (x5, f3) and csrs (c768_mstatus) with their new values
+                let value = parse_hex(tokens.next()?)?;
+                if token.starts_with('x') || token.starts_with('f') {
+                    commit.reg_writes.push((token.to_string(), value));
+                }
+            }
+        }
+        Some(commit)
+    }
+
+    fn describe(&self) -> String {
+        let mut out = String::new();
+        write!(out, "core{:4}: 0 0x{:016x} ", self.hart, self.pc).unwrap();
+        if self.bits & 0b11 != 0b11 {
+            write!(out, "(0x{:04x})", self.bits).unwrap();
+        } else {
+            write!(out, "(0x{:08x})", self.bits).unwrap();
+        }
+        for (reg, value) in &self.reg_writes {
+            write!(out, " {:<3} 0x{:016x}", reg, value).unwrap();
+        }
+        for address in &self.loads {
+            write!(out, " mem 0x{:016x}", address).unwrap();
+        }
+        for &(address, value, digits) in &self.stores {
+            write!(out, " mem 0x{:016x} 0x{:0digits$x}", address, value).unwrap();
+        }
+        out
+    }
+
+    // the same instruction with the same effects; values are compared, not how they were printed
+    fn matches(&self, other: &Commit) -> bool {
+        let stores =
+            |commit: &Commit| commit.stores.iter().map(|&(address, value, _)| (address, value)).collect::<Vec<_>>();
+        self.pc == other.pc
+            && self.bits == other.bits
+            && self.reg_writes == other.reg_writes
+            && self.loads == other.loads
+            && stores(self) == stores(other)
+    }
+}
+
+fn parse_hex(token: &str) -> Option<u64> {
+    u64::from_str_radix(token.strip_prefix("0x")?, 16).ok()
+}
+
+pub fn write_commit_log(filename: &str, m: &Machine, history: &mut History) -> Result<(), String> {
+    let failed = |e: io::Error| format!("writing {}: {}", filename, e);
+    let mut out = io::BufWriter::new(std::fs::File::create(filename).map_err(failed)?);
+    for i in 0..history.len() {
+        let effects = history.get(i);
+        writeln!(out, "{}", Commit::from_effects(m, effects).describe()).map_err(failed)?;
+    }
+    out.flush().map_err(failed)
+}
+
+// compare the trace against a reference commit log and describe the first divergence
+pub fn check_commit_log(filename: &str, m: &Machine, history: &mut History) -> Result<String, String> {
+    let raw = std::fs::read_to_string(filename).map_err(|e| format!("loading {}: {}", filename, e))?;
+    let mut reference = raw.lines().filter_map(Commit::parse).peekable();
+
+    // skip anything the reference ran before the program, like Spike's boot rom
+    if !history.is_empty() {
+        let entry = history.get(0).pc.0 as u64;
+        while reference.next_if(|commit| commit.pc != entry).is_some() {}
+    }
+
+    for i in 0..history.len() {
+        let effects = history.get(i);
+        let ours = Commit::from_effects(m, effects);
+        let Some(theirs) = reference.next() else {
+            return Ok(format!("commit log: {} ended after {} of {} steps\n", filename, i, history.len()));
+        };
+
+        let same = if matches!(effects.instruction.op, Op::Ecall | Op::Ebreak) {
+            ours.pc == theirs.pc && ours.bits == theirs.bits
+        } else {
+            ours.matches(&theirs)
+        };
+        if !same {
+            return Ok(format!(
+                "commit log: first divergence at step {}\n  expected: {}\n  risclet:  {}\n",
+                i + 1,
+                theirs.describe(),
+                ours.describe()
+            ));
+        }
+
+        // skip the reference's system call handler
+        if matches!(effects.instruction.op, Op::Ecall | Op::Ebreak) {
+            let next = effects.pc.1 as u64;
+            while reference.next_if(|commit| commit.pc != next).is_some() {}
+        }
+    }
+
+    Ok(format!("commit log: all {} steps match {}\n", history.len(), filename))
+}
@@ -0,0 +1,178 @@
+use super::*;
+
+// Instruction and branch coverage. The report is the dasm listing annotated
+// with how many times each line ran, with ##### marking lines that never ran
+// and a note on conditional branches that never went one of their two ways.
+//
+// --lcov also saves the counts in lcov's tracefile format. Its line numbers
+// are line numbers in the -m dasm listing of the executable.
+
+#[derive(Default)]
+struct Counts {
+    count: usize,
+    taken: usize, // conditional branches only
+}
+
+pub struct Coverage {
+    instructions: Vec<Rc<Instruction>>,
+    executable: String,
+    lcov_file: Option<String>,
+    counts: HashMap<i64, Counts>,
+}
+
+// one line of the dasm listing
+struct Line<'a> {
+    instructions: &'a [Rc<Instruction>],
+    count: Option<usize>,           // None if it never ran
+    branch: Option<(usize, usize)>, // (taken, not taken) if a conditional branch that ran
+}
+
+impl Coverage {
+    pub fn new(instructions: &[Rc<Instruction>], executable: &str, lcov_file: Option<String>) -> Self {
+        Coverage {
+            instructions: instructions.to_vec(),
+            executable: executable.to_string(),
+            lcov_file,
+            counts: HashMap::new(),
+        }
+    }
+
+    fn lines(&self) -> Vec<Line<'_>> {
+        self.instructions
+            .chunk_by(|a, b| a.pseudo_index == b.pseudo_index)
+            .map(|instructions| {
+                // a jump may land in the middle of a pseudo-instruction
+                let count =
+                    instructions.iter().filter_map(|inst| self.counts.get(&inst.address)).map(|c| c.count).max();
+                let branch = instructions.iter().find(|inst| is_conditional_branch(&inst.op)).and_then(|inst| {
+                    let counts = self.counts.get(&inst.address)?;
+                    Some((counts.taken, counts.count - counts.taken))
+                });
+                Line { instructions, count, branch }
+            })
+            .collect()
+    }
+
+    fn lcov(&self, m: &Machine) -> String {
+        let (functions, _) = m.named_symbols();
+        let lines = self.lines();
+        let mut out = String::new();
+        writeln!(out, "TN:").unwrap();
+        writeln!(out, "SF:{}", self.executable).unwrap();
+
+        let mut function_lines = Vec::new();
+        for (n, line) in lines.iter().enumerate() {
+            let address = line.instructions[0].address;
+            if let Ok(i) = functions.binary_search_by_key(&address, |(start, _)| *start) {
+                function_lines.push((n + 1, &functions[i].1, line.count.unwrap_or(0)));
+            }
+        }
+        for (n, name, _) in &function_lines {
+            writeln!(out, "FN:{},{}", n, name).unwrap();
+        }
+        for (_, name, count) in &function_lines {
+            writeln!(out, "FNDA:{},{}", count, name).unwrap();
+        }
+        writeln!(out, "FNF:{}", function_lines.len()).unwrap();
+        writeln!(out, "FNH:{}", function_lines.iter().filter(|(_, _, count)| *count > 0).count()).unwrap();
+
+        let (mut branches, mut branches_hit) = (0, 0);
+        for (n, line) in lines.iter().enumerate() {
+            if !line.instructions.iter().any(|inst| is_conditional_branch(&inst.op)) {
+                continue;
+            }
+            branches += 2;
+            match line.branch {
+                Some((taken, not_taken)) => {
+                    writeln!(out, "BRDA:{},0,0,{}", n + 1, taken).unwrap();
+                    writeln!(out, "BRDA:{},0,1,{}", n + 1, not_taken).unwrap();
+                    branches_hit += (taken > 0) as usize + (not_taken > 0) as usize;
+                }
+                None => {
+                    writeln!(out, "BRDA:{},0,0,-", n + 1).unwrap();
+                    writeln!(out, "BRDA:{},0,1,-", n + 1).unwrap();
+                }
+            }
+        }
+        writeln!(out, "BRF:{}", branches).unwrap();
+        writeln!(out, "BRH:{}", branches_hit).unwrap();
+
+        for (n, line) in lines.iter().enumerate() {
+            writeln!(out, "DA:{},{}", n + 1, line.count.unwrap_or(0)).unwrap();
+        }
+        writeln!(out, "LF:{}", lines.len()).unwrap();
+        writeln!(out, "LH:{}", lines.iter().filter(|line| line.count.is_some()).count()).unwrap();
+        writeln!(out, "end_of_record").unwrap();
+        out
+    }
+}
+
+impl Analysis for Coverage {
+    fn step(&mut self, effects: &Effects) {
+        let counts = self.counts.entry(effects.instruction.address).or_default();
+        counts.count += 1;
+        if is_conditional_branch(&effects.instruction.op) {
+            let (old_pc, new_pc) = effects.pc;
+            counts.taken += (new_pc != old_pc + effects.instruction.length) as usize;
+        }
+    }
+
+    fn report(&self, m: &Machine) -> String {
+        let lines = self.lines();
+        let mut out = String::new();
+        for line in &lines {
+            let instruction = &line.instructions[0];
+            let text = fields_to_string(
+                &instruction.pseudo_fields,
+                instruction.address,
+                m.global_pointer,
+                instruction.length == 2,
+                false,
+                false,
+                m.style.registers,
+                false,
+                None,
+                &m.address_symbols,
+            );
+            let count = match line.count {
+                Some(count) => count.to_string(),
+                None => "#####".to_string(),
+            };
+            let note = match line.branch {
+                Some((0, _)) => "  <- never taken",
+                Some((_, 0)) => "  <- never falls through",
+                _ => "",
+            };
+            writeln!(out, "{:>10}  {}{}", count, text.trim_end(), note).unwrap();
+        }
+
+        let executed = lines.iter().filter(|line| line.count.is_some()).count();
+        let branches =
+            lines.iter().filter(|line| line.instructions.iter().any(|i| is_conditional_branch(&i.op))).count();
+        let directions: usize = lines
+            .iter()
+            .filter_map(|line| line.branch)
+            .map(|(taken, not_taken)| (taken > 0) as usize + (not_taken > 0) as usize)
+            .sum();
+        let percent = |n: usize, total: usize| if total > 0 { n as f64 * 100.0 / total as f64 } else { 0.0 };
+        writeln!(
+            out,
+            "coverage: {} of {} lines executed ({:.1}%), {} of {} branch directions taken ({:.1}%)",
+            executed,
+            lines.len(),
+            percent(executed, lines.len()),
+            directions,
+            branches * 2,
+            percent(directions, branches * 2)
+        )
+        .unwrap();
+        out
+    }
+
+    fn save(&self, m: &Machine) -> Result<(), String> {
+        let Some(filename) = &self.lcov_file else {
+            return Ok(());
+        };
+        std::fs::write(filename, self.lcov(m)).map_err(|e| format!("writing {}: {}", filename, e))
+    }
+}
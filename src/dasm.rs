@@ -0,0 +1,516 @@
+use super::*;
+
+// Listings for dasm mode beyond the plain one, plus the data listing that
+// follows the plain one. The objdump format follows
+// `objdump -d` closely enough to diff against binutils:
+//
+//      100e8:	1141                	addi	sp,sp,-16
+//      100ea:	00000517          	auipc	a0,0x0
+//
+// The asm format is a .s file that the GNU assembler turns back into the same
+// bytes. It lists real instructions rather than pseudo-instructions, keeps
+// compressed instructions compressed by letting the assembler choose them, and
+// switches compression off around any 32-bit instruction that has a 16-bit form
+// (or, in mostly uncompressed programs, on around each compressed instruction).
+// Anything that would not come back the same (like a hint or an unknown
+// instruction) is written as raw bytes.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DasmFormat {
+    Plain,
+    Objdump,
+    Asm,
+}
+
+impl DasmFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "plain" => Ok(DasmFormat::Plain),
+            "objdump" => Ok(DasmFormat::Objdump),
+            "asm" => Ok(DasmFormat::Asm),
+            _ => Err(format!("unknown dasm format {name}")),
+        }
+    }
+}
+
+// the instruction as stored in memory: 16 bits for compressed instructions
+fn raw_bits(m: &Machine, instruction: &Instruction) -> u32 {
+    match m.load_instruction(instruction.address) {
+        Ok((inst, 2)) => inst as u32 & 0xffff,
+        Ok((inst, _)) => inst as u32,
+        Err(_) => 0,
+    }
+}
+
+fn is_shift(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Slli { .. } | Op::Srli { .. } | Op::Srai { .. } | Op::Slliw { .. } | Op::Srliw { .. } | Op::Sraiw { .. }
+    )
+}
+
+pub fn objdump_listing(filename: &str, m: &Machine, instructions: &[Instruction]) -> String {
+    // objdump only knows the symbol table, not the numbered local labels
+    let mut symbols: Symbols = m
+        .address_symbols
+        .iter()
+        .filter(|(_, name)| name.parse::<i64>().is_err())
+        .map(|(&address, name)| (address, name.clone()))
+        .collect();
+    symbols.sort();
+
+    let mut out = String::new();
+    writeln!(out, "\n{}:     file format elf64-littleriscv\n\n", filename).unwrap();
+    writeln!(out, "Disassembly of section .text:").unwrap();
+    for instruction in instructions {
+        if let Ok(i) = symbols.binary_search_by_key(&instruction.address, |(address, _)| *address) {
+            writeln!(out, "\n{:016x} <{}>:", instruction.address, symbols[i].1).unwrap();
+        }
+        let bits = raw_bits(m, instruction);
+        let raw = if instruction.length == 2 {
+            format!("{:04x}                ", bits)
+        } else {
+            format!("{:08x}          ", bits)
+        };
+        let disasm = objdump_disassembly(instruction, bits, &symbols, m.style);
+        writeln!(out, "{:>8x}:\t{}\t{}", instruction.address, raw, disasm).unwrap();
+    }
+    for (address, bytes) in trailing_bytes(m, instructions) {
+        let (value, directive) = trailing_directive(&bytes);
+        let raw = format!("{:<20}", format!("{:0width$x}", value, width = bytes.len() * 2));
+        writeln!(out, "{:>8x}:\t{}\t{}\t0x{:x}", address, raw, directive, value).unwrap();
+    }
+    out
+}
+
+fn objdump_disassembly(instruction: &Instruction, bits: u32, symbols: &[(i64, String)], style: DisplayStyle) -> String {
+    let op = &instruction.op;
+    let fields = match op {
+        Op::Unimplemented { .. } => return format!(".{}byte\t0x{:x}", instruction.length, bits),
+
+        // like objdump -M no-aliases
+        _ if !style.pseudo => op.to_fields(),
+
+        // where binutils picks different aliases than the pseudo view
+        Op::Addi { rs1: GP, .. } | Op::Beq { rs1: ZERO, .. } | Op::Bne { rs1: ZERO, .. } => op.to_fields(),
+        Op::Addiw { rd, rs1, imm: 0 } => vec![Field::Opcode("sext.w"), Field::Reg(*rd), Field::Reg(*rs1)],
+        Op::Add { rd, rs1: ZERO, rs2 } if instruction.length == 2 => {
+            vec![Field::Opcode("mv"), Field::Reg(*rd), Field::Reg(*rs2)]
+        }
+        Op::Jalr { rd: RA, rs1, offset } => vec![Field::Opcode("jalr"), Field::Indirect(*offset, *rs1)],
+        Op::Jalr { rd: ZERO, rs1, offset } if *offset != 0 => vec![Field::Opcode("jr"), Field::Indirect(*offset, *rs1)],
+        _ => op.to_pseudo_fields(),
+    };
+
+    let operands: Vec<String> = fields[1..]
+        .iter()
+        .map(|field| match *field {
+            Field::Imm(imm) if matches!(op, Op::Lui { .. } | Op::Auipc { .. }) => {
+                format!("0x{:x}", (imm >> 12) & 0xfffff)
+            }
+            Field::Imm(imm) if is_shift(op) => format!("0x{:x}", imm),
+            Field::PCRelAddr(offset) => {
+                let target = instruction.address + offset;
+                let i = symbols.partition_point(|(start, _)| *start <= target);
+                match i.checked_sub(1).map(|i| &symbols[i]) {
+                    Some((start, name)) if *start == target => format!("{:x} <{}>", target, name),
+                    Some((start, name)) => format!("{:x} <{}+0x{:x}>", target, name, target - start),
+                    None => format!("{:x}", target),
+                }
+            }
+            _ => field.to_string(instruction.address, 0, false, true, style.registers, &HashMap::new()),
+        })
+        .collect();
+
+    let mnemonic = fields[0].to_string(instruction.address, 0, false, true, style.registers, &HashMap::new());
+    if operands.is_empty() { mnemonic } else { format!("{}\t{}", mnemonic, operands.join(",")) }
+}
+
+// the labels to use in the .s file: numbered local labels and names that
+// appear more than once (like local symbols from different source files) get
+// the address added so each one is unique, and every jump target gets one
+fn assembly_labels(m: &Machine, instructions: &[Instruction]) -> HashMap<i64, String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for name in m.address_symbols.values() {
+        *counts.entry(name).or_default() += 1;
+    }
+    let mut labels: HashMap<i64, String> = m
+        .address_symbols
+        .iter()
+        .filter(|(_, name)| *name != "__global_pointer$")
+        .map(|(&address, name)| {
+            if name.parse::<i64>().is_ok() {
+                (address, format!(".L{:x}", address))
+            } else if counts[name.as_str()] > 1 {
+                (address, format!("{}.{:x}", name, address))
+            } else {
+                (address, name.clone())
+            }
+        })
+        .collect();
+    let starts: HashSet<i64> = instructions.iter().map(|inst| inst.address).collect();
+    for instruction in instructions {
+        for field in instruction.op.to_fields() {
+            if let Field::PCRelAddr(offset) = field
+                && starts.contains(&(instruction.address + offset))
+            {
+                let target = instruction.address + offset;
+                labels.entry(target).or_insert_with(|| format!(".L{:x}", target));
+            }
+        }
+    }
+    labels
+}
+
+pub fn assembly_listing(filename: &str, m: &Machine, instructions: &[Instruction]) -> String {
+    let data_segments = data_segments(m);
+    let labels = assembly_labels(m, instructions);
+    let entry = labels.get(&m.pc_start);
+
+    let mut out = String::new();
+    writeln!(out, "# {} as a .s file, rebuilt with the same addresses by:", filename).unwrap();
+    writeln!(out, "#   riscv64-unknown-elf-as -march=rv64imc -o prog.o prog.s").unwrap();
+    write!(out, "#   riscv64-unknown-elf-ld -Ttext=0x{:x}", m.text_start).unwrap();
+    if !data_segments.is_empty() {
+        write!(out, " -Tdata=0x{:x}", m.data_start).unwrap();
+    }
+    if let Some(entry) = entry
+        && entry != "_start"
+    {
+        write!(out, " -e {}", entry).unwrap();
+    }
+    writeln!(out, " -o {} prog.o", filename).unwrap();
+    writeln!(out).unwrap();
+
+    // compress by default unless most instructions that could be compressed are not
+    let compressed = instructions.iter().filter(|inst| inst.length == 2).count();
+    let uncompressed =
+        instructions.iter().filter(|inst| inst.length == 4 && inst.op.encode_compressed().is_some()).count();
+    let rvc = compressed >= uncompressed;
+    writeln!(out, "        .option norelax").unwrap();
+    if !rvc {
+        writeln!(out, "        .option norvc").unwrap();
+    }
+    writeln!(out, "        .text").unwrap();
+    if let Some(entry) = entry {
+        writeln!(out, "        .globl  {}", entry).unwrap();
+    }
+
+    let mut zeros = 0;
+    for instruction in instructions {
+        // runs of zeros (like padding at the end of the section) are not instructions
+        let bits = raw_bits(m, instruction);
+        let label = labels.get(&instruction.address);
+        if zeros > 0 && (label.is_some() || bits != 0) {
+            writeln!(out, "        .zero   {}", zeros).unwrap();
+            zeros = 0;
+        }
+        if let Some(name) = label {
+            writeln!(out, "{}:", name).unwrap();
+        }
+        if bits == 0 {
+            zeros += instruction.length;
+            continue;
+        }
+
+        let op = &instruction.op;
+        let Some(text) = assembly_instruction(&labels, instruction, m.style.registers) else {
+            writeln!(out, "        .{}byte  0x{:02$x}", instruction.length, bits, instruction.length as usize * 2)
+                .unwrap();
+            continue;
+        };
+        let exact = match (op, instruction.length) {
+            (Op::Unimplemented { .. }, _) => false,
+            (_, 2) => op.encode_compressed().map(|inst| inst as u32 & 0xffff) == Some(bits),
+            _ => op.encode().ok() == Some(bits as i32),
+        };
+        if !exact && instruction.length == 2 {
+            writeln!(out, "        .2byte  0x{:04x}", bits).unwrap();
+        } else if !exact {
+            writeln!(out, "        .4byte  0x{:08x}", bits).unwrap();
+        } else if rvc && instruction.length == 4 && op.encode_compressed().is_some() {
+            // keep the assembler from compressing it
+            writeln!(out, "        .option push").unwrap();
+            writeln!(out, "        .option norvc").unwrap();
+            writeln!(out, "        {}", text).unwrap();
+            writeln!(out, "        .option pop").unwrap();
+        } else if !rvc && instruction.length == 2 {
+            writeln!(out, "        .option push").unwrap();
+            writeln!(out, "        .option rvc").unwrap();
+            writeln!(out, "        {}", text).unwrap();
+            writeln!(out, "        .option pop").unwrap();
+        } else {
+            writeln!(out, "        {}", text).unwrap();
+        }
+    }
+    if zeros > 0 {
+        writeln!(out, "        .zero   {}", zeros).unwrap();
+    }
+    for (_, bytes) in trailing_bytes(m, instructions) {
+        let (value, directive) = trailing_directive(&bytes);
+        writeln!(out, "        {:<8}0x{:0width$x}", directive, value, width = bytes.len() * 2).unwrap();
+    }
+
+    if !data_segments.is_empty() {
+        writeln!(out).unwrap();
+        writeln!(out, "        .data").unwrap();
+    }
+    let mut address = m.data_start;
+    for segment in data_segments {
+        if segment.start > address {
+            writeln!(out, "        .zero   {}", segment.start - address).unwrap();
+        }
+        let bytes = segment_bytes(segment);
+        for pair in data_boundaries(m, segment).windows(2) {
+            if let Some(name) = labels.get(&pair[0]) {
+                writeln!(out, "{}:", name).unwrap();
+            }
+            let chunk = &bytes[(pair[0] - segment.start) as usize..(pair[1] - segment.start) as usize];
+            for (_, directive) in data_directives(pair[0], chunk, true) {
+                writeln!(out, "        {}", directive).unwrap();
+            }
+        }
+        address = segment.end;
+    }
+    out
+}
+
+// None if the instruction jumps somewhere that is not an instruction
+fn assembly_instruction(
+    labels: &HashMap<i64, String>,
+    instruction: &Instruction,
+    names: RegisterNames,
+) -> Option<String> {
+    let op = &instruction.op;
+    let fields = op.to_fields();
+    let operands = fields[1..]
+        .iter()
+        .map(|field| match *field {
+            Field::Imm(imm) if matches!(op, Op::Lui { .. } | Op::Auipc { .. }) => {
+                Some(format!("0x{:x}", (imm >> 12) & 0xfffff))
+            }
+            Field::PCRelAddr(offset) => labels.get(&(instruction.address + offset)).cloned(),
+            _ => Some(field.to_string(instruction.address, 0, false, true, names, &HashMap::new())),
+        })
+        .collect::<Option<Vec<String>>>()?;
+
+    let mnemonic = fields[0].to_string(instruction.address, 0, false, true, names, &HashMap::new());
+    Some(format!("{:<8}{}", mnemonic, operands.join(", ")).trim_end().to_string())
+}
+
+// hex words as objdump prints them, separated by spaces, commas, or
+// newlines, each with or without 0x. The value decides the width: low bits
+// of 11 mean a 32-bit instruction, anything else that fits in 16 bits is a
+// compressed halfword. A .byte, .2byte, or .4byte before a value sets its
+// width instead, as the listing prints bytes left over at the end. The
+// result is the bytes in memory order.
+pub fn parse_hex_words(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut width = None;
+    for token in text.split(|c: char| c.is_whitespace() || c == ',').filter(|s| !s.is_empty()) {
+        match token {
+            ".byte" | ".2byte" | ".4byte" if width.is_none() => {
+                width = Some(match token {
+                    ".byte" => 1,
+                    ".2byte" => 2,
+                    _ => 4,
+                });
+                continue;
+            }
+            _ => {}
+        }
+        let digits = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")).unwrap_or(token);
+        let word = match u32::from_str_radix(digits, 16) {
+            Ok(word) if digits.len() <= 8 => word,
+            _ => return Err(format!("{} is not a 32-bit hex word", token)),
+        };
+        let size = match width.take() {
+            Some(size) if size < 4 && word >> (8 * size) != 0 => {
+                return Err(format!("{} does not fit in {} byte{}", token, size, if size == 1 { "" } else { "s" }));
+            }
+            Some(size) => size,
+            None if word & 0b11 != 0b11 && word <= 0xffff => 2,
+            None => 4,
+        };
+        bytes.extend(&word.to_le_bytes()[..size]);
+    }
+    if width.is_some() {
+        return Err("missing value after the last directive".to_string());
+    }
+    Ok(bytes)
+}
+
+// bytes after the last whole instruction in --hex or --raw input, as
+// halfwords and then a final byte, with the address of each
+fn trailing_bytes(m: &Machine, instructions: &[Instruction]) -> Vec<(i64, Vec<u8>)> {
+    let start = instructions.last().map_or(m.text_start, |inst| inst.address + inst.length);
+    let Some(segment) = m.segments.iter().find(|segment| segment.executable && segment.in_range(start, 1)) else {
+        return Vec::new();
+    };
+    let bytes = segment.load(start, segment.end - start, &mut None);
+    bytes.chunks(2).enumerate().map(|(i, chunk)| (start + 2 * i as i64, chunk.to_vec())).collect()
+}
+
+// little-endian value and directive for a piece of trailing bytes
+fn trailing_directive(bytes: &[u8]) -> (u32, &'static str) {
+    let value = bytes.iter().rev().fold(0, |value, &b| (value << 8) | b as u32);
+    (value, if bytes.len() == 2 { ".2byte" } else { ".byte" })
+}
+
+// the trailing bytes in the plain listing
+pub fn trailing_listing(m: &Machine, instructions: &[Instruction]) -> String {
+    let mut out = String::new();
+    for (_, bytes) in trailing_bytes(m, instructions) {
+        let (value, directive) = trailing_directive(&bytes);
+        writeln!(out, "{:16}{:<8}0x{:0width$x}", "", directive, value, width = bytes.len() * 2).unwrap();
+    }
+    out
+}
+
+// the data, rodata, and bss sections
+fn data_segments(m: &Machine) -> Vec<&Segment> {
+    m.segments
+        .iter()
+        .filter(|segment| !segment.executable && segment.start >= m.data_start && segment.end <= m.data_end)
+        .collect()
+}
+
+fn segment_bytes(segment: &Segment) -> Vec<u8> {
+    let mut bytes = segment.init.clone();
+    bytes.resize((segment.end - segment.start) as usize, 0);
+    bytes
+}
+
+// where a segment splits into pieces: at each label and at the end of each
+// symbol with a size, so padding after a variable is not mistaken for part of it
+fn data_boundaries(m: &Machine, segment: &Segment) -> Vec<i64> {
+    let mut boundaries = vec![segment.start, segment.end];
+    for &address in m.address_symbols.keys() {
+        boundaries.push(address);
+        if let Some(size) = m.symbol_sizes.get(&address) {
+            boundaries.push(address + size);
+        }
+    }
+    boundaries.retain(|&address| address >= segment.start && address <= segment.end);
+    boundaries.sort();
+    boundaries.dedup();
+    boundaries
+}
+
+// data directives for the bytes between two boundaries, with the address of each
+fn data_directives(address: i64, bytes: &[u8], hex: bool) -> Vec<(i64, String)> {
+    if bytes.is_empty() {
+        return Vec::new();
+    }
+    if bytes.iter().all(|&b| b == 0) {
+        return vec![(address, format!("{:<8}{}", ".zero", bytes.len()))];
+    }
+
+    // one or more NUL-terminated strings
+    let printable = |b: &u8| (0x20..0x7f).contains(b) || b"\t\n\r".contains(b);
+    let strings: Vec<&[u8]> = bytes.split_inclusive(|&b| b == 0).collect();
+    if strings.iter().all(|s| s.len() > 1 && s.ends_with(&[0]) && s[..s.len() - 1].iter().all(printable)) {
+        let mut directives = Vec::new();
+        let mut at = address;
+        for s in strings {
+            let text: String = s[..s.len() - 1].iter().map(|&b| char::from(b).escape_default().to_string()).collect();
+            directives.push((at, format!("{:<8}\"{}\"", ".string", text)));
+            at += s.len() as i64;
+        }
+        return directives;
+    }
+
+    // the widest values that fit the alignment and evenly divide the bytes,
+    // or failing that the widest that fit, with any odd bytes at the end
+    let aligned = |size: usize| address % size as i64 == 0 && bytes.len() >= size;
+    let size = [8, 4, 2]
+        .into_iter()
+        .find(|&size| aligned(size) && bytes.len().is_multiple_of(size))
+        .or_else(|| [8, 4, 2].into_iter().find(|&size| aligned(size)))
+        .unwrap_or(1);
+    let (directive, per_line) = match size {
+        8 => (".dword", 2),
+        4 => (".word", 4),
+        2 => (".half", 4),
+        _ => (".byte", 8),
+    };
+    let whole = bytes.len() / size * size;
+    let values: Vec<String> = bytes[..whole]
+        .chunks(size)
+        .map(|chunk| {
+            let value = chunk.iter().rev().fold(0u64, |value, &b| (value << 8) | b as u64);
+            // sign extended, with anything too big to read as a number (like an address) in hex
+            let shift = 64 - 8 * size as u32;
+            let signed = ((value << shift) as i64) >> shift;
+            if hex || signed != signed as i32 as i64 { format!("0x{:x}", value) } else { format!("{}", signed) }
+        })
+        .collect();
+    let mut directives: Vec<(i64, String)> = values
+        .chunks(per_line)
+        .enumerate()
+        .map(|(i, line)| (address + (i * per_line * size) as i64, format!("{:<8}{}", directive, line.join(", "))))
+        .collect();
+    directives.extend(data_directives(address + whole as i64, &bytes[whole..], hex));
+    directives
+}
+
+// the data segments laid out by label, for checking where each variable went
+pub fn data_listing(m: &Machine) -> String {
+    let mut out = String::new();
+    for segment in data_segments(m) {
+        writeln!(
+            out,
+            "\n{} (0x{:x}-0x{:x}, {} bytes)",
+            segment.name,
+            segment.start,
+            segment.end,
+            segment.end - segment.start
+        )
+        .unwrap();
+        let bytes = segment_bytes(segment);
+        for pair in data_boundaries(m, segment).windows(2) {
+            let chunk = &bytes[(pair[0] - segment.start) as usize..(pair[1] - segment.start) as usize];
+            let mut label: Vec<char> = match m.address_symbols.get(&pair[0]) {
+                Some(name) => name.chars().collect(),
+                None => Vec::new(),
+            };
+            if label.len() > 14 {
+                label.truncate(14);
+                label.push('…');
+            }
+            if !label.is_empty() {
+                label.push(':');
+            }
+            for (address, directive) in data_directives(pair[0], chunk, false) {
+                let label: String = label.drain(..).collect();
+                writeln!(out, "0x{:05x}  {:<16}{}", address, label, directive).unwrap();
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_words_take_their_width_from_the_value() {
+        // add a0, a0, a0 / li a0, 1 / nop / li a0, -1
+        assert_eq!(
+            parse_hex_words("00a50533 4505 0x13 fff00513"),
+            Ok(vec![0x33, 0x05, 0xa5, 0x00, 0x05, 0x45, 0x13, 0x00, 0x00, 0x00, 0x13, 0x05, 0xf0, 0xff])
+        );
+        assert_eq!(parse_hex_words("0x4505,0X00000013,\n1141"), Ok(vec![0x05, 0x45, 0x13, 0, 0, 0, 0x41, 0x11]));
+    }
+
+    #[test]
+    fn hex_words_take_explicit_widths() {
+        assert_eq!(parse_hex_words(".2byte 0x0513 .byte 0x15"), Ok(vec![0x13, 0x05, 0x15]));
+        assert_eq!(parse_hex_words(".4byte 1"), Ok(vec![1, 0, 0, 0]));
+        assert!(parse_hex_words(".byte 0x115").is_err());
+        assert!(parse_hex_words("4505 .byte").is_err());
+        assert!(parse_hex_words("123456789").is_err());
+        assert!(parse_hex_words("nop").is_err());
+    }
+}
@@ -0,0 +1,977 @@
+use super::*;
+
+// Encoding: the inverse of Op::new. Op::encode gives the 32-bit form of any
+// instruction, and Op::encode_compressed gives the 16-bit form when the C
+// extension has one that fits.
+//
+// `risclet encode <instruction>` assembles a single line of assembly and
+// prints its machine code with the bit fields of the instruction format
+// labeled, for working through instruction formats by hand:
+//
+//   $ risclet encode lw a0, 8(sp)
+//   lw      a0, 8(sp)
+//   0x00812503  bytes 03 25 81 00  I-type
+//     imm[11:0]     rs1    funct3  rd     opcode
+//     000000001000  00010  010     01010  0000011
+//   compressed: 0x4522  bytes 22 45  c.lwsp, CI-type
+//     funct3  uimm[5]  rd     uimm[4:2|7:6]  op
+//     010     0        01010  01000          10
+
+// a named range of bits in an instruction: (name, high bit, low bit)
+pub type BitField = (&'static str, u32, u32);
+
+const R_TYPE: &[BitField] =
+    &[("funct7", 31, 25), ("rs2", 24, 20), ("rs1", 19, 15), ("funct3", 14, 12), ("rd", 11, 7), ("opcode", 6, 0)];
+const I_TYPE: &[BitField] =
+    &[("imm[11:0]", 31, 20), ("rs1", 19, 15), ("funct3", 14, 12), ("rd", 11, 7), ("opcode", 6, 0)];
+const SHIFT_TYPE: &[BitField] =
+    &[("funct6", 31, 26), ("shamt", 25, 20), ("rs1", 19, 15), ("funct3", 14, 12), ("rd", 11, 7), ("opcode", 6, 0)];
+const SHIFTW_TYPE: &[BitField] =
+    &[("funct7", 31, 25), ("shamt", 24, 20), ("rs1", 19, 15), ("funct3", 14, 12), ("rd", 11, 7), ("opcode", 6, 0)];
+const CSR_TYPE: &[BitField] = &[("csr", 31, 20), ("rs1", 19, 15), ("funct3", 14, 12), ("rd", 11, 7), ("opcode", 6, 0)];
+const SYSTEM_TYPE: &[BitField] =
+    &[("funct12", 31, 20), ("rs1", 19, 15), ("funct3", 14, 12), ("rd", 11, 7), ("opcode", 6, 0)];
+const S_TYPE: &[BitField] = &[
+    ("imm[11:5]", 31, 25),
+    ("rs2", 24, 20),
+    ("rs1", 19, 15),
+    ("funct3", 14, 12),
+    ("imm[4:0]", 11, 7),
+    ("opcode", 6, 0),
+];
+const B_TYPE: &[BitField] = &[
+    ("imm[12]", 31, 31),
+    ("imm[10:5]", 30, 25),
+    ("rs2", 24, 20),
+    ("rs1", 19, 15),
+    ("funct3", 14, 12),
+    ("imm[4:1]", 11, 8),
+    ("imm[11]", 7, 7),
+    ("opcode", 6, 0),
+];
+const U_TYPE: &[BitField] = &[("imm[31:12]", 31, 12), ("rd", 11, 7), ("opcode", 6, 0)];
+const J_TYPE: &[BitField] = &[
+    ("imm[20]", 31, 31),
+    ("imm[10:1]", 30, 21),
+    ("imm[11]", 20, 20),
+    ("imm[19:12]", 19, 12),
+    ("rd", 11, 7),
+    ("opcode", 6, 0),
+];
+const UNKNOWN_TYPE: &[BitField] = &[("bits", 31, 7), ("opcode", 6, 0)];
+
+// the bit layout of a 32-bit instruction: (format, fields)
+fn layout(inst: i32) -> (&'static str, &'static [BitField]) {
+    let funct3 = (inst >> 12) & 0x7;
+    match inst & 0x7f {
+        0x33 | 0x3b => ("R-type", R_TYPE),
+        0x13 if funct3 == 1 || funct3 == 5 => ("I-type", SHIFT_TYPE),
+        0x1b if funct3 == 1 || funct3 == 5 => ("I-type", SHIFTW_TYPE),
+        0x13 | 0x1b | 0x03 | 0x67 | 0x0f => ("I-type", I_TYPE),
+        0x73 if funct3 == 0 => ("I-type", SYSTEM_TYPE),
+        0x73 => ("I-type", CSR_TYPE),
+        0x23 => ("S-type", S_TYPE),
+        0x63 => ("B-type", B_TYPE),
+        0x37 | 0x17 => ("U-type", U_TYPE),
+        0x6f => ("J-type", J_TYPE),
+        _ => ("unknown", UNKNOWN_TYPE),
+    }
+}
+
+// the bit layout of a compressed instruction: (mnemonic, format, fields)
+fn compressed_layout(inst: i32) -> (&'static str, &'static str, Vec<BitField>) {
+    let funct3 = ("funct3", 15, 13);
+    let op = ("op", 1, 0);
+    let rd = (inst >> 7) & 0x1f;
+    let rs2 = (inst >> 2) & 0x1f;
+    let bit12 = (inst >> 12) & 1;
+    let ci = |name, hi: &'static str, reg: &'static str, lo: &'static str| {
+        (name, "CI-type", vec![funct3, (hi, 12, 12), (reg, 11, 7), (lo, 6, 2), op])
+    };
+    let cl = |name, hi: &'static str, reg: &'static str, lo: &'static str| {
+        let fields = vec![funct3, (hi, 12, 10), ("rs1'", 9, 7), (lo, 6, 5), (reg, 4, 2), op];
+        (name, if reg == "rd'" { "CL-type" } else { "CS-type" }, fields)
+    };
+    let cb = |name, hi: &'static str, lo: &'static str| {
+        (name, "CB-type", vec![funct3, (hi, 12, 12), ("funct2", 11, 10), ("rd'/rs1'", 9, 7), (lo, 6, 2), op])
+    };
+    let cr = |name| (name, "CR-type", vec![("funct4", 15, 12), ("rd/rs1", 11, 7), ("rs2", 6, 2), op]);
+    let reserved = |name| (name, "reserved", vec![funct3, ("bits", 12, 2), op]);
+
+    match (inst & 3, (inst >> 13) & 7) {
+        (0, 0) => ("c.addi4spn", "CIW-type", vec![funct3, ("nzuimm[5:4|9:6|2|3]", 12, 5), ("rd'", 4, 2), op]),
+        (0, 1) => reserved("c.fld"),
+        (0, 2) => cl("c.lw", "uimm[5:3]", "rd'", "uimm[2|6]"),
+        (0, 3) => cl("c.ld", "uimm[5:3]", "rd'", "uimm[7:6]"),
+        (0, 4) => reserved("reserved"),
+        (0, 5) => reserved("c.fsd"),
+        (0, 6) => cl("c.sw", "uimm[5:3]", "rs2'", "uimm[2|6]"),
+        (0, 7) => cl("c.sd", "uimm[5:3]", "rs2'", "uimm[7:6]"),
+        (1, 0) if rd == 0 => ci("c.nop", "imm[5]", "rd/rs1", "imm[4:0]"),
+        (1, 0) => ci("c.addi", "imm[5]", "rd/rs1", "imm[4:0]"),
+        (1, 1) => ci("c.addiw", "imm[5]", "rd/rs1", "imm[4:0]"),
+        (1, 2) => ci("c.li", "imm[5]", "rd", "imm[4:0]"),
+        (1, 3) if rd == SP as i32 => ci("c.addi16sp", "nzimm[9]", "rd/rs1", "nzimm[4|6|8:7|5]"),
+        (1, 3) => ci("c.lui", "nzimm[17]", "rd", "nzimm[16:12]"),
+        (1, 4) => match ((inst >> 10) & 3, bit12, (inst >> 5) & 3) {
+            (0, _, _) => cb("c.srli", "shamt[5]", "shamt[4:0]"),
+            (1, _, _) => cb("c.srai", "shamt[5]", "shamt[4:0]"),
+            (2, _, _) => cb("c.andi", "imm[5]", "imm[4:0]"),
+            (_, b, f) => {
+                let name = ["c.sub", "c.xor", "c.or", "c.and", "c.subw", "c.addw", "reserved", "reserved"];
+                let fields = vec![("funct6", 15, 10), ("rd'/rs1'", 9, 7), ("funct2", 6, 5), ("rs2'", 4, 2), op];
+                (name[(b * 4 + f) as usize], "CA-type", fields)
+            }
+        },
+        (1, 5) => ("c.j", "CJ-type", vec![funct3, ("imm[11|4|9:8|10|6|7|3:1|5]", 12, 2), op]),
+        (1, 6 | 7) => {
+            let name = if (inst >> 13) & 7 == 6 { "c.beqz" } else { "c.bnez" };
+            (name, "CB-type", vec![funct3, ("imm[8|4:3]", 12, 10), ("rs1'", 9, 7), ("imm[7:6|2:1|5]", 6, 2), op])
+        }
+        (2, 0) => ci("c.slli", "shamt[5]", "rd/rs1", "shamt[4:0]"),
+        (2, 1) => reserved("c.fldsp"),
+        (2, 2) => ci("c.lwsp", "uimm[5]", "rd", "uimm[4:2|7:6]"),
+        (2, 3) => ci("c.ldsp", "uimm[5]", "rd", "uimm[4:3|8:6]"),
+        (2, 4) => match (bit12, rd, rs2) {
+            (0, _, 0) => cr("c.jr"),
+            (0, _, _) => cr("c.mv"),
+            (_, 0, 0) => cr("c.ebreak"),
+            (_, _, 0) => cr("c.jalr"),
+            (_, _, _) => cr("c.add"),
+        },
+        (2, 5) => reserved("c.fsdsp"),
+        (2, 6) => ("c.swsp", "CSS-type", vec![funct3, ("uimm[5:2|7:6]", 12, 7), ("rs2", 6, 2), op]),
+        (2, 7) => ("c.sdsp", "CSS-type", vec![funct3, ("uimm[5:3|8:6]", 12, 7), ("rs2", 6, 2), op]),
+        _ => unreachable!(),
+    }
+}
+
+// the mnemonic, format, and labeled bit fields of an instruction
+pub fn bit_fields(inst: i32, length: i64) -> (&'static str, &'static str, Vec<BitField>) {
+    if length == 2 {
+        return compressed_layout(inst & 0xffff);
+    }
+    let mnemonic = match Op::new(inst).to_fields().first() {
+        Some(Field::Opcode(name)) => *name,
+        _ => "???",
+    };
+    let (format, fields) = layout(inst);
+    (mnemonic, format, fields.to_vec())
+}
+
+// the bit fields of an instruction as two aligned rows: labels over binary values
+pub fn bit_field_rows(inst: i32, fields: &[BitField]) -> (String, String) {
+    let (mut labels, mut values) = (String::new(), String::new());
+    for (i, &(name, hi, lo)) in fields.iter().enumerate() {
+        let width = (hi - lo + 1) as usize;
+        let value = format!("{:0width$b}", (inst as u32 >> lo) & ((1u64 << width) - 1) as u32);
+        let column = name.len().max(width);
+        if i + 1 < fields.len() {
+            write!(labels, "{:<column$}  ", name).unwrap();
+            write!(values, "{:<column$}  ", value).unwrap();
+        } else {
+            labels.push_str(name);
+            values.push_str(&value);
+        }
+    }
+    (labels, values)
+}
+
+fn check_range(name: &str, value: i64, min: i64, max: i64, multiple: i64) -> Result<(), String> {
+    if value < min || value > max {
+        Err(format!("{} of {} is out of range ({} to {})", name, value, min, max))
+    } else if value % multiple != 0 {
+        Err(format!("{} of {} is not a multiple of {}", name, value, multiple))
+    } else {
+        Ok(())
+    }
+}
+
+fn r_type(opcode: i32, funct3: i32, funct7: i32, rd: usize, rs1: usize, rs2: usize) -> i32 {
+    (funct7 << 25) | ((rs2 as i32) << 20) | ((rs1 as i32) << 15) | (funct3 << 12) | ((rd as i32) << 7) | opcode
+}
+
+fn i_type(opcode: i32, funct3: i32, rd: usize, rs1: usize, imm: i64) -> Result<i32, String> {
+    check_range("immediate", imm, -2048, 2047, 1)?;
+    Ok(((imm as i32) << 20) | ((rs1 as i32) << 15) | (funct3 << 12) | ((rd as i32) << 7) | opcode)
+}
+
+fn shift_type(
+    opcode: i32,
+    funct3: i32,
+    funct7: i32,
+    rd: usize,
+    rs1: usize,
+    shamt: i64,
+    bits: i64,
+) -> Result<i32, String> {
+    check_range("shift amount", shamt, 0, bits - 1, 1)?;
+    Ok(r_type(opcode, funct3, funct7, rd, rs1, 0) | ((shamt as i32) << 20))
+}
+
+fn s_type(funct3: i32, rs1: usize, rs2: usize, offset: i64) -> Result<i32, String> {
+    check_range("offset", offset, -2048, 2047, 1)?;
+    let imm = offset as i32;
+    Ok(r_type(0x23, funct3, imm >> 5, imm as usize & 0x1f, rs1, rs2))
+}
+
+fn b_type(funct3: i32, rs1: usize, rs2: usize, offset: i64) -> Result<i32, String> {
+    check_range("branch offset", offset, -4096, 4094, 2)?;
+    let imm = offset as i32;
+    let high = ((imm >> 12) & 1) << 6 | ((imm >> 5) & 0x3f);
+    let low = (imm & 0x1e) | ((imm >> 11) & 1);
+    Ok(r_type(0x63, funct3, high, low as usize, rs1, rs2))
+}
+
+fn u_type(opcode: i32, rd: usize, imm: i64) -> Result<i32, String> {
+    check_range("upper immediate", imm, i32::MIN as i64, i32::MAX as i64, 0x1000)?;
+    Ok(imm as i32 | ((rd as i32) << 7) | opcode)
+}
+
+fn j_type(rd: usize, offset: i64) -> Result<i32, String> {
+    check_range("jump offset", offset, -(1 << 20), (1 << 20) - 2, 2)?;
+    let imm = offset as i32;
+    let bits = (((imm >> 20) & 1) << 31) | (((imm >> 1) & 0x3ff) << 21) | (((imm >> 11) & 1) << 20) | (imm & 0xff000);
+    Ok(bits | ((rd as i32) << 7) | 0x6f)
+}
+
+// scatter the bits of an immediate into a compressed instruction;
+// each (hi, lo, at) places imm[hi:lo] starting at instruction bit at
+fn scatter(imm: i64, layout: &[(u32, u32, u32)]) -> i32 {
+    layout.iter().fold(0, |inst, &(hi, lo, at)| inst | ((((imm >> lo) & ((1 << (hi - lo + 1)) - 1)) as i32) << at))
+}
+
+const CI_IMM: &[(u32, u32, u32)] = &[(5, 5, 12), (4, 0, 2)];
+const CL_WORD: &[(u32, u32, u32)] = &[(5, 3, 10), (2, 2, 6), (6, 6, 5)];
+const CL_DOUBLE: &[(u32, u32, u32)] = &[(5, 3, 10), (7, 6, 5)];
+
+// x8-x15, the registers compressed instructions can name with 3 bits
+fn is_prime(reg: usize) -> bool {
+    (8..16).contains(&reg)
+}
+
+fn fits(value: i64, min: i64, max: i64, multiple: i64) -> bool {
+    check_range("", value, min, max, multiple).is_ok()
+}
+
+impl Op {
+    // the 32-bit encoding of the instruction
+    pub fn encode(&self) -> Result<i32, String> {
+        match *self {
+            // r-type
+            Op::Add { rd, rs1, rs2 } => Ok(r_type(0x33, 0, 0x00, rd, rs1, rs2)),
+            Op::Sub { rd, rs1, rs2 } => Ok(r_type(0x33, 0, 0x20, rd, rs1, rs2)),
+            Op::Sll { rd, rs1, rs2 } => Ok(r_type(0x33, 1, 0x00, rd, rs1, rs2)),
+            Op::Slt { rd, rs1, rs2 } => Ok(r_type(0x33, 2, 0x00, rd, rs1, rs2)),
+            Op::Sltu { rd, rs1, rs2 } => Ok(r_type(0x33, 3, 0x00, rd, rs1, rs2)),
+            Op::Xor { rd, rs1, rs2 } => Ok(r_type(0x33, 4, 0x00, rd, rs1, rs2)),
+            Op::Srl { rd, rs1, rs2 } => Ok(r_type(0x33, 5, 0x00, rd, rs1, rs2)),
+            Op::Sra { rd, rs1, rs2 } => Ok(r_type(0x33, 5, 0x20, rd, rs1, rs2)),
+            Op::Or { rd, rs1, rs2 } => Ok(r_type(0x33, 6, 0x00, rd, rs1, rs2)),
+            Op::And { rd, rs1, rs2 } => Ok(r_type(0x33, 7, 0x00, rd, rs1, rs2)),
+
+            // rv64-specific r-type
+            Op::Addw { rd, rs1, rs2 } => Ok(r_type(0x3b, 0, 0x00, rd, rs1, rs2)),
+            Op::Subw { rd, rs1, rs2 } => Ok(r_type(0x3b, 0, 0x20, rd, rs1, rs2)),
+            Op::Sllw { rd, rs1, rs2 } => Ok(r_type(0x3b, 1, 0x00, rd, rs1, rs2)),
+            Op::Srlw { rd, rs1, rs2 } => Ok(r_type(0x3b, 5, 0x00, rd, rs1, rs2)),
+            Op::Sraw { rd, rs1, rs2 } => Ok(r_type(0x3b, 5, 0x20, rd, rs1, rs2)),
+
+            // i-type
+            Op::Addi { rd, rs1, imm } => i_type(0x13, 0, rd, rs1, imm),
+            Op::Slti { rd, rs1, imm } => i_type(0x13, 2, rd, rs1, imm),
+            Op::Sltiu { rd, rs1, imm } => i_type(0x13, 3, rd, rs1, imm),
+            Op::Xori { rd, rs1, imm } => i_type(0x13, 4, rd, rs1, imm),
+            Op::Ori { rd, rs1, imm } => i_type(0x13, 6, rd, rs1, imm),
+            Op::Andi { rd, rs1, imm } => i_type(0x13, 7, rd, rs1, imm),
+            Op::Slli { rd, rs1, shamt } => shift_type(0x13, 1, 0x00, rd, rs1, shamt, 64),
+            Op::Srli { rd, rs1, shamt } => shift_type(0x13, 5, 0x00, rd, rs1, shamt, 64),
+            Op::Srai { rd, rs1, shamt } => shift_type(0x13, 5, 0x20, rd, rs1, shamt, 64),
+
+            // rv64-specific i-type
+            Op::Addiw { rd, rs1, imm } => i_type(0x1b, 0, rd, rs1, imm),
+            Op::Slliw { rd, rs1, shamt } => shift_type(0x1b, 1, 0x00, rd, rs1, shamt, 32),
+            Op::Srliw { rd, rs1, shamt } => shift_type(0x1b, 5, 0x00, rd, rs1, shamt, 32),
+            Op::Sraiw { rd, rs1, shamt } => shift_type(0x1b, 5, 0x20, rd, rs1, shamt, 32),
+
+            // branch
+            Op::Beq { rs1, rs2, offset } => b_type(0, rs1, rs2, offset),
+            Op::Bne { rs1, rs2, offset } => b_type(1, rs1, rs2, offset),
+            Op::Blt { rs1, rs2, offset } => b_type(4, rs1, rs2, offset),
+            Op::Bge { rs1, rs2, offset } => b_type(5, rs1, rs2, offset),
+            Op::Bltu { rs1, rs2, offset } => b_type(6, rs1, rs2, offset),
+            Op::Bgeu { rs1, rs2, offset } => b_type(7, rs1, rs2, offset),
+
+            // jump
+            Op::Jal { rd, offset } => j_type(rd, offset),
+            Op::Jalr { rd, rs1, offset } => i_type(0x67, 0, rd, rs1, offset),
+
+            // load
+            Op::Lb { rd, rs1, offset } => i_type(0x03, 0, rd, rs1, offset),
+            Op::Lh { rd, rs1, offset } => i_type(0x03, 1, rd, rs1, offset),
+            Op::Lw { rd, rs1, offset } => i_type(0x03, 2, rd, rs1, offset),
+            Op::Ld { rd, rs1, offset } => i_type(0x03, 3, rd, rs1, offset),
+            Op::Lbu { rd, rs1, offset } => i_type(0x03, 4, rd, rs1, offset),
+            Op::Lhu { rd, rs1, offset } => i_type(0x03, 5, rd, rs1, offset),
+            Op::Lwu { rd, rs1, offset } => i_type(0x03, 6, rd, rs1, offset),
+
+            // store
+            Op::Sb { rs1, rs2, offset } => s_type(0, rs1, rs2, offset),
+            Op::Sh { rs1, rs2, offset } => s_type(1, rs1, rs2, offset),
+            Op::Sw { rs1, rs2, offset } => s_type(2, rs1, rs2, offset),
+            Op::Sd { rs1, rs2, offset } => s_type(3, rs1, rs2, offset),
+
+            // u-type
+            Op::Lui { rd, imm } => u_type(0x37, rd, imm),
+            Op::Auipc { rd, imm } => u_type(0x17, rd, imm),
+
+            // misc: fence iorw, iorw
+            Op::Fence => Ok(0x0ff0000f),
+            Op::Ecall => Ok(0x00000073),
+            Op::Ebreak => Ok(0x00100073),
+
+            // zicsr: csrrs rd, csr, zero
+            Op::Csrr { rd, csr } => {
+                check_range("csr", csr, 0, 0xfff, 1)?;
+                Ok(((csr as i32) << 20) | (2 << 12) | ((rd as i32) << 7) | 0x73)
+            }
+
+            // m extension
+            Op::Mul { rd, rs1, rs2 } => Ok(r_type(0x33, 0, 0x01, rd, rs1, rs2)),
+            Op::Mulh { rd, rs1, rs2 } => Ok(r_type(0x33, 1, 0x01, rd, rs1, rs2)),
+            Op::Mulhsu { rd, rs1, rs2 } => Ok(r_type(0x33, 2, 0x01, rd, rs1, rs2)),
+            Op::Mulhu { rd, rs1, rs2 } => Ok(r_type(0x33, 3, 0x01, rd, rs1, rs2)),
+            Op::Div { rd, rs1, rs2 } => Ok(r_type(0x33, 4, 0x01, rd, rs1, rs2)),
+            Op::Divu { rd, rs1, rs2 } => Ok(r_type(0x33, 5, 0x01, rd, rs1, rs2)),
+            Op::Rem { rd, rs1, rs2 } => Ok(r_type(0x33, 6, 0x01, rd, rs1, rs2)),
+            Op::Remu { rd, rs1, rs2 } => Ok(r_type(0x33, 7, 0x01, rd, rs1, rs2)),
+
+            // m extension rv64-specific
+            Op::Mulw { rd, rs1, rs2 } => Ok(r_type(0x3b, 0, 0x01, rd, rs1, rs2)),
+            Op::Divw { rd, rs1, rs2 } => Ok(r_type(0x3b, 4, 0x01, rd, rs1, rs2)),
+            Op::Divuw { rd, rs1, rs2 } => Ok(r_type(0x3b, 5, 0x01, rd, rs1, rs2)),
+            Op::Remw { rd, rs1, rs2 } => Ok(r_type(0x3b, 6, 0x01, rd, rs1, rs2)),
+            Op::Remuw { rd, rs1, rs2 } => Ok(r_type(0x3b, 7, 0x01, rd, rs1, rs2)),
+
+            Op::Unimplemented { inst, .. } => Ok(inst),
+        }
+    }
+
+    // the 16-bit encoding of the instruction, if the C extension has one;
+    // hints (like c.addi with a zero immediate) are never chosen
+    pub fn encode_compressed(&self) -> Option<i32> {
+        let c = |quadrant: i32, funct3: i32| (funct3 << 13) | quadrant;
+        let reg = |reg: usize, at: u32| (reg as i32) << at;
+        let prime = |reg: usize, at: u32| ((reg as i32) & 7) << at;
+        let inst = match *self {
+            Op::Addi { rd: ZERO, rs1: ZERO, imm: 0 } => c(1, 0),
+            Op::Addi { rd, rs1: SP, imm } if is_prime(rd) && fits(imm, 4, 1020, 4) => {
+                c(0, 0) | scatter(imm, &[(5, 4, 11), (9, 6, 7), (2, 2, 6), (3, 3, 5)]) | prime(rd, 2)
+            }
+            Op::Addi { rd: SP, rs1: SP, imm } if imm != 0 && fits(imm, -512, 496, 16) => {
+                c(1, 3) | scatter(imm, &[(9, 9, 12), (4, 4, 6), (6, 6, 5), (8, 7, 3), (5, 5, 2)]) | reg(SP, 7)
+            }
+            Op::Addi { rd, rs1: ZERO, imm } if rd != ZERO && fits(imm, -32, 31, 1) => {
+                c(1, 2) | scatter(imm, CI_IMM) | reg(rd, 7)
+            }
+            Op::Addi { rd, rs1, imm } if rd == rs1 && rd != ZERO && imm != 0 && fits(imm, -32, 31, 1) => {
+                c(1, 0) | scatter(imm, CI_IMM) | reg(rd, 7)
+            }
+            Op::Addiw { rd, rs1, imm } if rd == rs1 && rd != ZERO && fits(imm, -32, 31, 1) => {
+                c(1, 1) | scatter(imm, CI_IMM) | reg(rd, 7)
+            }
+            Op::Lui { rd, imm } if rd != ZERO && rd != SP && imm != 0 && fits(imm, -0x20000, 0x1f000, 0x1000) => {
+                c(1, 3) | scatter(imm, &[(17, 17, 12), (16, 12, 2)]) | reg(rd, 7)
+            }
+            Op::Srli { rd, rs1, shamt } if rd == rs1 && is_prime(rd) && shamt != 0 => {
+                c(1, 4) | scatter(shamt, CI_IMM) | prime(rd, 7)
+            }
+            Op::Srai { rd, rs1, shamt } if rd == rs1 && is_prime(rd) && shamt != 0 => {
+                c(1, 4) | (1 << 10) | scatter(shamt, CI_IMM) | prime(rd, 7)
+            }
+            Op::Andi { rd, rs1, imm } if rd == rs1 && is_prime(rd) && fits(imm, -32, 31, 1) => {
+                c(1, 4) | (2 << 10) | scatter(imm, CI_IMM) | prime(rd, 7)
+            }
+            Op::Sub { rd, rs1, rs2 }
+            | Op::Xor { rd, rs1, rs2 }
+            | Op::Or { rd, rs1, rs2 }
+            | Op::And { rd, rs1, rs2 }
+            | Op::Subw { rd, rs1, rs2 }
+            | Op::Addw { rd, rs1, rs2 }
+                if rd == rs1 && is_prime(rd) && is_prime(rs2) =>
+            {
+                let funct = match self {
+                    Op::Sub { .. } => 0,
+                    Op::Xor { .. } => 1,
+                    Op::Or { .. } => 2,
+                    Op::And { .. } => 3,
+                    Op::Subw { .. } => 4,
+                    _ => 5,
+                };
+                c(1, 4) | (3 << 10) | ((funct >> 2) << 12) | ((funct & 3) << 5) | prime(rd, 7) | prime(rs2, 2)
+            }
+            Op::Jal { rd: ZERO, offset } if fits(offset, -2048, 2046, 2) => {
+                let layout =
+                    [(11, 11, 12), (4, 4, 11), (9, 8, 9), (10, 10, 8), (6, 6, 7), (7, 7, 6), (3, 1, 3), (5, 5, 2)];
+                c(1, 5) | scatter(offset, &layout)
+            }
+            Op::Beq { rs1, rs2: ZERO, offset } | Op::Bne { rs1, rs2: ZERO, offset }
+                if is_prime(rs1) && fits(offset, -256, 254, 2) =>
+            {
+                let funct3 = if matches!(self, Op::Beq { .. }) { 6 } else { 7 };
+                let layout = [(8, 8, 12), (4, 3, 10), (7, 6, 5), (2, 1, 3), (5, 5, 2)];
+                c(1, funct3) | scatter(offset, &layout) | prime(rs1, 7)
+            }
+            Op::Slli { rd, rs1, shamt } if rd == rs1 && rd != ZERO && shamt != 0 => {
+                c(2, 0) | scatter(shamt, CI_IMM) | reg(rd, 7)
+            }
+            Op::Lw { rd, rs1: SP, offset } if rd != ZERO && fits(offset, 0, 252, 4) => {
+                c(2, 2) | scatter(offset, &[(5, 5, 12), (4, 2, 4), (7, 6, 2)]) | reg(rd, 7)
+            }
+            Op::Ld { rd, rs1: SP, offset } if rd != ZERO && fits(offset, 0, 504, 8) => {
+                c(2, 3) | scatter(offset, &[(5, 5, 12), (4, 3, 5), (8, 6, 2)]) | reg(rd, 7)
+            }
+            Op::Sw { rs1: SP, rs2, offset } if fits(offset, 0, 252, 4) => {
+                c(2, 6) | scatter(offset, &[(5, 2, 9), (7, 6, 7)]) | reg(rs2, 2)
+            }
+            Op::Sd { rs1: SP, rs2, offset } if fits(offset, 0, 504, 8) => {
+                c(2, 7) | scatter(offset, &[(5, 3, 10), (8, 6, 7)]) | reg(rs2, 2)
+            }
+            Op::Lw { rd, rs1, offset } if is_prime(rd) && is_prime(rs1) && fits(offset, 0, 124, 4) => {
+                c(0, 2) | scatter(offset, CL_WORD) | prime(rs1, 7) | prime(rd, 2)
+            }
+            Op::Ld { rd, rs1, offset } if is_prime(rd) && is_prime(rs1) && fits(offset, 0, 248, 8) => {
+                c(0, 3) | scatter(offset, CL_DOUBLE) | prime(rs1, 7) | prime(rd, 2)
+            }
+            Op::Sw { rs1, rs2, offset } if is_prime(rs1) && is_prime(rs2) && fits(offset, 0, 124, 4) => {
+                c(0, 6) | scatter(offset, CL_WORD) | prime(rs1, 7) | prime(rs2, 2)
+            }
+            Op::Sd { rs1, rs2, offset } if is_prime(rs1) && is_prime(rs2) && fits(offset, 0, 248, 8) => {
+                c(0, 7) | scatter(offset, CL_DOUBLE) | prime(rs1, 7) | prime(rs2, 2)
+            }
+            Op::Jalr { rd: ZERO, rs1, offset: 0 } if rs1 != ZERO => c(2, 4) | reg(rs1, 7),
+            Op::Jalr { rd: RA, rs1, offset: 0 } if rs1 != ZERO => c(2, 4) | (1 << 12) | reg(rs1, 7),
+            Op::Add { rd, rs1: ZERO, rs2 } if rd != ZERO && rs2 != ZERO => c(2, 4) | reg(rd, 7) | reg(rs2, 2),
+            Op::Add { rd, rs1, rs2 } if rd == rs1 && rd != ZERO && rs2 != ZERO => {
+                c(2, 4) | (1 << 12) | reg(rd, 7) | reg(rs2, 2)
+            }
+            Op::Ebreak => c(2, 4) | (1 << 12),
+            _ => return None,
+        };
+        Some(inst)
+    }
+}
+
+fn parse_register(token: &str) -> Result<usize, String> {
+    let token = token.trim();
+    if let Some(n) = token.strip_prefix('x')
+        && let Ok(n) = n.parse::<usize>()
+        && n < 32
+    {
+        return Ok(n);
+    }
+    match token {
+        "fp" => Ok(8),
+        _ => R.iter().position(|&name| name == token).ok_or_else(|| format!("unknown register {}", token)),
+    }
+}
+
+pub fn parse_number(token: &str) -> Result<i64, String> {
+    let token = token.trim();
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, token.strip_prefix('+').unwrap_or(token)),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).map(|n| n as i64)
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        u64::from_str_radix(binary, 2).map(|n| n as i64)
+    } else {
+        digits.parse::<i64>()
+    };
+    let value = value.map_err(|_| format!("invalid number {}", token))?;
+    Ok(if negative { value.wrapping_neg() } else { value })
+}
+
+// offset(reg), with the offset optional
+fn parse_indirect(token: &str) -> Result<(i64, usize), String> {
+    let token = token.trim();
+    let (offset, rest) =
+        token.split_once('(').ok_or_else(|| format!("expected offset(register) but found {}", token))?;
+    let reg = rest.strip_suffix(')').ok_or_else(|| format!("missing ) in {}", token))?;
+    let offset = if offset.trim().is_empty() { 0 } else { parse_number(offset)? };
+    Ok((offset, parse_register(reg)?))
+}
+
+fn parse_csr(token: &str) -> Result<i64, String> {
+    match token.trim() {
+        "mhartid" => Ok(MHARTID),
+        token => parse_number(token),
+    }
+}
+
+// lui and auipc take the upper 20 bits, as in GNU as, or the full value
+// as risclet's own listings show it
+fn parse_upper(token: &str) -> Result<i64, String> {
+    let value = parse_number(token)?;
+    if (0..=0xfffff).contains(&value) { Ok(((value << 12) as i32) as i64) } else { Ok(value) }
+}
+
+// assemble one line: a base instruction, a compressed one, or a single-instruction pseudo-instruction,
+// with branch and jump targets given as offsets from the instruction
+pub fn assemble(line: &str) -> Result<Op, String> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let args: Vec<&str> = if rest.trim().is_empty() { Vec::new() } else { rest.split(',').map(str::trim).collect() };
+    let expect = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(format!("{} takes {} operand{} but found {}", mnemonic, n, if n == 1 { "" } else { "s" }, args.len()))
+        }
+    };
+    let reg = |i: usize| parse_register(args[i]);
+    let num = |i: usize| parse_number(args[i]);
+
+    let r = |f: fn(usize, usize, usize) -> Op| -> Result<Op, String> {
+        expect(3)?;
+        Ok(f(reg(0)?, reg(1)?, reg(2)?))
+    };
+    let i = |f: fn(usize, usize, i64) -> Op| -> Result<Op, String> {
+        expect(3)?;
+        Ok(f(reg(0)?, reg(1)?, num(2)?))
+    };
+    let branch = |f: fn(usize, usize, i64) -> Op, swap: bool| -> Result<Op, String> {
+        expect(3)?;
+        let (a, b) = if swap { (reg(1)?, reg(0)?) } else { (reg(0)?, reg(1)?) };
+        Ok(f(a, b, num(2)?))
+    };
+    let load = |f: fn(usize, usize, i64) -> Op| -> Result<Op, String> {
+        expect(2)?;
+        let (offset, rs1) = parse_indirect(args[1])?;
+        Ok(f(reg(0)?, rs1, offset))
+    };
+    let store = |f: fn(usize, usize, i64) -> Op| -> Result<Op, String> {
+        expect(2)?;
+        let (offset, rs1) = parse_indirect(args[1])?;
+        Ok(f(rs1, reg(0)?, offset))
+    };
+
+    if mnemonic.starts_with("c.") {
+        return assemble_compressed(mnemonic, &args);
+    }
+
+    let op = match mnemonic {
+        "" => return Err("nothing to encode".to_string()),
+
+        // r-type
+        "add" => r(|rd, rs1, rs2| Op::Add { rd, rs1, rs2 })?,
+        "sub" => r(|rd, rs1, rs2| Op::Sub { rd, rs1, rs2 })?,
+        "sll" => r(|rd, rs1, rs2| Op::Sll { rd, rs1, rs2 })?,
+        "slt" => r(|rd, rs1, rs2| Op::Slt { rd, rs1, rs2 })?,
+        "sltu" => r(|rd, rs1, rs2| Op::Sltu { rd, rs1, rs2 })?,
+        "xor" => r(|rd, rs1, rs2| Op::Xor { rd, rs1, rs2 })?,
+        "srl" => r(|rd, rs1, rs2| Op::Srl { rd, rs1, rs2 })?,
+        "sra" => r(|rd, rs1, rs2| Op::Sra { rd, rs1, rs2 })?,
+        "or" => r(|rd, rs1, rs2| Op::Or { rd, rs1, rs2 })?,
+        "and" => r(|rd, rs1, rs2| Op::And { rd, rs1, rs2 })?,
+        "addw" => r(|rd, rs1, rs2| Op::Addw { rd, rs1, rs2 })?,
+        "subw" => r(|rd, rs1, rs2| Op::Subw { rd, rs1, rs2 })?,
+        "sllw" => r(|rd, rs1, rs2| Op::Sllw { rd, rs1, rs2 })?,
+        "srlw" => r(|rd, rs1, rs2| Op::Srlw { rd, rs1, rs2 })?,
+        "sraw" => r(|rd, rs1, rs2| Op::Sraw { rd, rs1, rs2 })?,
+
+        // i-type
+        "addi" => i(|rd, rs1, imm| Op::Addi { rd, rs1, imm })?,
+        "slti" => i(|rd, rs1, imm| Op::Slti { rd, rs1, imm })?,
+        "sltiu" => i(|rd, rs1, imm| Op::Sltiu { rd, rs1, imm })?,
+        "xori" => i(|rd, rs1, imm| Op::Xori { rd, rs1, imm })?,
+        "ori" => i(|rd, rs1, imm| Op::Ori { rd, rs1, imm })?,
+        "andi" => i(|rd, rs1, imm| Op::Andi { rd, rs1, imm })?,
+        "slli" => i(|rd, rs1, shamt| Op::Slli { rd, rs1, shamt })?,
+        "srli" => i(|rd, rs1, shamt| Op::Srli { rd, rs1, shamt })?,
+        "srai" => i(|rd, rs1, shamt| Op::Srai { rd, rs1, shamt })?,
+        "addiw" => i(|rd, rs1, imm| Op::Addiw { rd, rs1, imm })?,
+        "slliw" => i(|rd, rs1, shamt| Op::Slliw { rd, rs1, shamt })?,
+        "srliw" => i(|rd, rs1, shamt| Op::Srliw { rd, rs1, shamt })?,
+        "sraiw" => i(|rd, rs1, shamt| Op::Sraiw { rd, rs1, shamt })?,
+
+        // branch
+        "beq" => branch(|rs1, rs2, offset| Op::Beq { rs1, rs2, offset }, false)?,
+        "bne" => branch(|rs1, rs2, offset| Op::Bne { rs1, rs2, offset }, false)?,
+        "blt" => branch(|rs1, rs2, offset| Op::Blt { rs1, rs2, offset }, false)?,
+        "bge" => branch(|rs1, rs2, offset| Op::Bge { rs1, rs2, offset }, false)?,
+        "bltu" => branch(|rs1, rs2, offset| Op::Bltu { rs1, rs2, offset }, false)?,
+        "bgeu" => branch(|rs1, rs2, offset| Op::Bgeu { rs1, rs2, offset }, false)?,
+        "bgt" => branch(|rs1, rs2, offset| Op::Blt { rs1, rs2, offset }, true)?,
+        "ble" => branch(|rs1, rs2, offset| Op::Bge { rs1, rs2, offset }, true)?,
+        "bgtu" => branch(|rs1, rs2, offset| Op::Bltu { rs1, rs2, offset }, true)?,
+        "bleu" => branch(|rs1, rs2, offset| Op::Bgeu { rs1, rs2, offset }, true)?,
+        "beqz" | "bnez" | "bltz" | "bgez" | "blez" | "bgtz" => {
+            expect(2)?;
+            let (rs, offset) = (reg(0)?, num(1)?);
+            match mnemonic {
+                "beqz" => Op::Beq { rs1: rs, rs2: ZERO, offset },
+                "bnez" => Op::Bne { rs1: rs, rs2: ZERO, offset },
+                "bltz" => Op::Blt { rs1: rs, rs2: ZERO, offset },
+                "bgez" => Op::Bge { rs1: rs, rs2: ZERO, offset },
+                "blez" => Op::Bge { rs1: ZERO, rs2: rs, offset },
+                _ => Op::Blt { rs1: ZERO, rs2: rs, offset },
+            }
+        }
+
+        // jump
+        "jal" if args.len() == 1 => Op::Jal { rd: RA, offset: num(0)? },
+        "jal" => {
+            expect(2)?;
+            Op::Jal { rd: reg(0)?, offset: num(1)? }
+        }
+        "j" => {
+            expect(1)?;
+            Op::Jal { rd: ZERO, offset: num(0)? }
+        }
+        "jalr" if args.len() == 1 => Op::Jalr { rd: RA, rs1: reg(0)?, offset: 0 },
+        "jalr" if args.len() == 2 && args[1].contains('(') => {
+            let (offset, rs1) = parse_indirect(args[1])?;
+            Op::Jalr { rd: reg(0)?, rs1, offset }
+        }
+        "jalr" if args.len() == 2 => Op::Jalr { rd: reg(0)?, rs1: reg(1)?, offset: 0 },
+        "jalr" => i(|rd, rs1, offset| Op::Jalr { rd, rs1, offset })?,
+        "jr" => {
+            expect(1)?;
+            Op::Jalr { rd: ZERO, rs1: reg(0)?, offset: 0 }
+        }
+        "ret" => {
+            expect(0)?;
+            Op::Jalr { rd: ZERO, rs1: RA, offset: 0 }
+        }
+
+        // load and store
+        "lb" => load(|rd, rs1, offset| Op::Lb { rd, rs1, offset })?,
+        "lh" => load(|rd, rs1, offset| Op::Lh { rd, rs1, offset })?,
+        "lw" => load(|rd, rs1, offset| Op::Lw { rd, rs1, offset })?,
+        "ld" => load(|rd, rs1, offset| Op::Ld { rd, rs1, offset })?,
+        "lbu" => load(|rd, rs1, offset| Op::Lbu { rd, rs1, offset })?,
+        "lhu" => load(|rd, rs1, offset| Op::Lhu { rd, rs1, offset })?,
+        "lwu" => load(|rd, rs1, offset| Op::Lwu { rd, rs1, offset })?,
+        "sb" => store(|rs1, rs2, offset| Op::Sb { rs1, rs2, offset })?,
+        "sh" => store(|rs1, rs2, offset| Op::Sh { rs1, rs2, offset })?,
+        "sw" => store(|rs1, rs2, offset| Op::Sw { rs1, rs2, offset })?,
+        "sd" => store(|rs1, rs2, offset| Op::Sd { rs1, rs2, offset })?,
+
+        // u-type
+        "lui" | "auipc" => {
+            expect(2)?;
+            let (rd, imm) = (reg(0)?, parse_upper(args[1])?);
+            if mnemonic == "lui" { Op::Lui { rd, imm } } else { Op::Auipc { rd, imm } }
+        }
+
+        // misc
+        "fence" => Op::Fence,
+        "ecall" => {
+            expect(0)?;
+            Op::Ecall
+        }
+        "ebreak" => {
+            expect(0)?;
+            Op::Ebreak
+        }
+        "csrr" => {
+            expect(2)?;
+            Op::Csrr { rd: reg(0)?, csr: parse_csr(args[1])? }
+        }
+
+        // m extension
+        "mul" => r(|rd, rs1, rs2| Op::Mul { rd, rs1, rs2 })?,
+        "mulh" => r(|rd, rs1, rs2| Op::Mulh { rd, rs1, rs2 })?,
+        "mulhsu" => r(|rd, rs1, rs2| Op::Mulhsu { rd, rs1, rs2 })?,
+        "mulhu" => r(|rd, rs1, rs2| Op::Mulhu { rd, rs1, rs2 })?,
+        "div" => r(|rd, rs1, rs2| Op::Div { rd, rs1, rs2 })?,
+        "divu" => r(|rd, rs1, rs2| Op::Divu { rd, rs1, rs2 })?,
+        "rem" => r(|rd, rs1, rs2| Op::Rem { rd, rs1, rs2 })?,
+        "remu" => r(|rd, rs1, rs2| Op::Remu { rd, rs1, rs2 })?,
+        "mulw" => r(|rd, rs1, rs2| Op::Mulw { rd, rs1, rs2 })?,
+        "divw" => r(|rd, rs1, rs2| Op::Divw { rd, rs1, rs2 })?,
+        "divuw" => r(|rd, rs1, rs2| Op::Divuw { rd, rs1, rs2 })?,
+        "remw" => r(|rd, rs1, rs2| Op::Remw { rd, rs1, rs2 })?,
+        "remuw" => r(|rd, rs1, rs2| Op::Remuw { rd, rs1, rs2 })?,
+
+        // pseudo-instructions
+        "nop" => {
+            expect(0)?;
+            Op::Addi { rd: ZERO, rs1: ZERO, imm: 0 }
+        }
+        "li" => {
+            expect(2)?;
+            let imm = num(1)?;
+            if !(-2048..=2047).contains(&imm) {
+                return Err(format!("li of {} takes more than one instruction", imm));
+            }
+            Op::Addi { rd: reg(0)?, rs1: ZERO, imm }
+        }
+        "mv" | "not" | "neg" | "negw" | "sext.w" | "seqz" | "snez" | "sltz" | "sgtz" => {
+            expect(2)?;
+            let (rd, rs) = (reg(0)?, reg(1)?);
+            match mnemonic {
+                "mv" => Op::Addi { rd, rs1: rs, imm: 0 },
+                "not" => Op::Xori { rd, rs1: rs, imm: -1 },
+                "neg" => Op::Sub { rd, rs1: ZERO, rs2: rs },
+                "negw" => Op::Subw { rd, rs1: ZERO, rs2: rs },
+                "sext.w" => Op::Addiw { rd, rs1: rs, imm: 0 },
+                "seqz" => Op::Sltiu { rd, rs1: rs, imm: 1 },
+                "snez" => Op::Sltu { rd, rs1: ZERO, rs2: rs },
+                "sltz" => Op::Slt { rd, rs1: rs, rs2: ZERO },
+                _ => Op::Slt { rd, rs1: ZERO, rs2: rs },
+            }
+        }
+
+        _ => return Err(format!("unknown instruction {}", mnemonic)),
+    };
+    Ok(op)
+}
+
+// a compressed instruction is assembled as the base instruction it stands
+// for, which must then have that compressed encoding
+fn assemble_compressed(mnemonic: &str, args: &[&str]) -> Result<Op, String> {
+    let base = match mnemonic {
+        // operands as the base instruction takes them
+        "c.nop" => "nop",
+        "c.ebreak" => "ebreak",
+        "c.li" => "li",
+        "c.lui" => "lui",
+        "c.j" => "j",
+        "c.jr" => "jr",
+        "c.jalr" => "jalr",
+        "c.beqz" => "beqz",
+        "c.bnez" => "bnez",
+        "c.addi4spn" => "addi",
+        "c.lw" | "c.lwsp" => "lw",
+        "c.ld" | "c.ldsp" => "ld",
+        "c.sw" | "c.swsp" => "sw",
+        "c.sd" | "c.sdsp" => "sd",
+        "c.mv" => "add",
+        "c.addi16sp" => "addi",
+
+        // rd is also the first source
+        "c.addi" => "addi",
+        "c.addiw" => "addiw",
+        "c.slli" => "slli",
+        "c.srli" => "srli",
+        "c.srai" => "srai",
+        "c.andi" => "andi",
+        "c.add" => "add",
+        "c.sub" => "sub",
+        "c.xor" => "xor",
+        "c.or" => "or",
+        "c.and" => "and",
+        "c.subw" => "subw",
+        "c.addw" => "addw",
+        _ => return Err(format!("unknown instruction {}", mnemonic)),
+    };
+    let operands = match (mnemonic, args) {
+        ("c.mv", [rd, rs2]) => format!("{}, zero, {}", rd, rs2),
+        ("c.addi16sp", [imm]) => format!("sp, sp, {}", imm),
+        ("c.addi16sp", [rd, imm]) => format!("{}, {}, {}", rd, rd, imm),
+        ("c.addi" | "c.addiw" | "c.slli" | "c.srli" | "c.srai" | "c.andi", [rd, imm]) => {
+            format!("{}, {}, {}", rd, rd, imm)
+        }
+        ("c.add" | "c.sub" | "c.xor" | "c.or" | "c.and" | "c.subw" | "c.addw", [rd, rs2]) => {
+            format!("{}, {}, {}", rd, rd, rs2)
+        }
+        ("c.mv" | "c.addi" | "c.addiw" | "c.slli" | "c.srli" | "c.srai" | "c.andi", _)
+        | ("c.add" | "c.sub" | "c.xor" | "c.or" | "c.and" | "c.subw" | "c.addw", _) => {
+            return Err(format!("{} takes 2 operands but found {}", mnemonic, args.len()));
+        }
+        _ => args.join(", "),
+    };
+    let line = format!("{} {}", base, operands);
+    let op = assemble(&line).map_err(|e| match e.strip_prefix(base) {
+        Some(rest) if rest.starts_with(" takes") => format!("{}{}", mnemonic, rest),
+        _ => e,
+    })?;
+
+    let written = format!("{} {}", mnemonic, args.join(", "));
+    match op.encode_compressed() {
+        Some(inst) if compressed_layout(inst).0 == mnemonic => Ok(op),
+        Some(inst) => Err(format!("{} is {} in compressed form", written.trim(), compressed_layout(inst).0)),
+        None => Err(format!("{} has no compressed encoding; the uncompressed form is {}", written.trim(), line.trim())),
+    }
+}
+
+fn describe_encoding(out: &mut String, inst: i32, length: i64) {
+    let (mnemonic, format, fields) = bit_fields(inst, length);
+    let bytes: Vec<String> = (0..length).map(|i| format!("{:02x}", (inst >> (8 * i)) & 0xff)).collect();
+    if length == 2 {
+        writeln!(out, "compressed: 0x{:04x}  bytes {}  {}, {}", inst & 0xffff, bytes.join(" "), mnemonic, format)
+            .unwrap();
+    } else {
+        writeln!(out, "0x{:08x}  bytes {}  {}", inst, bytes.join(" "), format).unwrap();
+    }
+    let (labels, values) = bit_field_rows(inst, &fields);
+    writeln!(out, "  {}", labels).unwrap();
+    writeln!(out, "  {}", values).unwrap();
+}
+
+pub fn encode_command(line: &str) -> Result<(), String> {
+    let op = assemble(line)?;
+    let inst = op.encode()?;
+
+    let mut out = String::new();
+    let text = fields_to_disassembly(&op.to_fields(), 0, 0, false, false, true, RegisterNames::Abi, &HashMap::new());
+    writeln!(out, "{}", text).unwrap();
+    if !line.trim_start().starts_with("c.") {
+        describe_encoding(&mut out, inst, 4);
+    }
+    if let Some(compressed) = op.encode_compressed() {
+        describe_encoding(&mut out, compressed, 2);
+    }
+    print!("{}", out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one line for every Op variant the assembler produces
+    const BASE: &[&str] = &[
+        "add a0, a1, a2",
+        "sub t0, t1, t2",
+        "sll s0, s1, a0",
+        "slt a3, a4, a5",
+        "sltu a6, a7, s2",
+        "xor s3, s4, s5",
+        "srl s6, s7, s8",
+        "sra s9, s10, s11",
+        "or t3, t4, t5",
+        "and t6, zero, ra",
+        "addw a0, a1, a2",
+        "subw a0, a1, a2",
+        "sllw a0, a1, a2",
+        "srlw a0, a1, a2",
+        "sraw a0, a1, a2",
+        "addi a0, a1, -2048",
+        "slti a0, a1, 2047",
+        "sltiu a0, a1, 1",
+        "xori a0, a1, -1",
+        "ori a0, a1, 0x7f0",
+        "andi a0, a1, 0xff",
+        "slli a0, a1, 63",
+        "srli a0, a1, 1",
+        "srai a0, a1, 32",
+        "addiw a0, a1, -1",
+        "slliw a0, a1, 31",
+        "srliw a0, a1, 1",
+        "sraiw a0, a1, 16",
+        "beq a0, a1, -4096",
+        "bne a0, a1, 4094",
+        "blt a0, a1, 8",
+        "bge a0, a1, -8",
+        "bltu a0, a1, 16",
+        "bgeu a0, a1, -16",
+        "jal ra, -1048576",
+        "jal zero, 1048574",
+        "jalr ra, -4(a0)",
+        "lb a0, -1(sp)",
+        "lh a0, 2(a1)",
+        "lw a0, 4(a1)",
+        "ld a0, 2040(a1)",
+        "lbu a0, -2048(a1)",
+        "lhu a0, 6(a1)",
+        "lwu a0, 8(a1)",
+        "sb a0, -1(sp)",
+        "sh a0, 2(a1)",
+        "sw a0, 4(a1)",
+        "sd a0, 2040(a1)",
+        "lui a0, 0xfffff",
+        "auipc a0, 0x12345",
+        "fence",
+        "ecall",
+        "ebreak",
+        "csrr a0, mhartid",
+        "mul a0, a1, a2",
+        "mulh a0, a1, a2",
+        "mulhsu a0, a1, a2",
+        "mulhu a0, a1, a2",
+        "div a0, a1, a2",
+        "divu a0, a1, a2",
+        "rem a0, a1, a2",
+        "remu a0, a1, a2",
+        "mulw a0, a1, a2",
+        "divw a0, a1, a2",
+        "divuw a0, a1, a2",
+        "remw a0, a1, a2",
+        "remuw a0, a1, a2",
+    ];
+
+    // one line for every compressed instruction
+    const COMPRESSED: &[&str] = &[
+        "c.nop",
+        "c.ebreak",
+        "c.addi4spn a0, sp, 1020",
+        "c.lw a0, 124(a1)",
+        "c.ld s0, 248(s1)",
+        "c.sw a5, 4(a4)",
+        "c.sd a5, 8(a4)",
+        "c.addi a0, -32",
+        "c.addiw a0, 31",
+        "c.li t0, -1",
+        "c.addi16sp -512",
+        "c.lui a0, 0xfffff",
+        "c.srli a0, 63",
+        "c.srai a1, 1",
+        "c.andi a2, -1",
+        "c.sub a0, a1",
+        "c.xor a0, a1",
+        "c.or a0, a1",
+        "c.and a0, a1",
+        "c.subw a0, a1",
+        "c.addw a0, a1",
+        "c.j -2048",
+        "c.beqz a0, -256",
+        "c.bnez a0, 254",
+        "c.slli t0, 1",
+        "c.lwsp ra, 252(sp)",
+        "c.ldsp ra, 504(sp)",
+        "c.swsp ra, 252(sp)",
+        "c.sdsp ra, 504(sp)",
+        "c.jr ra",
+        "c.jalr t0",
+        "c.mv a0, t0",
+        "c.add a0, t0",
+    ];
+
+    #[test]
+    fn assembled_instructions_decode_to_themselves() {
+        for line in BASE {
+            let op = assemble(line).unwrap();
+            assert!(Op::new(op.encode().unwrap()) == op, "{}", line);
+        }
+        for line in COMPRESSED {
+            let op = assemble(line).unwrap();
+            let inst = op.encode_compressed().unwrap();
+            assert!(Op::new(inst) == op, "{}", line);
+            assert_eq!(compressed_layout(inst).0, line.split(' ').next().unwrap());
+        }
+    }
+
+    fn error(line: &str) -> String {
+        match assemble(line) {
+            Ok(_) => panic!("{} should not assemble", line),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn compressed_mnemonics_need_a_compressed_encoding() {
+        assert_eq!(
+            error("c.addi a0, 100"),
+            "c.addi a0, 100 has no compressed encoding; the uncompressed form is addi a0, a0, 100"
+        );
+        assert_eq!(error("c.addi sp, 16"), "c.addi sp, 16 is c.addi16sp in compressed form");
+        assert_eq!(error("c.lw a0, 4(sp)"), "c.lw a0, 4(sp) is c.lwsp in compressed form");
+        assert_eq!(error("c.sub a0"), "c.sub takes 2 operands but found 1");
+        assert_eq!(error("c.li a0, 1, 2"), "c.li takes 2 operands but found 3");
+        assert_eq!(error("c.fld a0, 0(a1)"), "unknown instruction c.fld");
+    }
+}
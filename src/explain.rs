@@ -0,0 +1,205 @@
+use super::*;
+
+// Plain-English descriptions of a step for beginners, with the values of the
+// operands substituted in:
+//
+//   a0 <- a1 (5) + a2 (7) = 12
+//   a0 <- load 4 bytes from 0x11008 (sp+8) = -3
+//   branch taken because t0 (3) < t1 (9), pc <- 0x10230
+//
+// Everything comes from the recorded effects, so this works the same for live
+// runs, checkpointed runs, and replays.
+
+impl Effects {
+    pub fn explain(&self, hex_mode: bool, names: RegisterNames) -> String {
+        let num = |value: i64| number(value, hex_mode);
+        let reg = |r: usize| self.operand(r, hex_mode, names);
+        let result = |rd: usize, expr: String| match &self.reg_write {
+            Some((_, new)) => format!("{} <- {} = {}", names.name(rd), expr, num(new.value)),
+            None => format!("{} (discarded because rd is zero)", expr),
+        };
+        let copy = |rd: usize, expr: String| match &self.reg_write {
+            Some(_) => format!("{} <- {}", names.name(rd), expr),
+            None => format!("{} (discarded because rd is zero)", expr),
+        };
+        let binary = |rd: usize, rs1: usize, symbol: &str, rs2: usize| {
+            result(rd, format!("{} {} {}", reg(rs1), symbol, reg(rs2)))
+        };
+        let immediate =
+            |rd: usize, rs1: usize, symbol: &str, imm: i64| result(rd, format!("{} {} {}", reg(rs1), symbol, num(imm)));
+
+        match self.instruction.op {
+            Op::Add { rd, rs1, rs2 } => binary(rd, rs1, "+", rs2),
+            Op::Sub { rd, rs1, rs2 } => binary(rd, rs1, "-", rs2),
+            Op::Sll { rd, rs1, rs2 } => binary(rd, rs1, "<<", rs2),
+            Op::Slt { rd, rs1, rs2 } => result(rd, format!("({} < {}) ? 1 : 0", reg(rs1), reg(rs2))),
+            Op::Sltu { rd, rs1, rs2 } => result(rd, format!("({} < {} unsigned) ? 1 : 0", reg(rs1), reg(rs2))),
+            Op::Xor { rd, rs1, rs2 } => binary(rd, rs1, "^", rs2),
+            Op::Srl { rd, rs1, rs2 } => binary(rd, rs1, ">>>", rs2),
+            Op::Sra { rd, rs1, rs2 } => binary(rd, rs1, ">>", rs2),
+            Op::Or { rd, rs1, rs2 } => binary(rd, rs1, "|", rs2),
+            Op::And { rd, rs1, rs2 } => binary(rd, rs1, "&", rs2),
+
+            Op::Addw { rd, rs1, rs2 } => binary(rd, rs1, "+ (32-bit)", rs2),
+            Op::Subw { rd, rs1, rs2 } => binary(rd, rs1, "- (32-bit)", rs2),
+            Op::Sllw { rd, rs1, rs2 } => binary(rd, rs1, "<< (32-bit)", rs2),
+            Op::Srlw { rd, rs1, rs2 } => binary(rd, rs1, ">>> (32-bit)", rs2),
+            Op::Sraw { rd, rs1, rs2 } => binary(rd, rs1, ">> (32-bit)", rs2),
+
+            Op::Addi { rd: ZERO, rs1: ZERO, imm: 0 } => String::from("no operation"),
+            Op::Addi { rd, rs1: ZERO, imm } => copy(rd, num(imm)),
+            Op::Addi { rd, rs1, imm: 0 } => copy(rd, reg(rs1)),
+            Op::Addi { rd, rs1, imm } if imm < 0 => immediate(rd, rs1, "-", imm.wrapping_neg()),
+            Op::Addi { rd, rs1, imm } => immediate(rd, rs1, "+", imm),
+            Op::Slti { rd, rs1, imm } => result(rd, format!("({} < {}) ? 1 : 0", reg(rs1), num(imm))),
+            Op::Sltiu { rd, rs1, imm } => result(rd, format!("({} < {} unsigned) ? 1 : 0", reg(rs1), num(imm))),
+            Op::Xori { rd, rs1, imm: -1 } => result(rd, format!("~{}", reg(rs1))),
+            Op::Xori { rd, rs1, imm } => immediate(rd, rs1, "^", imm),
+            Op::Ori { rd, rs1, imm } => immediate(rd, rs1, "|", imm),
+            Op::Andi { rd, rs1, imm } => immediate(rd, rs1, "&", imm),
+            Op::Slli { rd, rs1, shamt } => immediate(rd, rs1, "<<", shamt),
+            Op::Srli { rd, rs1, shamt } => immediate(rd, rs1, ">>>", shamt),
+            Op::Srai { rd, rs1, shamt } => immediate(rd, rs1, ">>", shamt),
+
+            Op::Addiw { rd, rs1, imm } => immediate(rd, rs1, "+ (32-bit)", imm),
+            Op::Slliw { rd, rs1, shamt } => immediate(rd, rs1, "<< (32-bit)", shamt),
+            Op::Srliw { rd, rs1, shamt } => immediate(rd, rs1, ">>> (32-bit)", shamt),
+            Op::Sraiw { rd, rs1, shamt } => immediate(rd, rs1, ">> (32-bit)", shamt),
+
+            Op::Beq { rs1, rs2, .. } => self.branch(rs1, "==", "!=", rs2, hex_mode, names),
+            Op::Bne { rs1, rs2, .. } => self.branch(rs1, "!=", "==", rs2, hex_mode, names),
+            Op::Blt { rs1, rs2, .. } => self.branch(rs1, "<", ">=", rs2, hex_mode, names),
+            Op::Bge { rs1, rs2, .. } => self.branch(rs1, ">=", "<", rs2, hex_mode, names),
+            Op::Bltu { rs1, rs2, .. } => self.branch(rs1, "< (unsigned)", ">= (unsigned)", rs2, hex_mode, names),
+            Op::Bgeu { rs1, rs2, .. } => self.branch(rs1, ">= (unsigned)", "< (unsigned)", rs2, hex_mode, names),
+
+            Op::Jal { rd: ZERO, .. } => format!("jump to {}", num(self.pc.1)),
+            Op::Jal { rd, .. } => format!(
+                "call {}, {} <- return address {}",
+                num(self.pc.1),
+                names.name(rd),
+                num(self.pc.0 + self.instruction.length)
+            ),
+            Op::Jalr { rd: ZERO, rs1: RA, offset: 0 } => format!("return to {}", num(self.pc.1)),
+            Op::Jalr { rd, rs1, offset } => {
+                let target =
+                    if offset == 0 { reg(rs1) } else { format!("{} + {} = {}", reg(rs1), num(offset), num(self.pc.1)) };
+                let next = self.pc.0 + self.instruction.length;
+                match rd {
+                    ZERO => format!("jump to {}", target),
+                    _ => format!("call {}, {} <- return address {}", target, names.name(rd), num(next)),
+                }
+            }
+
+            Op::Lb { rd, rs1, offset } => self.load(rd, rs1, offset, "", hex_mode, names),
+            Op::Lh { rd, rs1, offset } => self.load(rd, rs1, offset, "", hex_mode, names),
+            Op::Lw { rd, rs1, offset } => self.load(rd, rs1, offset, "", hex_mode, names),
+            Op::Ld { rd, rs1, offset } => self.load(rd, rs1, offset, "", hex_mode, names),
+            Op::Lbu { rd, rs1, offset } => self.load(rd, rs1, offset, " unsigned", hex_mode, names),
+            Op::Lhu { rd, rs1, offset } => self.load(rd, rs1, offset, " unsigned", hex_mode, names),
+            Op::Lwu { rd, rs1, offset } => self.load(rd, rs1, offset, " unsigned", hex_mode, names),
+
+            Op::Sb { rs1, rs2, offset } => self.store(rs1, rs2, offset, hex_mode, names),
+            Op::Sh { rs1, rs2, offset } => self.store(rs1, rs2, offset, hex_mode, names),
+            Op::Sw { rs1, rs2, offset } => self.store(rs1, rs2, offset, hex_mode, names),
+            Op::Sd { rs1, rs2, offset } => self.store(rs1, rs2, offset, hex_mode, names),
+
+            Op::Lui { rd, imm } => result(rd, format!("0x{:x} << 12", (imm >> 12) & 0xfffff)),
+            Op::Auipc { rd, imm } => result(rd, format!("pc ({}) + {}", num(self.pc.0), num(imm))),
+
+            Op::Fence => String::from("memory fence (no effect with in-order harts)"),
+            Op::Ecall => format!("system call number {}", reg(17)),
+            Op::Ebreak => String::from("breakpoint"),
+            Op::Csrr { rd, csr: MHARTID } => result(rd, String::from("csr mhartid (the hart id)")),
+            Op::Csrr { rd, csr } => result(rd, format!("csr 0x{:x}", csr)),
+
+            Op::Mul { rd, rs1, rs2 } => binary(rd, rs1, "*", rs2),
+            Op::Mulh { rd, rs1, rs2 } => result(rd, format!("upper 64 bits of {} * {}", reg(rs1), reg(rs2))),
+            Op::Mulhsu { rd, rs1, rs2 } => result(rd, format!("upper 64 bits of {} * {} unsigned", reg(rs1), reg(rs2))),
+            Op::Mulhu { rd, rs1, rs2 } => {
+                result(rd, format!("upper 64 bits of {} * {} both unsigned", reg(rs1), reg(rs2)))
+            }
+            Op::Div { rd, rs1, rs2 } => binary(rd, rs1, "/", rs2),
+            Op::Divu { rd, rs1, rs2 } => binary(rd, rs1, "/ (unsigned)", rs2),
+            Op::Rem { rd, rs1, rs2 } => binary(rd, rs1, "%", rs2),
+            Op::Remu { rd, rs1, rs2 } => binary(rd, rs1, "% (unsigned)", rs2),
+
+            Op::Mulw { rd, rs1, rs2 } => binary(rd, rs1, "* (32-bit)", rs2),
+            Op::Divw { rd, rs1, rs2 } => binary(rd, rs1, "/ (32-bit)", rs2),
+            Op::Divuw { rd, rs1, rs2 } => binary(rd, rs1, "/ (32-bit unsigned)", rs2),
+            Op::Remw { rd, rs1, rs2 } => binary(rd, rs1, "% (32-bit)", rs2),
+            Op::Remuw { rd, rs1, rs2 } => binary(rd, rs1, "% (32-bit unsigned)", rs2),
+
+            Op::Unimplemented { .. } => self.report(hex_mode, names).swap_remove(0),
+        }
+    }
+
+    // a register with the value it held before the instruction ran
+    fn operand(&self, reg: usize, hex_mode: bool, names: RegisterNames) -> String {
+        if reg == ZERO {
+            return String::from("0");
+        }
+        match self.reg_reads.iter().find(|read| read.register == reg) {
+            Some(read) => format!("{} ({})", names.name(reg), number(read.value, hex_mode)),
+            None => String::from(names.name(reg)),
+        }
+    }
+
+    fn branch(
+        &self,
+        rs1: usize,
+        taken: &str,
+        not_taken: &str,
+        rs2: usize,
+        hex_mode: bool,
+        names: RegisterNames,
+    ) -> String {
+        let (a, b) = (self.operand(rs1, hex_mode, names), self.operand(rs2, hex_mode, names));
+        if self.pc.1 != self.pc.0 + self.instruction.length {
+            format!("branch taken because {} {} {}, pc <- {}", a, taken, b, number(self.pc.1, hex_mode))
+        } else {
+            format!("branch not taken because {} {} {}", a, not_taken, b)
+        }
+    }
+
+    // where a load or store went, e.g., 0x11008 (sp+8)
+    fn address(&self, address: i64, rs1: usize, offset: i64, names: RegisterNames) -> String {
+        let base = match offset {
+            0 => String::from(names.name(rs1)),
+            _ if offset < 0 => format!("{}{}", names.name(rs1), offset),
+            _ => format!("{}+{}", names.name(rs1), offset),
+        };
+        format!("0x{:x} ({})", address, base)
+    }
+
+    fn load(&self, rd: usize, rs1: usize, offset: i64, unsigned: &str, hex_mode: bool, names: RegisterNames) -> String {
+        let Some(read) = &self.mem_read else {
+            return self.report(hex_mode, names).swap_remove(0);
+        };
+        let bytes = if read.value.len() == 1 { "byte" } else { "bytes" };
+        let from = self.address(read.address, rs1, offset, names);
+        let load = format!("load {} {}{} from {}", read.value.len(), bytes, unsigned, from);
+        match &self.reg_write {
+            Some((_, new)) => format!("{} <- {} = {}", names.name(rd), load, number(new.value, hex_mode)),
+            None => format!("{} (discarded because rd is zero)", load),
+        }
+    }
+
+    fn store(&self, rs1: usize, rs2: usize, offset: i64, hex_mode: bool, names: RegisterNames) -> String {
+        let Some((_, new)) = &self.mem_write else {
+            return self.report(hex_mode, names).swap_remove(0);
+        };
+        let size = new.value.len();
+        let which = if size == 8 {
+            String::from("all 8 bytes")
+        } else {
+            format!("low {} byte{}", size, if size == 1 { "" } else { "s" })
+        };
+        let to = self.address(new.address, rs1, offset, names);
+        format!("store {} of {} to {}", which, self.operand(rs2, hex_mode, names), to)
+    }
+}
+
+fn number(value: i64, hex_mode: bool) -> String {
+    if !hex_mode || (0..=9).contains(&value) { format!("{}", value) } else { format!("0x{:x}", value) }
+}
@@ -0,0 +1,196 @@
+use super::*;
+
+// Export the trace with one record per step, for autograders and notebooks.
+//
+// jsonl writes one JSON object per line:
+//
+//   {"step":1,"hart":0,"pc":65536,"next_pc":65540,"disassembly":"addi    a0, zero, 3",
+//    "reg_reads":[{"reg":"zero","value":0}],"reg_write":{"reg":"a0","old":0,"new":3},
+//    "mem_read":null,"mem_write":null,"stdin":null,"stdout":null,"syscall":null,"error":null}
+//
+// where a memory read is {"address":..,"bytes":"<hex>"} and a memory write is
+// {"address":..,"old":"<hex>","new":"<hex>"}. csv writes the same fields as
+// columns, with reg_reads as space-separated reg=value pairs.
+//
+// syscall is the system call an ecall/ebreak made; error is a lint or
+// runtime error that stopped the program. Register names, in the disassembly,
+// the register fields, and lint errors alike, follow --style.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Jsonl,
+    Csv,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "jsonl" => Ok(TraceFormat::Jsonl),
+            "csv" => Ok(TraceFormat::Csv),
+            _ => Err(format!("unknown trace format {name}")),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "step,hart,pc,next_pc,disassembly,reg_reads,reg_write_reg,reg_write_old,reg_write_new,\
+    mem_read_address,mem_read_bytes,mem_write_address,mem_write_old,mem_write_new,stdin,stdout,syscall,error";
+
+pub fn write_trace(filename: &str, format: TraceFormat, m: &Machine, history: &mut History) -> Result<(), String> {
+    // stream the records, since a checkpointed run may be too long to hold in memory
+    let failed = |e: io::Error| format!("writing {}: {}", filename, e);
+    let mut out = io::BufWriter::new(std::fs::File::create(filename).map_err(failed)?);
+    if format == TraceFormat::Csv {
+        writeln!(out, "{}", CSV_HEADER).map_err(failed)?;
+    }
+
+    for i in 0..history.len() {
+        let effects = history.get(i);
+        let instruction = &effects.instruction;
+        let disassembly = fields_to_disassembly(
+            &instruction.verbose_fields,
+            instruction.address,
+            m.global_pointer,
+            instruction.length == 2,
+            false,
+            false,
+            m.style.registers,
+            &m.address_symbols,
+        );
+        let (syscall, error) = split_message(effects);
+        let text = |bytes: &Option<Vec<u8>>| bytes.as_ref().map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+
+        match format {
+            TraceFormat::Jsonl => {
+                let reads = effects
+                    .reg_reads
+                    .iter()
+                    .map(|read| format!("{{\"reg\":\"{}\",\"value\":{}}}", m.reg_name(read.register), read.value))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let reg_write = match &effects.reg_write {
+                    Some((old, new)) => {
+                        format!(
+                            "{{\"reg\":\"{}\",\"old\":{},\"new\":{}}}",
+                            m.reg_name(old.register),
+                            old.value,
+                            new.value
+                        )
+                    }
+                    None => "null".to_string(),
+                };
+                let mem_read = match &effects.mem_read {
+                    Some(read) => format!("{{\"address\":{},\"bytes\":\"{}\"}}", read.address, to_hex(&read.value)),
+                    None => "null".to_string(),
+                };
+                let mem_write = match &effects.mem_write {
+                    Some((old, new)) => format!(
+                        "{{\"address\":{},\"old\":\"{}\",\"new\":\"{}\"}}",
+                        old.address,
+                        to_hex(&old.value),
+                        to_hex(&new.value)
+                    ),
+                    None => "null".to_string(),
+                };
+                writeln!(
+                    out,
+                    "{{\"step\":{},\"hart\":{},\"pc\":{},\"next_pc\":{},\"disassembly\":{},\"reg_reads\":[{}],\
+                     \"reg_write\":{},\"mem_read\":{},\"mem_write\":{},\"stdin\":{},\"stdout\":{},\"syscall\":{},\"error\":{}}}",
+                    i + 1,
+                    effects.hart,
+                    effects.pc.0,
+                    effects.pc.1,
+                    json_string(Some(&disassembly)),
+                    reads,
+                    reg_write,
+                    mem_read,
+                    mem_write,
+                    json_string(text(&effects.stdin).as_deref()),
+                    json_string(text(&effects.stdout).as_deref()),
+                    json_string(syscall),
+                    json_string(error)
+                )
+                .map_err(failed)?;
+            }
+            TraceFormat::Csv => {
+                let reads = effects
+                    .reg_reads
+                    .iter()
+                    .map(|read| format!("{}={}", m.reg_name(read.register), read.value))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let reg_write = match &effects.reg_write {
+                    Some((old, new)) => {
+                        format!("{},{},{}", m.reg_name(old.register), old.value, new.value)
+                    }
+                    None => ",,".to_string(),
+                };
+                let mem_read = match &effects.mem_read {
+                    Some(read) => format!("{},{}", read.address, to_hex(&read.value)),
+                    None => ",".to_string(),
+                };
+                let mem_write = match &effects.mem_write {
+                    Some((old, new)) => format!("{},{},{}", old.address, to_hex(&old.value), to_hex(&new.value)),
+                    None => ",,".to_string(),
+                };
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    i + 1,
+                    effects.hart,
+                    effects.pc.0,
+                    effects.pc.1,
+                    csv_field(Some(&disassembly)),
+                    reads,
+                    reg_write,
+                    mem_read,
+                    mem_write,
+                    csv_field(text(&effects.stdin).as_deref()),
+                    csv_field(text(&effects.stdout).as_deref()),
+                    csv_field(syscall),
+                    csv_field(error)
+                )
+                .map_err(failed)?;
+            }
+        }
+    }
+
+    out.flush().map_err(failed)
+}
+
+// other_message is either the system call an ecall/ebreak made or an error
+fn split_message(effects: &Effects) -> (Option<&str>, Option<&str>) {
+    let Some(msg) = effects.other_message.as_deref() else {
+        return (None, None);
+    };
+    let is_syscall =
+        matches!(effects.instruction.op, Op::Ecall | Op::Ebreak) && (!effects.terminate || msg.starts_with("exit("));
+    if is_syscall { (Some(msg), None) } else { (None, Some(msg)) }
+}
+
+fn json_string(s: Option<&str>) -> String {
+    let Some(s) = s else {
+        return "null".to_string();
+    };
+    let mut out = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => write!(out, "\\u{:04x}", ch as u32).unwrap(),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_field(s: Option<&str>) -> String {
+    match s {
+        Some(s) if s.contains([',', '"', '\n', '\r']) => format!("\"{}\"", s.replace('"', "\"\"")),
+        Some(s) => s.to_string(),
+        None => String::new(),
+    }
+}
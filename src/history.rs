@@ -0,0 +1,408 @@
+use super::*;
+
+// History is the sequence of Effects the debugger moves through.
+//
+// By default every step is kept. For long runs, tracing with checkpoints keeps
+// a snapshot of the machine every chunk_size steps instead, and only one chunk
+// of Effects (the window) at a time. Moving into another chunk re-executes it
+// from its checkpoint, which is deterministic because the checkpoint includes
+// the scheduler and linter state and stdin reads are replayed from the trace.
+// Like memory, the linter's record of memory is kept in shared pages, so each
+// checkpoint only adds the pages written since the one before.
+
+// memory is snapshotted in pages so pages that did not change since the
+// previous checkpoint are shared with it rather than copied again
+const PAGE_SIZE: usize = 4096;
+
+struct Snapshot {
+    pages: Vec<Vec<Rc<[u8]>>>, // per segment, empty if not writeable
+    x: [i64; 32],
+    pc: i64,
+    hart: usize,
+    harts: Vec<Hart>,
+    stack_frames: Vec<i64>,
+}
+
+// what the linter knows about memory, in pages that a checkpoint shares with
+// the live tracer until a store touches them
+#[derive(Clone, Default)]
+pub struct LintMemory {
+    pages: HashMap<i64, Rc<HashMap<i64, ValueInMemory>>>,
+}
+
+impl LintMemory {
+    pub(crate) fn get(&self, address: &i64) -> Option<&ValueInMemory> {
+        self.pages.get(&(address / PAGE_SIZE as i64))?.get(address)
+    }
+
+    pub(crate) fn contains_key(&self, address: &i64) -> bool {
+        self.get(address).is_some()
+    }
+
+    pub(crate) fn insert(&mut self, address: i64, value: ValueInMemory) {
+        let page = self.pages.entry(address / PAGE_SIZE as i64).or_default();
+        Rc::make_mut(page).insert(address, value);
+    }
+}
+
+struct Checkpoint {
+    snapshot: Snapshot,
+    tracer: Tracer,
+    output_len: usize,
+    reads: usize,
+}
+
+pub struct History {
+    len: usize,
+    window_start: usize,
+    window: Vec<Effects>,
+
+    // only used when tracing with checkpoints
+    chunk_size: usize,
+    checkpoints: Vec<Checkpoint>,
+    last: Option<Effects>, // the final step exactly as traced
+    output: Vec<u8>,       // stdout with stdin echoed, as the debugger shows it
+    reads: Vec<Vec<u8>>,   // every stdin read, in order
+    scratch: Option<Box<Machine>>,
+    instructions: Vec<Rc<Instruction>>,
+    addresses: HashMap<i64, usize>,
+}
+
+impl History {
+    // checkpoint_steps == 0 means keep every step
+    pub fn new(
+        m: &Machine,
+        instructions: &[Rc<Instruction>],
+        addresses: &HashMap<i64, usize>,
+        checkpoint_steps: usize,
+    ) -> Self {
+        let checkpointed = checkpoint_steps > 0;
+        History {
+            len: 0,
+            window_start: 0,
+            window: Vec::new(),
+            chunk_size: checkpoint_steps,
+            checkpoints: Vec::new(),
+            last: None,
+            output: Vec::new(),
+            reads: Vec::new(),
+            scratch: if checkpointed { Some(Box::new(m.clone())) } else { None },
+            instructions: if checkpointed { instructions.to_vec() } else { Vec::new() },
+            addresses: if checkpointed { addresses.clone() } else { HashMap::new() },
+        }
+    }
+
+    pub fn from_effects(sequence: Vec<Effects>) -> Self {
+        History {
+            len: sequence.len(),
+            window_start: 0,
+            window: sequence,
+            chunk_size: 0,
+            checkpoints: Vec::new(),
+            last: None,
+            output: Vec::new(),
+            reads: Vec::new(),
+            scratch: None,
+            instructions: Vec::new(),
+            addresses: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_checkpointed(&self) -> bool {
+        self.chunk_size > 0
+    }
+
+    // take a checkpoint if the next step starts a new chunk
+    pub(crate) fn checkpoint(&mut self, m: &Machine, tracer: &Tracer) {
+        if !self.is_checkpointed() || !self.len.is_multiple_of(self.chunk_size) {
+            return;
+        }
+        let snapshot = m.snapshot(self.checkpoints.last().map(|checkpoint| &checkpoint.snapshot));
+        self.checkpoints.push(Checkpoint {
+            snapshot,
+            tracer: tracer.clone(),
+            output_len: self.output.len(),
+            reads: self.reads.len(),
+        });
+    }
+
+    // add the next step; only the latest is kept unless keep is set
+    pub fn push(&mut self, effects: Effects, keep: bool) {
+        if self.is_checkpointed() {
+            if let Some(output) = &effects.stdout {
+                self.output.extend(output);
+            }
+            if let Some(input) = &effects.stdin {
+                self.output.extend(input);
+                self.reads.push(input.clone());
+            }
+        }
+        if !keep || self.is_checkpointed() {
+            self.window.clear();
+            self.window_start = self.len;
+        }
+        self.window.push(effects);
+        self.len += 1;
+    }
+
+    // count steps that ran without recording their effects
+    pub fn skip(&mut self, steps: usize) {
+        self.window.clear();
+        self.len += steps;
+        self.window_start = self.len;
+    }
+
+    pub fn last(&mut self) -> Option<&Effects> {
+        if self.is_empty() { None } else { Some(self.get(self.len - 1)) }
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut Effects> {
+        if self.window_start + self.window.len() == self.len { self.window.last_mut() } else { None }
+    }
+
+    pub fn get(&mut self, i: usize) -> &Effects {
+        assert!(i < self.len);
+        if i < self.window_start || i >= self.window_start + self.window.len() {
+            self.materialize(i / self.chunk_size);
+        }
+        &self.window[i - self.window_start]
+    }
+
+    // the first step of the chunk containing step i; scans that look back
+    // through the history stop here rather than re-executing earlier chunks
+    pub fn chunk_start(&self, i: usize) -> usize {
+        if self.is_checkpointed() { i - i % self.chunk_size } else { 0 }
+    }
+
+    // move the machine from the state before step `from` to the state before step `to`
+    pub fn seek(&mut self, m: &mut Machine, mut from: usize, to: usize) {
+        if self.is_checkpointed() && from / self.chunk_size != to / self.chunk_size {
+            let checkpoint = &self.checkpoints[to / self.chunk_size];
+            m.restore(&checkpoint.snapshot);
+            m.stdout = self.output[..checkpoint.output_len].to_vec();
+            from = self.chunk_start(to);
+        }
+        while from < to {
+            m.apply(self.get(from), true);
+            from += 1;
+        }
+        while from > to {
+            from -= 1;
+            m.apply(self.get(from), false);
+        }
+    }
+
+    // re-execute a chunk from its checkpoint to fill the window
+    fn materialize(&mut self, chunk: usize) {
+        assert!(self.is_checkpointed(), "step was not kept in the history");
+
+        // hold on to the final step, which may carry messages added by trace
+        if self.last.is_none() && self.window_start + self.window.len() == self.len {
+            self.last = self.window.pop();
+        }
+
+        let checkpoint = &self.checkpoints[chunk];
+        let m = self.scratch.as_mut().unwrap();
+        m.restore(&checkpoint.snapshot);
+        m.input = Some(self.reads[checkpoint.reads..].iter().cloned().collect());
+        let mut tracer = checkpoint.tracer.clone();
+
+        let start = chunk * self.chunk_size;
+        let end = min(start + self.chunk_size, self.len);
+        self.window.clear();
+        self.window_start = start;
+        for step in start..end {
+            if step + 1 == self.len
+                && let Some(last) = &self.last
+            {
+                self.window.push(last.clone());
+                break;
+            }
+            let (instruction, mut effects) =
+                tracer.step(m, &self.instructions, &self.addresses).expect("re-execution should follow the trace");
+            tracer.check(m, &instruction, &mut effects);
+            self.window.push(effects);
+        }
+    }
+}
+
+impl Machine {
+    fn snapshot(&self, previous: Option<&Snapshot>) -> Snapshot {
+        let pages = self
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(s, segment)| {
+                if !segment.writeable {
+                    return Vec::new();
+                }
+                segment
+                    .mem
+                    .chunks(PAGE_SIZE)
+                    .enumerate()
+                    .map(|(p, page)| match previous.and_then(|prev| prev.pages[s].get(p)) {
+                        Some(old) if **old == *page => old.clone(),
+                        _ => Rc::from(page),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Snapshot {
+            pages,
+            x: self.x,
+            pc: self.pc,
+            hart: self.hart,
+            harts: self.harts.clone(),
+            stack_frames: self.stack_frames.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        for (segment, pages) in self.segments.iter_mut().zip(&snapshot.pages) {
+            if segment.writeable {
+                segment.mem.clear();
+                for page in pages {
+                    segment.mem.extend_from_slice(page);
+                }
+            }
+        }
+
+        self.x = snapshot.x;
+        self.pc = snapshot.pc;
+        self.hart = snapshot.hart;
+        self.harts = snapshot.harts.clone();
+        self.stack_frames = snapshot.stack_frames.clone();
+        self.stack_start = self.harts[self.hart].stack_start;
+        self.stack_end = self.harts[self.hart].stack_end;
+        self.effects = None;
+
+        // output and input are not in the snapshot; seek sets the output it needs
+        self.stdout.clear();
+        self.stdin.clear();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // a loop that calls a function that saves s0 and ra, clobbers s0, and
+    // restores both, so the linter has to remember what it saw stored
+    const SAVES_AND_RESTORES: &[&str] = &[
+        "addi s0, zero, 40",
+        "jal ra, 24",
+        "addi s0, s0, -1",
+        "bne s0, zero, -8",
+        "addi a0, zero, 0",
+        "addi a7, zero, 93",
+        "ecall",
+        "addi sp, sp, -16",
+        "sd ra, 8(sp)",
+        "sd s0, 0(sp)",
+        "addi s0, zero, 7",
+        "ld s0, 0(sp)",
+        "ld ra, 8(sp)",
+        "addi sp, sp, 16",
+        "jalr zero, 0(ra)",
+    ];
+
+    // decode the text segment the way main does, without pseudo-instructions
+    pub(crate) fn decode(m: &mut Machine) -> (Vec<Rc<Instruction>>, HashMap<i64, usize>) {
+        let mut instructions = Vec::new();
+        let mut pc = m.text_start;
+        while pc < m.text_end {
+            let (inst, length) = m.load_instruction(pc).unwrap();
+            instructions.push(Instruction {
+                address: pc,
+                op: Op::new(inst),
+                length,
+                pseudo_index: instructions.len(),
+                verbose_fields: Vec::new(),
+                pseudo_fields: Vec::new(),
+            });
+            pc += length;
+        }
+        add_local_labels(m, &instructions);
+        let instructions: Vec<Rc<Instruction>> = instructions.into_iter().map(Rc::new).collect();
+        let addresses = instructions.iter().enumerate().map(|(i, inst)| (inst.address, i)).collect();
+        (instructions, addresses)
+    }
+
+    pub(crate) fn trace_machine(mut m: Machine, lint: bool, seed: Option<u64>, checkpoint_steps: usize) -> History {
+        let (instructions, addresses) = decode(&mut m);
+        let tracer = Tracer::new(&m, lint, Scheduler::new(m.harts.len(), 3, seed));
+        trace(&mut m, &instructions, &addresses, MAX_STEPS_DEFAULT, "", true, checkpoint_steps, tracer, &mut [])
+    }
+
+    pub(crate) fn summary(effects: &Effects) -> String {
+        format!(
+            "{} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            effects.hart,
+            effects.pc,
+            effects.reg_write.as_ref().map(|(old, new)| (new.register, old.value, new.value)),
+            effects.mem_write.as_ref().map(|(old, new)| (new.address, &old.value, &new.value)),
+            effects.stdout,
+            effects.stdin,
+            effects.other_message,
+            effects.function_start,
+            effects.function_end,
+            effects.terminate,
+        )
+    }
+
+    fn assert_same_effects(load: impl Fn() -> Machine, lint: bool, seed: Option<u64>) {
+        let mut kept = trace_machine(load(), lint, seed, 0);
+        let mut checkpointed = trace_machine(load(), lint, seed, 7);
+        assert_eq!(kept.len(), checkpointed.len());
+        assert!(kept.len() > 7 * 3, "the run should span several chunks");
+
+        // visit the chunks out of order so each one is re-executed from its checkpoint
+        let len = kept.len();
+        for i in (0..len).rev().step_by(5).chain(0..len) {
+            assert_eq!(summary(kept.get(i)), summary(checkpointed.get(i)), "step {}", i);
+        }
+    }
+
+    fn a_out(hart_count: usize) -> impl Fn() -> Machine {
+        move || load_elf("test/a.out", hart_count).unwrap()
+    }
+
+    fn saves_and_restores() -> Machine {
+        let code = SAVES_AND_RESTORES
+            .iter()
+            .flat_map(|line| assemble(line).unwrap().encode().unwrap().to_le_bytes())
+            .collect();
+        let code: Vec<u8> = code;
+        let end = 0x10000 + code.len() as i64;
+        let text = Segment::new(".text", 0x10000, end, false, true, code);
+        let symbols = HashMap::from([(0x10000, "_start".to_string()), (0x1001c, "save".to_string())]);
+        Machine::new(vec![text], 0x10000, 0, symbols, HashMap::new(), HashMap::new(), HashSet::new(), HashSet::new(), 1)
+    }
+
+    #[test]
+    fn checkpointed_trace_re_executes_the_same_effects() {
+        assert_same_effects(a_out(1), false, None);
+    }
+
+    #[test]
+    fn checkpointed_trace_re_executes_the_same_effects_with_lint() {
+        assert_same_effects(a_out(1), true, None);
+        assert_same_effects(saves_and_restores, true, None);
+        let mut history = trace_machine(saves_and_restores(), true, None, 7);
+        assert_eq!(history.last().unwrap().other_message.as_deref(), Some("exit(0)"));
+    }
+
+    #[test]
+    fn checkpointed_trace_re_executes_the_same_effects_with_random_harts() {
+        assert_same_effects(a_out(2), true, Some(0x5eed));
+    }
+}
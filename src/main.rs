@@ -1,19 +1,54 @@
+pub mod cache;
+pub mod callgraph;
+pub mod cfg;
+pub mod commitlog;
+pub mod coverage;
+pub mod dasm;
+pub mod encode;
+pub mod explain;
+pub mod export;
+pub mod history;
+pub mod pipeline;
+pub mod predictor;
+pub mod profile;
+pub mod replay;
 pub mod riscv;
+pub mod selftest;
+pub mod staticlint;
 pub mod ui;
 
+use self::cache::*;
+use self::callgraph::*;
+use self::cfg::*;
+use self::commitlog::*;
+use self::coverage::*;
+use self::dasm::*;
+use self::encode::*;
+use self::export::*;
+use self::history::*;
+use self::pipeline::*;
+use self::predictor::*;
+use self::profile::*;
+use self::replay::*;
 use self::riscv::*;
+use self::selftest::*;
+use self::staticlint::*;
 use self::ui::*;
 use crossterm::tty::IsTty;
 use std::cmp::min;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::{self, Write as FmtWrite};
 use std::io::{self, Read, Write};
+use std::mem::take;
 use std::rc::Rc;
+use std::time::Instant;
 
 const STACK_SIZE: i64 = 8192;
 const MAX_STEPS_DEFAULT: usize = 100000000;
 
+#[derive(Clone)]
 struct Segment {
+    name: String,
     start: i64,
     end: i64,
     mem: Vec<u8>,
@@ -23,14 +58,14 @@ struct Segment {
 }
 
 impl Segment {
-    fn new(start: i64, end: i64, writeable: bool, executable: bool, init: Vec<u8>) -> Self {
+    fn new(name: &str, start: i64, end: i64, writeable: bool, executable: bool, init: Vec<u8>) -> Self {
         assert!(start > 0 && end > start);
         assert!(init.len() <= (end - start) as usize);
-        Self { start, end, mem: Vec::new(), init, writeable, executable }
+        Self { name: name.to_string(), start, end, mem: Vec::new(), init, writeable, executable }
     }
 
     fn in_range(&self, addr: i64, size: i64) -> bool {
-        addr >= self.start && addr + size <= self.end
+        addr >= self.start && addr <= self.end - size
     }
 
     fn reset(&mut self) {
@@ -63,12 +98,37 @@ impl Segment {
     }
 }
 
+// a function's extent in the text segment. Functions come from STT_FUNC
+// symbols, ending at start + st_size; any other named text label outside of
+// those is also taken as a function (hand-written code rarely marks them with
+// .type and .size) and ends where the next function starts. Labels inside a
+// sized function, like loop or done, belong to it.
+#[derive(Clone)]
+struct Function {
+    start: i64,
+    end: i64,
+    name: String,
+}
+
+// saved register state for each hart; the active hart's state lives in Machine
+#[derive(Clone)]
+struct Hart {
+    x: [i64; 32],
+    pc: i64,
+    stack_start: i64,
+    stack_end: i64,
+    stack_frames: Vec<i64>,
+}
+
+#[derive(Clone)]
 pub struct Machine {
     segments: Vec<Segment>,
     pc_start: i64,
     global_pointer: i64,
     address_symbols: HashMap<i64, String>,
     other_symbols: HashMap<String, i64>,
+    symbol_sizes: HashMap<i64, i64>, // st_size of address symbols that have one
+    functions: Vec<Function>,        // sorted by start address
     stack_start: i64,
     stack_end: i64,
     data_start: i64,
@@ -84,24 +144,52 @@ pub struct Machine {
     most_recent_memory: i64,
     most_recent_data: (i64, usize),  // (address, size)
     most_recent_stack: (i64, usize), // (address, size)
+    hart: usize,
+    harts: Vec<Hart>,
+
+    // when re-executing from a checkpoint, stdin reads come from here
+    input: Option<VecDeque<Vec<u8>>>,
+
+    // set when an instruction changes the pc; lets run_fast skip Effects
+    pc_written: bool,
+
+    // how listings, the TUI, and lint messages show instructions and registers
+    style: DisplayStyle,
 }
 
 impl Machine {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         mut segments: Vec<Segment>,
         pc_start: i64,
         global_pointer: i64,
         address_symbols: HashMap<i64, String>,
         other_symbols: HashMap<String, i64>,
+        symbol_sizes: HashMap<i64, i64>,
+        function_symbols: HashSet<i64>,
+        global_symbols: HashSet<i64>,
+        hart_count: usize,
     ) -> Self {
-        let mut stack_start = 0x100000 - STACK_SIZE;
+        // each hart gets its own stack, with hart 0 at the top
+        let stacks_size = STACK_SIZE * hart_count as i64;
+        let mut stacks_start = 0x100000 - stacks_size;
         for segment in &segments {
-            if segment.end + STACK_SIZE >= stack_start {
-                stack_start = (segment.end + STACK_SIZE * 2 - 1) & (STACK_SIZE - 1);
+            if segment.end + stacks_size >= stacks_start {
+                stacks_start = (segment.end + STACK_SIZE * 2 - 1) & !(STACK_SIZE - 1);
             }
         }
 
-        let stack_end = stack_start + STACK_SIZE;
+        let stack_end = stacks_start + stacks_size;
+        let stack_start = stack_end - STACK_SIZE;
+        let harts = (0..hart_count as i64)
+            .map(|i| Hart {
+                x: [0; 32],
+                pc: pc_start,
+                stack_start: stack_start - i * STACK_SIZE,
+                stack_end: stack_end - i * STACK_SIZE,
+                stack_frames: Vec::new(),
+            })
+            .collect();
         let mut data_start = stack_end - 8;
         let mut data_end = 0;
         let mut text_start = stack_end;
@@ -121,7 +209,7 @@ impl Machine {
             data_start = 0;
         }
 
-        segments.push(Segment::new(stack_start, stack_end, true, false, Vec::new()));
+        segments.push(Segment::new("stack", stacks_start, stack_end, true, false, Vec::new()));
 
         let mut machine = Self {
             segments,
@@ -129,6 +217,8 @@ impl Machine {
             global_pointer,
             address_symbols,
             other_symbols,
+            symbol_sizes,
+            functions: Vec::new(),
             stack_start,
             stack_end,
             data_start,
@@ -144,20 +234,71 @@ impl Machine {
             most_recent_memory: 0,
             most_recent_data: (0, 0),
             most_recent_stack: (0, 0),
+            hart: 0,
+            harts,
+            input: None,
+            pc_written: false,
+            style: DisplayStyle::default(),
         };
 
         machine.reset();
+
+        // the loader calls the entry point
+        let mut called = machine.call_targets();
+        called.insert(pc_start);
+        machine.functions = function_table(
+            &machine.address_symbols,
+            &machine.symbol_sizes,
+            &function_symbols,
+            &global_symbols,
+            &called,
+            text_start,
+            text_end,
+        );
         machine
     }
 
+    // addresses that a jal or an auipc/jalr pair calls
+    fn call_targets(&self) -> HashSet<i64> {
+        let mut targets = HashSet::new();
+        let mut previous: Option<(i64, Op)> = None;
+        let mut pc = self.text_start;
+        while pc < self.text_end {
+            let Ok((inst, length)) = self.load_instruction(pc) else { break };
+            let op = Op::new(inst);
+            if is_call(&op) {
+                match (&op, &previous) {
+                    (Op::Jal { offset, .. }, _) => {
+                        targets.insert(pc + offset);
+                    }
+                    (Op::Jalr { rs1, offset, .. }, Some((address, Op::Auipc { rd, imm }))) if rd == rs1 => {
+                        targets.insert(address + imm + offset);
+                    }
+                    _ => {}
+                }
+            }
+            previous = Some((pc, op));
+            pc += length;
+        }
+        targets
+    }
+
     fn reset(&mut self) {
         for segment in &mut self.segments {
             segment.reset();
         }
 
-        self.x = [0; 32];
-        self.x[2] = self.stack_end;
+        for hart in &mut self.harts {
+            hart.x = [0; 32];
+            hart.x[SP] = hart.stack_end;
+            hart.pc = self.pc_start;
+            hart.stack_frames.clear();
+        }
+        self.x = self.harts[0].x;
         self.pc = self.pc_start;
+        self.stack_start = self.harts[0].stack_start;
+        self.stack_end = self.harts[0].stack_end;
+        self.hart = 0;
 
         self.stdout.clear();
         self.stdin.clear();
@@ -165,7 +306,53 @@ impl Machine {
         self.effects = None;
     }
 
-    fn set_most_recent_memory(&mut self, sequence: &[Effects], seq_i: usize) {
+    fn switch_hart(&mut self, hart: usize) {
+        if hart == self.hart {
+            return;
+        }
+
+        // save the active hart
+        let old = &mut self.harts[self.hart];
+        old.x = self.x;
+        old.pc = self.pc;
+        old.stack_frames = take(&mut self.stack_frames);
+
+        // restore the new one
+        let new = &mut self.harts[hart];
+        self.x = new.x;
+        self.pc = new.pc;
+        self.stack_frames = take(&mut new.stack_frames);
+        self.stack_start = new.stack_start;
+        self.stack_end = new.stack_end;
+        self.hart = hart;
+    }
+
+    // (functions, named data symbols) sorted by address; numbered local
+    // labels and labels inside a function are in neither
+    fn named_symbols(&self) -> (Symbols, Symbols) {
+        let functions = self.functions.iter().map(|f| (f.start, f.name.clone())).collect();
+        let mut objects = Vec::new();
+        for (&address, name) in &self.address_symbols {
+            if name.parse::<usize>().is_err() && !(self.text_start <= address && address < self.text_end) {
+                objects.push((address, name.clone()));
+            }
+        }
+        objects.sort_unstable();
+        (functions, objects)
+    }
+
+    // the function whose code includes an address
+    fn function_at(&self, address: i64) -> Option<&Function> {
+        let i = self.functions.partition_point(|f| f.start <= address);
+        self.functions[..i].last().filter(|f| address < f.end)
+    }
+
+    // the name of a register in the display style
+    fn reg_name(&self, reg: usize) -> &'static str {
+        self.style.registers.name(reg)
+    }
+
+    fn set_most_recent_memory(&mut self, history: &mut History, seq_i: usize) {
         self.most_recent_memory = if self.data_start > 0 { self.data_start } else { self.stack_end - 8 };
         self.most_recent_data = (self.data_start, 0);
         self.most_recent_stack = (self.stack_end - 8, 0);
@@ -173,7 +360,8 @@ impl Machine {
         let mut stack = false;
         let mut data = false;
 
-        for effect in sequence[..=seq_i].iter().rev() {
+        for i in (history.chunk_start(seq_i)..=seq_i).rev() {
+            let effect = history.get(i);
             let (address, value_len) = if let Some(read) = &effect.mem_read {
                 (read.address, read.value.len())
             } else if let Some((_, write)) = &effect.mem_write {
@@ -182,7 +370,7 @@ impl Machine {
                 continue;
             };
 
-            if !stack && address >= self.stack_start {
+            if !stack && self.stack_start <= address && address < self.stack_end {
                 self.most_recent_stack = (address, value_len);
                 if !data {
                     self.most_recent_memory = address;
@@ -214,6 +402,21 @@ impl Machine {
         Err(format!("segfault: load addr=0x{:x} size={}", addr, size))
     }
 
+    // load without recording a memory read in the effects
+    fn peek(&self, addr: i64, size: i64) -> Result<Vec<u8>, String> {
+        for segment in &self.segments {
+            if segment.in_range(addr, size) {
+                return Ok(segment.load(addr, size, &mut None).to_vec());
+            }
+        }
+        Err(format!("segfault: load addr=0x{:x} size={}", addr, size))
+    }
+
+    fn peek_i64(&self, addr: i64) -> Result<i64, String> {
+        let bytes = self.peek(addr, 8)?;
+        Ok(i64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
     fn load_i8(&mut self, addr: i64) -> Result<i64, String> {
         let bytes = self.load(addr, 1)?;
         Ok(i8::from_le_bytes(bytes[..1].try_into().unwrap()) as i64)
@@ -273,6 +476,12 @@ impl Machine {
         Err(format!("segfault: instruction fetch addr=0x{:x}", addr))
     }
 
+    fn is_semihosting_call(&self) -> bool {
+        // the ebreak at pc must be bracketed by the semihosting marker instructions
+        self.load_instruction(self.pc - 4) == Ok((SEMIHOSTING_ENTRY, 4))
+            && self.load_instruction(self.pc + 4) == Ok((SEMIHOSTING_EXIT, 4))
+    }
+
     fn store(&mut self, addr: i64, raw: &[u8]) -> Result<(), String> {
         let size = raw.len() as i64;
         for segment in &mut self.segments {
@@ -285,11 +494,11 @@ impl Machine {
     }
 
     fn get(&mut self, reg: usize) -> i64 {
-        if reg != 0 && self.effects.is_some() {
-            let effects = self.effects.as_mut().unwrap();
-            if !effects.reg_reads.iter().any(|r| r.register == reg) {
-                effects.reg_reads.push(RegisterValue { register: reg, value: self.x[reg] });
-            }
+        if reg != 0
+            && let Some(effects) = &mut self.effects
+            && !effects.reg_reads.iter().any(|r| r.register == reg)
+        {
+            effects.reg_reads.push(RegisterValue { register: reg, value: self.x[reg] });
         }
         self.x[reg]
     }
@@ -317,6 +526,7 @@ impl Machine {
     fn set_pc(&mut self, value: i64) -> Result<(), String> {
         let old_pc = self.pc;
         self.pc = value;
+        self.pc_written = true;
         if self.pc & 1 != 0 {
             return Err(format!("bus error: pc addr={}", self.pc));
         }
@@ -328,7 +538,7 @@ impl Machine {
 
     fn execute_and_collect_effects(&mut self, instruction: &Rc<Instruction>) -> Effects {
         // trace the effects
-        self.effects = Some(Effects::new(instruction));
+        self.effects = Some(Effects::new(instruction, self.hart));
 
         // execute the instruction
         let exec_res = instruction.op.execute(self, instruction.length);
@@ -351,6 +561,8 @@ impl Machine {
     }
 
     fn apply(&mut self, effect: &Effects, is_forward: bool) {
+        self.switch_hart(effect.hart);
+
         let (old_pc, new_pc) = effect.pc;
         self.set_pc(if is_forward { new_pc } else { old_pc }).expect("PC should be valid during replay");
 
@@ -410,18 +622,22 @@ pub struct Instruction {
     pseudo_fields: Vec<Field>,
 }
 
+#[derive(Clone)]
 struct MemoryValue {
     address: i64,
     value: Vec<u8>,
 }
 
+#[derive(Clone)]
 struct RegisterValue {
     register: usize,
     value: i64,
 }
 
+#[derive(Clone)]
 pub struct Effects {
     instruction: Rc<Instruction>,
+    hart: usize,
 
     // pairs are (old_value, new_value)
     pc: (i64, i64),
@@ -438,9 +654,10 @@ pub struct Effects {
 }
 
 impl Effects {
-    fn new(instruction: &Rc<Instruction>) -> Self {
+    fn new(instruction: &Rc<Instruction>, hart: usize) -> Self {
         Effects {
             instruction: instruction.clone(),
+            hart,
             pc: (0, 0),
             reg_reads: Vec::new(),
             reg_write: None,
@@ -460,13 +677,13 @@ impl Effects {
         self.terminate = true;
     }
 
-    fn report(&self, hex_mode: bool) -> Vec<String> {
+    fn report(&self, hex_mode: bool, names: RegisterNames) -> Vec<String> {
         let mut parts = Vec::new();
         if let Some((_, RegisterValue { register: rd, value: val })) = self.reg_write {
             if hex_mode {
-                parts.push(format!("{} <- 0x{:x}", R[rd], val));
+                parts.push(format!("{} <- 0x{:x}", names.name(rd), val));
             } else {
-                parts.push(format!("{} <- {}", R[rd], val));
+                parts.push(format!("{} <- {}", names.name(rd), val));
             }
         }
         if self.pc.1 != self.pc.0 + self.instruction.length {
@@ -495,7 +712,33 @@ impl Effects {
     }
 }
 
-fn load_elf(filename: &str) -> Result<Machine, String> {
+// code without an ELF file around it (hex words or a raw binary) as a single
+// text segment at the base address, with no symbols
+fn load_raw(bytes: Vec<u8>, base: i64) -> Result<Machine, String> {
+    if bytes.is_empty() {
+        return Err("no instructions to disassemble".to_string());
+    }
+    if base <= 0 || base & 1 != 0 {
+        return Err(format!("base address 0x{:x} is not a positive multiple of 2", base));
+    }
+    let Some(end) = base.checked_add(bytes.len() as i64) else {
+        return Err(format!("{} bytes at base address 0x{:x} run past the end of memory", bytes.len(), base));
+    };
+    let segments = vec![Segment::new(".text", base, end, false, true, bytes)];
+    Ok(Machine::new(
+        segments,
+        base,
+        0,
+        HashMap::new(),
+        HashMap::new(),
+        HashMap::new(),
+        HashSet::new(),
+        HashSet::new(),
+        1,
+    ))
+}
+
+fn load_elf(filename: &str, hart_count: usize) -> Result<Machine, String> {
     let raw = std::fs::read(filename).map_err(|e| format!("loading {}: {}", filename, e))?;
 
     // unpack the elf header
@@ -643,6 +886,7 @@ fn load_elf(filename: &str) -> Result<Machine, String> {
                 }
             }
             segments.push(Segment::new(
+                sh_strs.get(&sh_name).map_or("", |name| name.as_str()),
                 sh_addr,
                 sh_addr + sh_size as i64,
                 (sh_flags & 0x1) != 0,
@@ -673,8 +917,13 @@ fn load_elf(filename: &str) -> Result<Machine, String> {
     // parse the symbol table
     let mut address_symbols = HashMap::new();
     let mut other_symbols = HashMap::new();
+    let mut symbol_sizes = HashMap::new();
+    let mut function_symbols = HashSet::new();
+    let mut global_symbols = HashSet::new();
     let mut global_pointer = 0;
     const SYMBOL_SIZE: usize = 24;
+    const STT_FUNC: u8 = 2;
+    const STB_GLOBAL: u8 = 1;
 
     for start in (0..syms_raw.len()).step_by(SYMBOL_SIZE) {
         if start + SYMBOL_SIZE > syms_raw.len() {
@@ -686,7 +935,7 @@ fn load_elf(filename: &str) -> Result<Machine, String> {
         //let st_other = symbol[0x05];
         let st_shndx = u16::from_le_bytes(symbol[0x06..0x08].try_into().unwrap());
         let st_value = i64::from_le_bytes(symbol[0x08..0x10].try_into().unwrap());
-        //let st_size = u64::from_le_bytes(symbol[0x10..0x18].try_into().unwrap());
+        let st_size = i64::from_le_bytes(symbol[0x10..0x18].try_into().unwrap());
 
         // find the name
         let mut end = st_name;
@@ -714,13 +963,72 @@ fn load_elf(filename: &str) -> Result<Machine, String> {
         // sort into text, data/bss, and other symbols
         if st_shndx > 0 {
             address_symbols.insert(st_value, name);
+            if st_size > 0 {
+                symbol_sizes.insert(st_value, st_size);
+            }
+            if st_info & 0xf == STT_FUNC {
+                function_symbols.insert(st_value);
+            }
+            if st_info >> 4 == STB_GLOBAL {
+                global_symbols.insert(st_value);
+            }
         } else {
             other_symbols.insert(name, st_value);
         }
     }
 
     // allocate address space
-    Ok(Machine::new(segments, e_entry, global_pointer, address_symbols, other_symbols))
+    Ok(Machine::new(
+        segments,
+        e_entry,
+        global_pointer,
+        address_symbols,
+        other_symbols,
+        symbol_sizes,
+        function_symbols,
+        global_symbols,
+        hart_count,
+    ))
+}
+
+// functions are STT_FUNC symbols, plus labels outside them that are .globl
+// or called; any other label is part of the function it falls in
+#[allow(clippy::too_many_arguments)]
+fn function_table(
+    address_symbols: &HashMap<i64, String>,
+    symbol_sizes: &HashMap<i64, i64>,
+    function_symbols: &HashSet<i64>,
+    global_symbols: &HashSet<i64>,
+    called: &HashSet<i64>,
+    text_start: i64,
+    text_end: i64,
+) -> Vec<Function> {
+    let sized: Vec<(i64, i64)> =
+        function_symbols.iter().filter_map(|start| Some((*start, start + symbol_sizes.get(start)?))).collect();
+    let mut starts: Vec<(i64, &String)> = address_symbols
+        .iter()
+        .filter(|&(&address, name)| {
+            text_start <= address
+                && address < text_end
+                && name.parse::<usize>().is_err()
+                && (function_symbols.contains(&address)
+                    || ((global_symbols.contains(&address) || called.contains(&address))
+                        && !sized.iter().any(|&(start, end)| start < address && address < end)))
+        })
+        .map(|(&address, name)| (address, name))
+        .collect();
+    starts.sort_unstable();
+
+    let mut functions = Vec::new();
+    for (i, &(start, name)) in starts.iter().enumerate() {
+        let next = starts.get(i + 1).map_or(text_end, |&(address, _)| address);
+        let end = match symbol_sizes.get(&start) {
+            Some(size) if function_symbols.contains(&start) => start + size,
+            _ => next,
+        };
+        functions.push(Function { start, end, name: name.clone() });
+    }
+    functions
 }
 
 fn add_local_labels(m: &mut Machine, instructions: &[Instruction]) {
@@ -746,81 +1054,264 @@ fn add_local_labels(m: &mut Machine, instructions: &[Instruction]) {
     }
 }
 
+// (address, name) pairs sorted by address
+type Symbols = Vec<(i64, String)>;
+
+// the name of the closest symbol at or below the address
+fn nearest_symbol(symbols: &[(i64, String)], address: i64) -> Option<&str> {
+    let i = symbols.partition_point(|(start, _)| *start <= address);
+    if i == 0 { None } else { Some(&symbols[i - 1].1) }
+}
+
+// a model or statistic computed from the stream of Effects as the program runs
+pub trait Analysis {
+    fn step(&mut self, effects: &Effects);
+    fn report(&self, m: &Machine) -> String;
+
+    // write any files the analysis produces
+    fn save(&self, _m: &Machine) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// executes one step at a time: picks the hart, finds its next instruction,
+// and lints the result. Cloneable so checkpoints can resume tracing later.
+#[derive(Clone)]
+struct Tracer {
+    lint: bool,
+    linters: Vec<Linter>,
+    frames: Vec<usize>, // per hart: stack frames marked without the linter
+    scheduler: Scheduler,
+    next_i: Vec<usize>,
+}
+
+impl Tracer {
+    fn new(m: &Machine, lint: bool, scheduler: Scheduler) -> Self {
+        let linters = m.harts.iter().map(|hart| Linter::new(hart.stack_end)).collect();
+        Tracer { lint, linters, frames: vec![0; m.harts.len()], scheduler, next_i: vec![0; m.harts.len()] }
+    }
+
+    // returns None if there is no instruction at the pc
+    fn step(
+        &mut self,
+        m: &mut Machine,
+        instructions: &[Rc<Instruction>],
+        addresses: &HashMap<i64, usize>,
+    ) -> Option<(Rc<Instruction>, Effects)> {
+        m.switch_hart(self.scheduler.next_hart());
+        let mut i = self.next_i[m.hart];
+        if i >= instructions.len() || instructions[i].address != m.pc {
+            i = *addresses.get(&m.pc)?;
+        }
+
+        let instruction = instructions[i].clone();
+        let effects = m.execute_and_collect_effects(&instruction);
+        self.next_i[m.hart] = i + 1;
+        Some((instruction, effects))
+    }
+
+    fn check(&mut self, m: &Machine, instruction: &Rc<Instruction>, effects: &mut Effects) {
+        if effects.terminate {
+            return;
+        }
+        if !self.lint {
+            self.mark_frames(m, instruction, effects);
+        } else if let Err(msg) = self.linters[m.hart].check_instruction(m, instruction, effects) {
+            effects.error(msg);
+        }
+    }
+
+    // without the linter, calls to the start of a function and the returns
+    // that match them still mark stack frames for the tui
+    fn mark_frames(&mut self, m: &Machine, instruction: &Rc<Instruction>, effects: &mut Effects) {
+        let (_, target_pc) = effects.pc;
+        match instruction.op {
+            Op::Jal { rd: 1..32, .. } | Op::Jalr { rd: 1..32, .. }
+                if m.function_at(target_pc).is_some_and(|function| function.start == target_pc) =>
+            {
+                effects.function_start = Some(m.x[SP]);
+                self.frames[m.hart] += 1;
+            }
+            Op::Jalr { rd: ZERO, rs1: RA, offset: 0 } if self.frames[m.hart] > 0 => {
+                effects.function_end = Some(m.x[SP]);
+                self.frames[m.hart] -= 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn trace(
     m: &mut Machine,
     instructions: &[Rc<Instruction>],
     addresses: &HashMap<i64, usize>,
-    lint: bool,
     max_steps: usize,
     mode: &str,
-) -> Vec<Effects> {
-    let mut linter = Linter::new(m.x[2]);
-    let mut sequence: Vec<Effects> = Vec::new();
-    let mut i = 0;
+    keep_sequence: bool,
+    checkpoint_steps: usize,
+    mut tracer: Tracer,
+    analyses: &mut [Box<dyn Analysis>],
+) -> History {
+    let mut history = History::new(m, instructions, addresses, checkpoint_steps);
     let echo_in = ["run", "debug"].contains(&mode) && !io::stdin().is_tty();
 
     for steps in 1..=max_steps {
-        if i >= instructions.len() || instructions[i].address != m.pc {
-            let Some(&new_i) = addresses.get(&m.pc) else {
-                if let Some(effects) = sequence.last_mut() {
-                    effects.error("next instruction not found".to_string());
-                }
-                break;
-            };
-            i = new_i;
-        }
-
-        // execute the instruction
-        let instruction = &instructions[i];
-        let mut effects = m.execute_and_collect_effects(instruction);
-        i += 1;
+        history.checkpoint(m, &tracer);
+        let Some((instruction, mut effects)) = tracer.step(m, instructions, addresses) else {
+            if let Some(effects) = history.last_mut() {
+                effects.error("next instruction not found".to_string());
+            }
+            break;
+        };
 
         // echo the output?
-        if !effects.terminate && ["run", "debug"].contains(&mode) {
-            if let Some(output) = &effects.stdout {
-                let mut handle = io::stdout().lock();
-                if let Err(e) = handle.write(output) {
-                    effects.error(format!("error echoing stdout: {}", e));
-                }
+        if !effects.terminate
+            && ["run", "debug"].contains(&mode)
+            && let Some(output) = &effects.stdout
+        {
+            let mut handle = io::stdout().lock();
+            if let Err(e) = handle.write(output) {
+                effects.error(format!("error echoing stdout: {}", e));
             }
         }
 
         // echo the input?
-        if !effects.terminate && echo_in {
-            if let Some(input) = &effects.stdin {
-                let mut handle = io::stdout().lock();
-                if let Err(e) = handle.write(input) {
-                    effects.error(format!("error echoing stdin: {}", e));
-                }
+        if !effects.terminate
+            && echo_in
+            && let Some(input) = &effects.stdin
+        {
+            let mut handle = io::stdout().lock();
+            if let Err(e) = handle.write(input) {
+                effects.error(format!("error echoing stdin: {}", e));
             }
         }
 
-        if !effects.terminate && lint {
-            if let Err(msg) = linter.check_instruction(m, instruction, &mut effects) {
-                effects.error(msg);
-            }
+        tracer.check(m, &instruction, &mut effects);
+        for analysis in analyses.iter_mut() {
+            analysis.step(&effects);
         }
 
         let terminate = effects.terminate;
-        sequence.push(effects);
+        history.push(effects, keep_sequence);
         if terminate {
             break;
         }
 
-        if steps == max_steps {
-            if let Some(last) = sequence.last_mut() {
-                if last.other_message.is_none() {
-                    last.error(format!("stopped after {} steps", max_steps));
-                }
+        if steps == max_steps
+            && let Some(last) = history.last_mut()
+            && last.other_message.is_none()
+        {
+            last.error(format!("stopped after {} steps", max_steps));
+        }
+    }
+
+    history
+}
+
+// run mode without linting or recording: execute straight from a table of
+// decoded instructions indexed by pc and skip Effects except for the step
+// that ends the run, which main inspects for the exit status
+fn run_fast(m: &mut Machine, instructions: &[Rc<Instruction>], max_steps: usize, mut scheduler: Scheduler) -> History {
+    let mut by_pc = vec![usize::MAX; ((m.text_end - m.text_start) / 2) as usize];
+    for (i, instruction) in instructions.iter().enumerate() {
+        by_pc[((instruction.address - m.text_start) / 2) as usize] = i;
+    }
+    let mut history = History::new(m, instructions, &HashMap::new(), 0);
+    let echo_in = !io::stdin().is_tty();
+    let mut prev: Option<(usize, usize)> = None; // (instruction, hart) of the previous step
+
+    for steps in 1..=max_steps {
+        m.switch_hart(scheduler.next_hart());
+        let pc = m.pc;
+        let i =
+            if pc >= m.text_start && pc < m.text_end { by_pc[((pc - m.text_start) / 2) as usize] } else { usize::MAX };
+        if i == usize::MAX {
+            if let Some((prev_i, hart)) = prev {
+                history.skip(steps - 2);
+                let mut effects = Effects::new(&instructions[prev_i], hart);
+                effects.error("next instruction not found".to_string());
+                history.push(effects, false);
+            }
+            return history;
+        }
+
+        let instruction = &instructions[i];
+        m.pc_written = false;
+        let mut result = instruction.op.execute(m, instruction.length);
+        if !m.pc_written {
+            m.pc = pc + instruction.length;
+        }
+
+        // echo output and input as they happen
+        if result.is_ok() && !m.stdout.is_empty() {
+            if let Err(e) = io::stdout().lock().write(&m.stdout) {
+                result = Err(format!("error echoing stdout: {}", e));
             }
-        } else if mode != "debug" {
-            sequence.clear();
+            m.stdout.clear();
         }
+        if result.is_ok() && !m.stdin.is_empty() {
+            if echo_in && let Err(e) = io::stdout().lock().write(&m.stdin) {
+                result = Err(format!("error echoing stdin: {}", e));
+            }
+            m.stdin.clear();
+        }
+
+        if result.is_ok() && steps == max_steps {
+            result = Err(format!("stopped after {} steps", max_steps));
+        }
+        if let Err(msg) = result {
+            let mut effects = Effects::new(instruction, m.hart);
+            effects.pc = (pc, m.pc);
+            effects.error(msg);
+            history.skip(steps - 1);
+            history.push(effects, false);
+            return history;
+        }
+        prev = Some((i, m.hart));
+    }
+
+    history
+}
+
+// decides which hart runs at each step
+#[derive(Clone)]
+struct Scheduler {
+    hart_count: usize,
+    quantum: usize,
+    rng: Option<u64>,
+    hart: usize,
+    remaining: usize,
+}
+
+impl Scheduler {
+    fn new(hart_count: usize, quantum: usize, seed: Option<u64>) -> Self {
+        // xorshift state must be non-zero
+        let rng = seed.map(|seed| seed.wrapping_mul(0x9e3779b97f4a7c15) | 1);
+        Self { hart_count, quantum, rng, hart: hart_count - 1, remaining: 0 }
     }
 
-    sequence
+    fn next_hart(&mut self) -> usize {
+        if self.remaining == 0 {
+            if let Some(state) = &mut self.rng {
+                // seeded: run a random hart for a random quantum of 1..=quantum steps
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+                self.hart = (*state % self.hart_count as u64) as usize;
+                self.remaining = 1 + ((*state >> 32) % self.quantum as u64) as usize;
+            } else {
+                // round robin with a fixed quantum
+                self.hart = (self.hart + 1) % self.hart_count;
+                self.remaining = self.quantum;
+            }
+        }
+        self.remaining -= 1;
+        self.hart
+    }
 }
 
+#[derive(Clone)]
 struct FunctionRegisters {
     at_entry: [Option<usize>; 32],
     valid: [bool; 32],
@@ -828,13 +1319,15 @@ struct FunctionRegisters {
     at_entry_sp: i64,
 }
 
+#[derive(Clone)]
 struct ValueInMemory {
     n: usize,
     size: usize,
 }
 
+#[derive(Clone)]
 struct Linter {
-    memory: HashMap<i64, ValueInMemory>,
+    memory: LintMemory,
 
     stack: Vec<FunctionRegisters>,
     at_entry: [Option<usize>; 32],
@@ -859,7 +1352,7 @@ impl Linter {
         let registers = at_entry;
 
         Self {
-            memory: HashMap::new(),
+            memory: LintMemory::default(),
             stack: Vec::new(),
             at_entry,
             at_entry_sp,
@@ -888,7 +1381,7 @@ impl Linter {
         for read in &effects.reg_reads {
             let x = read.register;
             if !self.valid[x] || self.registers[x].is_none() {
-                return Err(format!("{} is uninitialized", R[x]));
+                return Err(format!("{} is uninitialized", m.reg_name(x)));
             }
 
             // save-only values can be moved to other registers
@@ -902,7 +1395,7 @@ impl Linter {
                     _ => {
                         return Err(format!(
                             "the value in {} can only be saved to memory; it is not a valid input",
-                            R[x]
+                            m.reg_name(x)
                         ));
                     }
                 }
@@ -932,20 +1425,21 @@ impl Linter {
         // special per-instruction cases
         match instruction.op {
             // function call
-            Op::Jal { rd: 1..32, .. } | Op::Jalr { rd: 1..32, .. } => {
-                let op_name = if matches!(instruction.op, Op::Jal { .. }) { "jal" } else { "jalr" };
+            ref op if is_call(op) => {
+                let op_name = if matches!(op, Op::Jal { .. }) { "jal" } else { "jalr" };
 
                 // must use ra for return address
-                let Some((_, RegisterValue { register: RA, .. })) = effects.reg_write else {
+                if link_register(op) != Some(RA) {
                     return Err(format!("{} did not use ra for return address", op_name));
-                };
+                }
 
-                // must call named function
+                // must call the start of a function
                 let (_, target_pc) = effects.pc;
-                if !m.address_symbols.contains_key(&target_pc) {
-                    return Err(format!("{} to unlabeled address", op_name));
-                }
-                let name = &m.address_symbols[&target_pc];
+                let name = match m.function_at(target_pc) {
+                    Some(function) if function.start == target_pc => &function.name,
+                    Some(function) => return Err(format!("{} into the middle of {}", op_name, function.name)),
+                    None => return Err(format!("{} to unlabeled address", op_name)),
+                };
 
                 // push caller register context
                 self.stack.push(FunctionRegisters {
@@ -977,7 +1471,7 @@ impl Linter {
                     // make sure func args are all valid values
                     for &x in A_REGS.iter().take(arg_count) {
                         if !self.valid[x] {
-                            return Err(format!("argument in {} is uninitialized", R[x]));
+                            return Err(format!("argument in {} is uninitialized", m.reg_name(x)));
                         }
                     }
                     for &x in A_REGS.iter().skip(arg_count) {
@@ -1010,14 +1504,14 @@ impl Linter {
                 // ra, gp, and tp must match what they were at call time
                 for x in [1, 3, 4] {
                     if self.registers[x] != self.at_entry[x] {
-                        return Err(format!("{} is not same value as when function called", R[x]));
+                        return Err(format!("{} is not same value as when function called", m.reg_name(x)));
                     }
                 }
 
                 // s registers must be same as at call time
                 for &x in &S_REGS {
                     if self.registers[x] != self.at_entry[x] {
-                        return Err(format!("{} is not same value as when function called", R[x]));
+                        return Err(format!("{} is not same value as when function called", m.reg_name(x)));
                     }
                 }
 
@@ -1145,8 +1639,8 @@ impl Linter {
                 self.registers[rd] = Some(n);
             }
 
-            // reads and writes
-            Op::Ecall => {
+            // reads and writes (system calls and semihosting calls)
+            Op::Ecall | Op::Ebreak => {
                 // write syscall
                 if let Some(read) = &effects.mem_read {
                     let addr = read.address;
@@ -1154,10 +1648,10 @@ impl Linter {
 
                     // only allow byte values from memory
                     for address in addr..addr + size as i64 {
-                        if let Some(val) = self.memory.get(&address) {
-                            if val.size != 1 {
-                                return Err("write syscall on non-byte data".to_string());
-                            }
+                        if let Some(val) = self.memory.get(&address)
+                            && val.size != 1
+                        {
+                            return Err("write syscall on non-byte data".to_string());
                         }
                     }
                 }
@@ -1169,10 +1663,10 @@ impl Linter {
 
                     for address in addr..addr + size as i64 {
                         // do not allow overwrite of non-byte data
-                        if let Some(val) = self.memory.get(&address) {
-                            if val.size != 1 {
-                                return Err("read syscall overwriting non-byte data".to_string());
-                            }
+                        if let Some(val) = self.memory.get(&address)
+                            && val.size != 1
+                        {
+                            return Err("read syscall overwriting non-byte data".to_string());
                         }
 
                         // record data as individual bytes
@@ -1192,6 +1686,22 @@ impl Linter {
 fn main() -> Result<(), String> {
     let args: Vec<String> = std::env::args().collect();
 
+    // risclet encode <instruction>
+    if args.get(1).is_some_and(|arg| arg == "encode") {
+        if args.len() < 3 {
+            return Err("encode needs an instruction, e.g., risclet encode addi a0, a0, 1".to_string());
+        }
+        return encode_command(&args[2..].join(" "));
+    }
+
+    // risclet decode <hex words> is short for risclet -m dasm --hex <hex words>
+    let args = if args.get(1).is_some_and(|arg| arg == "decode") {
+        let words = if args.len() > 2 { args[2..].join(" ") } else { "-".to_string() };
+        vec![args[0].clone(), "-m".to_string(), "dasm".to_string(), "--hex".to_string(), words]
+    } else {
+        args
+    };
+
     let mut mode = String::from("debug");
     let mut executable = String::from("a.out");
     let mut lint = String::from("true");
@@ -1199,13 +1709,38 @@ fn main() -> Result<(), String> {
     let mut usage = false;
     let mut i = 1;
     let mut max_steps = MAX_STEPS_DEFAULT;
+    let mut hart_count = 1;
+    let mut quantum = 1;
+    let mut seed = None;
+    let mut record_file = None;
+    let mut replay_file = None;
+    let mut checkpoint_steps = 0;
+    let mut bench = false;
+    let mut pipeline = false;
+    let mut icache = None;
+    let mut dcache = None;
+    let mut predictor = None;
+    let mut profile = false;
+    let mut coverage = false;
+    let mut lcov_file = None;
+    let mut cfg_file = None;
+    let mut trace_file = None;
+    let mut commit_log_file = None;
+    let mut check_log_file = None;
+    let mut selftest_cases = None;
+    let mut trace_format = TraceFormat::Jsonl;
+    let mut dasm_format = DasmFormat::Plain;
+    let mut style = DisplayStyle::default();
+    let mut hex_words = None;
+    let mut raw_file = None;
+    let mut base = None;
     while i < args.len() {
         match args[i].as_str() {
             "-m" | "--mode" => {
                 i += 1;
                 if i < args.len() {
                     mode = args[i].clone();
-                    if !["run", "dasm", "debug"].contains(&mode.as_str()) {
+                    if !["run", "dasm", "debug", "cfg", "callgraph", "lint"].contains(&mode.as_str()) {
                         eprintln!("invalid mode");
                         usage = true;
                     }
@@ -1250,6 +1785,260 @@ fn main() -> Result<(), String> {
                     usage = true;
                 }
             }
+            "-H" | "--harts" => {
+                i += 1;
+                if i < args.len() {
+                    match args[i].parse::<usize>() {
+                        Ok(count) if count > 0 => hart_count = count,
+                        _ => {
+                            eprintln!("{} with invalid number of harts {}", args[i - 1], args[i]);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "-q" | "--quantum" => {
+                i += 1;
+                if i < args.len() {
+                    match args[i].parse::<usize>() {
+                        Ok(steps) if steps > 0 => quantum = steps,
+                        _ => {
+                            eprintln!("{} with invalid number of steps {}", args[i - 1], args[i]);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "-r" | "--random" => {
+                i += 1;
+                if i < args.len() {
+                    if let Ok(n) = args[i].parse::<u64>() {
+                        seed = Some(n);
+                    } else {
+                        eprintln!("{} with invalid seed {}", args[i - 1], args[i]);
+                        usage = true;
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--record" => {
+                i += 1;
+                if i < args.len() {
+                    record_file = Some(args[i].clone());
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--replay" => {
+                i += 1;
+                if i < args.len() {
+                    replay_file = Some(args[i].clone());
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "-c" | "--checkpoint" => {
+                i += 1;
+                if i < args.len() {
+                    match args[i].parse::<usize>() {
+                        Ok(steps) if steps > 0 => checkpoint_steps = steps,
+                        _ => {
+                            eprintln!("{} with invalid number of steps {}", args[i - 1], args[i]);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--bench" => bench = true,
+            "--pipeline" => pipeline = true,
+            "--icache" | "--dcache" => {
+                i += 1;
+                if i < args.len() {
+                    match CacheConfig::parse(&args[i]) {
+                        Ok(config) if args[i - 1] == "--icache" => icache = Some(Cache::new(config)),
+                        Ok(config) => dcache = Some(Cache::new(config)),
+                        Err(msg) => {
+                            eprintln!("{}: {}", args[i - 1], msg);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--predictor" => {
+                i += 1;
+                if i < args.len() {
+                    match Predictor::parse(&args[i]) {
+                        Ok(p) => predictor = Some(p),
+                        Err(msg) => {
+                            eprintln!("{}: {}", args[i - 1], msg);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--profile" => profile = true,
+            "--coverage" => coverage = true,
+            "--lcov" => {
+                i += 1;
+                if i < args.len() {
+                    lcov_file = Some(args[i].clone());
+                    coverage = true;
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--cfg" => {
+                i += 1;
+                if i < args.len() {
+                    cfg_file = Some(args[i].clone());
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--trace-out" => {
+                i += 1;
+                if i < args.len() {
+                    trace_file = Some(args[i].clone());
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--commit-log" | "--check-commit-log" => {
+                i += 1;
+                if i < args.len() {
+                    if args[i - 1] == "--commit-log" {
+                        commit_log_file = Some(args[i].clone());
+                    } else {
+                        check_log_file = Some(args[i].clone());
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--trace-format" => {
+                i += 1;
+                if i < args.len() {
+                    match TraceFormat::parse(&args[i]) {
+                        Ok(format) => trace_format = format,
+                        Err(msg) => {
+                            eprintln!("{}: {}", args[i - 1], msg);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--dasm-format" => {
+                i += 1;
+                if i < args.len() {
+                    match DasmFormat::parse(&args[i]) {
+                        Ok(format) => dasm_format = format,
+                        Err(msg) => {
+                            eprintln!("{}: {}", args[i - 1], msg);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--selftest" => {
+                i += 1;
+                if i < args.len() {
+                    let (cases, seed) = match args[i].split_once(':') {
+                        Some((cases, seed)) => (cases, Some(seed)),
+                        None => (args[i].as_str(), None),
+                    };
+                    match (cases.parse::<usize>(), seed.map(str::parse::<u64>).transpose()) {
+                        (Ok(n), Ok(seed)) => selftest_cases = Some((n, seed)),
+                        (Err(_), _) => {
+                            eprintln!("{} with invalid number of cases {}", args[i - 1], cases);
+                            usage = true;
+                        }
+                        (_, Err(_)) => {
+                            eprintln!("{} with invalid seed {}", args[i - 1], seed.unwrap());
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--hex" => {
+                i += 1;
+                if i < args.len() {
+                    hex_words = Some(args[i].clone());
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--raw" => {
+                i += 1;
+                if i < args.len() {
+                    raw_file = Some(args[i].clone());
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--base" => {
+                i += 1;
+                if i < args.len() {
+                    match parse_number(&args[i]) {
+                        Ok(address) => base = Some(address),
+                        Err(msg) => {
+                            eprintln!("{}: {}", args[i - 1], msg);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
+            "--style" => {
+                i += 1;
+                if i < args.len() {
+                    match DisplayStyle::parse(&args[i]) {
+                        Ok(parsed) => style = parsed,
+                        Err(msg) => {
+                            eprintln!("--style: {}", msg);
+                            usage = true;
+                        }
+                    }
+                } else {
+                    eprintln!("missing argument for {}", args[i - 1]);
+                    usage = true;
+                }
+            }
             "-h" | "--help" => usage = true,
             _ => usage = true,
         }
@@ -1257,25 +2046,126 @@ fn main() -> Result<(), String> {
     }
     if usage {
         eprintln!("Usage: risclet [options]");
+        eprintln!("       risclet encode <instruction>   Show the machine code and bit fields of an instruction");
+        eprintln!("       risclet decode [hex words]     Disassemble hex words (or ones read from stdin)");
         eprintln!();
         eprintln!("Options:");
         eprintln!("  -e, --executable <path>            Path of executable to run (default a.out)");
         eprintln!("  -l, --lint <true|false>            Apply strict ABI and other checks (default true)");
-        eprintln!("  -m, --mode <run|dasm|debug>        Simulator Mode (default debug)");
+        eprintln!("  -m, --mode <mode>                  Simulator Mode: run, dasm, debug, cfg (prints a DOT graph),");
+        eprintln!("                                     callgraph (functions and who calls whom), or lint (checks");
+        eprintln!("                                     every path without running) (default debug)");
+        eprintln!("      --dasm-format <format>         Listing for dasm mode: plain, objdump, or asm (a .s file");
+        eprintln!("                                     that reassembles to the same program) (default plain)");
+        eprintln!("      --hex <words|->                Disassemble hex words (- reads them from stdin) in dasm mode");
+        eprintln!("      --raw <path>                   Disassemble a raw binary file instead of an ELF executable");
+        eprintln!("      --base <address>               Address of the --hex or --raw code (default 0x10000)");
+        eprintln!("      --style <settings>             Register names and pseudo-instructions in listings, e.g.,");
+        eprintln!("                                     regs=numeric (x10), regs=fp (fp for s0), pseudo=false");
         eprintln!("  -s, --steps <count>                Maximum steps to run (default {})", MAX_STEPS_DEFAULT);
+        eprintln!("  -H, --harts <count>                Number of harts sharing memory (default 1)");
+        eprintln!("  -q, --quantum <steps>              Steps each hart runs before switching (default 1)");
+        eprintln!("  -r, --random <seed>                Switch harts randomly with quanta up to --quantum");
+        eprintln!("      --record <path>                Save the executed session to a replay file");
+        eprintln!("      --replay <path>                Debug a recorded session without running it again");
+        eprintln!("  -c, --checkpoint <steps>           Snapshot every <steps> steps instead of keeping them all");
+        eprintln!("      --bench                        Report instructions per second (run mode)");
+        eprintln!("      --pipeline                     Report cycles and stalls for a 5-stage pipeline");
+        eprintln!("      --icache <settings>            Simulate an instruction cache, e.g., size=4k,ways=2,line=32");
+        eprintln!("      --dcache <settings>            Simulate a data cache, also with replace=lru|fifo|random");
+        eprintln!("                                     and write=back|through");
+        eprintln!("      --predictor <name[:bits]>      Evaluate a branch predictor: static, btfn, 1bit, 2bit,");
+        eprintln!("                                     or gshare, with 2^bits table entries (default 10)");
+        eprintln!("      --profile                      Report instruction counts and calls by function");
+        eprintln!("      --coverage                     Report execution counts on the dasm listing");
+        eprintln!("      --lcov <path>                  Also save coverage as an lcov tracefile");
+        eprintln!("      --cfg <path>                   Save the control-flow graph with execution counts as DOT");
+        eprintln!("      --trace-out <path>             Save every step of the trace for other tools");
+        eprintln!("      --trace-format <jsonl|csv>     Format for --trace-out (default jsonl)");
+        eprintln!("      --commit-log <path>            Save a commit log in the format of spike --log-commits");
+        eprintln!("      --check-commit-log <path>      Report where the run diverges from a spike commit log");
+        eprintln!("      --selftest <cases[:seed]>      Check the decoder and executor against a reference model");
+        eprintln!("                                     on random instructions");
         eprintln!("  -h, --help                         Show this help");
         std::process::exit(1);
     }
 
+    if let Some((cases, seed)) = selftest_cases {
+        return selftest(cases, seed);
+    }
+
+    if bench && mode != "run" {
+        return Err("--bench only works in run mode".to_string());
+    }
+    if dasm_format != DasmFormat::Plain && mode != "dasm" {
+        return Err("--dasm-format only works in dasm mode".to_string());
+    }
+    if (hex_words.is_some() || raw_file.is_some()) && mode != "dasm" {
+        return Err("--hex and --raw only work in dasm mode".to_string());
+    }
+    if base.is_some() && hex_words.is_none() && raw_file.is_none() {
+        return Err("--base only works with --hex or --raw".to_string());
+    }
+    if cfg_file.is_some() && !["run", "debug"].contains(&mode.as_str()) {
+        return Err("--cfg only works in run or debug mode; cfg mode prints the graph without counts".to_string());
+    }
+
+    // a replay file stands in for running the program
+    let replay = match &replay_file {
+        Some(filename) => {
+            if mode != "debug" {
+                return Err("--replay only works in debug mode".to_string());
+            }
+            let replay = Replay::load(filename)?;
+            hart_count = replay.hart_count;
+            Some(replay)
+        }
+        None => None,
+    };
+    let executable_hash = if replay.is_some() || record_file.is_some() {
+        let raw = std::fs::read(&executable).map_err(|e| format!("loading {}: {}", executable, e))?;
+        hash_executable(&raw)
+    } else {
+        0
+    };
+    if let Some(replay) = &replay
+        && replay.executable_hash != executable_hash
+    {
+        return Err(format!("{} is not the executable that was recorded", executable));
+    }
+
     // load the program from disk and form the
     // simulated address space and cpu
-    let mut m = load_elf(&executable)?;
+    let mut m = if let Some(words) = &hex_words {
+        let text = if words == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text).map_err(|e| format!("reading stdin: {}", e))?;
+            text
+        } else {
+            words.clone()
+        };
+        executable = String::from("hex words");
+        load_raw(parse_hex_words(&text)?, base.unwrap_or(0x10000))?
+    } else if let Some(filename) = &raw_file {
+        let bytes = std::fs::read(filename).map_err(|e| format!("loading {}: {}", filename, e))?;
+        executable = filename.clone();
+        load_raw(bytes, base.unwrap_or(0x10000))?
+    } else {
+        load_elf(&executable, hart_count)?
+    };
+    m.style = style;
 
     // disassemble the entire text segment
     let mut instructions = Vec::new();
     let mut pc = m.text_start;
     while pc < m.text_end {
-        let (inst, length) = m.load_instruction(pc)?;
+        let (inst, length) = match m.load_instruction(pc) {
+            Ok(found) => found,
+            // hex words and raw files can stop partway through an instruction;
+            // dasm lists the bytes that are left over
+            Err(_) if hex_words.is_some() || raw_file.is_some() => break,
+            Err(msg) => return Err(msg),
+        };
         let instruction = Instruction {
             address: pc,
             op: Op::new(inst),
@@ -1300,7 +2190,10 @@ fn main() -> Result<(), String> {
         let mut i = 0;
         let mut j = 0;
         while i < instructions.len() {
-            let n = if let Some((n, fields)) = get_pseudo_sequence(&instructions[i..], &m.address_symbols) {
+            let n = if !m.style.pseudo {
+                instructions[i].pseudo_fields = instructions[i].op.to_fields();
+                1
+            } else if let Some((n, fields)) = get_pseudo_sequence(&instructions[i..], &m.address_symbols) {
                 instructions[i].pseudo_fields = fields;
                 n
             } else {
@@ -1317,6 +2210,14 @@ fn main() -> Result<(), String> {
         }
     }
 
+    if mode == "dasm" && dasm_format == DasmFormat::Objdump {
+        print!("{}", objdump_listing(&executable, &m, &instructions));
+        return Ok(());
+    }
+    if mode == "dasm" && dasm_format == DasmFormat::Asm {
+        print!("{}", assembly_listing(&executable, &m, &instructions));
+        return Ok(());
+    }
     if mode == "dasm" {
         let mut prev = usize::MAX;
         for instruction in &instructions {
@@ -1334,39 +2235,148 @@ fn main() -> Result<(), String> {
                     instruction.length == 2,
                     false,
                     false,
+                    m.style.registers,
                     false,
                     None,
                     &m.address_symbols
                 )
             );
         }
+        print!("{}", trailing_listing(&m, &instructions));
+        print!("{}", data_listing(&m));
         return Ok(());
     }
 
     // convert to Rc<Instruction> so Effects can reference entries
     let instructions: Vec<Rc<Instruction>> = instructions.into_iter().map(Rc::new).collect();
 
+    if mode == "callgraph" {
+        print!("{}", callgraph_report(&m, &instructions));
+        return Ok(());
+    }
+    if mode == "cfg" {
+        print!("{}", ControlFlowGraph::new(&instructions, &executable, None).dot(&m));
+        return Ok(());
+    }
+    if mode == "lint" {
+        let problems = static_lint(&m, &instructions);
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        if problems.is_empty() {
+            println!("lint: no problems found");
+        } else {
+            println!("lint: {} problem{} found", problems.len(), if problems.len() == 1 { "" } else { "s" });
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // trace the entire execution
     // for run mode, have pre_trace echo output as it goes
     // so inputs and outputs are correctly interleved
-    let sequence = trace(&mut m, &instructions, &addresses, lint == "true", max_steps, &mode);
+    let mut analyses: Vec<Box<dyn Analysis>> = Vec::new();
+    if pipeline {
+        analyses.push(Box::new(Pipeline::new(hart_count)));
+    }
+    if let Some(predictor) = &predictor {
+        analyses.push(Box::new(BranchStats::new(predictor, hart_count)));
+    }
+    let caches = Caches { icache, dcache };
+    if caches.is_enabled() {
+        analyses.push(Box::new(CacheStats::new(&m, caches.clone())));
+    }
+    if profile {
+        analyses.push(Box::new(Profile::new(&m)));
+    }
+    if coverage {
+        analyses.push(Box::new(Coverage::new(&instructions, &executable, lcov_file)));
+    }
+    if cfg_file.is_some() {
+        analyses.push(Box::new(ControlFlowGraph::new(&instructions, &executable, cfg_file)));
+    }
+
+    let exporting = trace_file.is_some() || commit_log_file.is_some() || check_log_file.is_some();
+    let start_time = Instant::now();
+    let mut history = if let Some(replay) = &replay {
+        let mut history = History::from_effects(replay.effects(&m, &instructions, &addresses)?);
+        for i in 0..history.len() {
+            let effects = history.get(i);
+            for analysis in analyses.iter_mut() {
+                analysis.step(effects);
+            }
+        }
+        history
+    } else if mode == "run" && lint == "false" && record_file.is_none() && !exporting && analyses.is_empty() {
+        run_fast(&mut m, &instructions, max_steps, Scheduler::new(hart_count, quantum, seed))
+    } else {
+        let tracer = Tracer::new(&m, lint == "true", Scheduler::new(hart_count, quantum, seed));
+        let keep_sequence = mode == "debug" || record_file.is_some() || exporting;
+        trace(
+            &mut m,
+            &instructions,
+            &addresses,
+            max_steps,
+            &mode,
+            keep_sequence,
+            checkpoint_steps,
+            tracer,
+            &mut analyses,
+        )
+    };
+
+    if bench {
+        let seconds = start_time.elapsed().as_secs_f64();
+        eprintln!(
+            "{} instructions in {:.3}s ({:.0} instructions/sec)",
+            history.len(),
+            seconds,
+            history.len() as f64 / seconds
+        );
+    }
+
+    if let Some(filename) = &record_file {
+        // m.stdin holds everything the program consumed
+        replay::record(filename, executable_hash, hart_count, &m.stdin, &mut history)?;
+    }
+    if let Some(filename) = &trace_file {
+        write_trace(filename, trace_format, &m, &mut history)?;
+    }
+    if let Some(filename) = &commit_log_file {
+        write_commit_log(filename, &m, &mut history)?;
+    }
+    if let Some(filename) = &check_log_file {
+        eprint!("{}", check_commit_log(filename, &m, &mut history)?);
+    }
 
     // debug
     if mode == "debug" {
         m.reset();
-        m.set_most_recent_memory(&sequence, 0);
-        let mut tui = Tui::new(m, instructions, addresses, pseudo_addresses, sequence)?;
+        m.set_most_recent_memory(&mut history, 0);
+        let report = analyses.iter().map(|analysis| analysis.report(&m)).collect::<String>();
+        for analysis in &analyses {
+            analysis.save(&m)?;
+        }
+        let mut tui = Tui::new(m, instructions, addresses, pseudo_addresses, history, caches, predictor)?;
         tui.main_loop()?;
+        drop(tui);
+        eprint!("{}", report);
         return Ok(());
     }
 
+    for analysis in &analyses {
+        eprint!("{}", analysis.report(&m));
+        analysis.save(&m)?;
+    }
+
     // should have ended with exit(0)
-    if let Some(effects) = sequence.last() {
-        if let (Op::Ecall, Some(msg)) = (&effects.instruction.op, &effects.other_message) {
-            if msg.starts_with("exit(") && msg.ends_with(")") {
-                let n: i32 = msg[5..msg.len() - 1].parse().unwrap();
-                std::process::exit(n);
-            }
+    if let Some(effects) = history.last() {
+        if let (Op::Ecall | Op::Ebreak, Some(msg)) = (&effects.instruction.op, &effects.other_message)
+            && msg.starts_with("exit(")
+            && msg.ends_with(")")
+        {
+            let n: i32 = msg[5..msg.len() - 1].parse().unwrap();
+            std::process::exit(n);
         }
 
         if let Some(msg) = &effects.other_message {
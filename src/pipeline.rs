@@ -0,0 +1,153 @@
+use super::*;
+
+// A classic 5-stage in-order pipeline (IF ID EX MEM WB) with full forwarding,
+// timed from the Effects stream. Each instruction enters the pipeline one cycle
+// after the one before it on the same hart unless that instruction delays it:
+//
+//   load-use:                a load followed by a read of the loaded register
+//                            stalls for 1 cycle (the value arrives after MEM)
+//   jal:                     1 cycle, since the target is known in ID
+//   taken branches and jalr: 2 cycles, since fetch predicts not taken and
+//                            the branch resolves in EX
+//
+// Each hart is treated as its own core with its own pipeline.
+
+pub const STAGES: [&str; 5] = ["IF", "ID", "EX", "MEM", "WB"];
+const LOAD_USE_STALL: usize = 1;
+const JUMP_PENALTY: usize = 1;
+const BRANCH_PENALTY: usize = 2;
+
+// cycles lost before `next` because of `prev`, the previous step on the same hart:
+// (load-use stall, control penalty)
+pub fn hazards(prev: &Effects, next: &Effects) -> (usize, usize) {
+    let load_use = match prev.instruction.op {
+        Op::Lb { rd, .. }
+        | Op::Lh { rd, .. }
+        | Op::Lw { rd, .. }
+        | Op::Ld { rd, .. }
+        | Op::Lbu { rd, .. }
+        | Op::Lhu { rd, .. }
+        | Op::Lwu { rd, .. }
+            if rd != ZERO && next.reg_reads.iter().any(|read| read.register == rd) =>
+        {
+            LOAD_USE_STALL
+        }
+        _ => 0,
+    };
+
+    let (old_pc, new_pc) = prev.pc;
+    let control = if new_pc == old_pc + prev.instruction.length {
+        0
+    } else if let Op::Jal { .. } = prev.instruction.op {
+        JUMP_PENALTY
+    } else {
+        BRANCH_PENALTY
+    };
+
+    (load_use, control)
+}
+
+#[derive(Default)]
+struct Site {
+    instruction: Option<Rc<Instruction>>,
+    count: usize,
+    load_use: usize, // cycles this instruction waited for a load
+    control: usize,  // cycles lost after this instruction redirected fetch
+}
+
+pub struct Pipeline {
+    prev: Vec<Option<Effects>>, // most recent step of each hart
+    instructions: usize,
+    cycles: usize,
+    load_use: usize,
+    jumps: usize,
+    branches: usize,
+    sites: HashMap<i64, Site>,
+}
+
+impl Pipeline {
+    pub fn new(hart_count: usize) -> Self {
+        Pipeline {
+            prev: vec![None; hart_count],
+            instructions: 0,
+            cycles: 0,
+            load_use: 0,
+            jumps: 0,
+            branches: 0,
+            sites: HashMap::new(),
+        }
+    }
+}
+
+impl Analysis for Pipeline {
+    fn step(&mut self, effects: &Effects) {
+        self.instructions += 1;
+        let mut load_use = 0;
+        match &self.prev[effects.hart] {
+            // the first instruction on a hart also pays to fill the pipeline
+            None => self.cycles += STAGES.len(),
+            Some(prev) => {
+                let control;
+                (load_use, control) = hazards(prev, effects);
+                self.cycles += 1 + load_use + control;
+                self.load_use += load_use;
+                if control > 0 {
+                    if let Op::Jal { .. } = prev.instruction.op {
+                        self.jumps += control;
+                    } else {
+                        self.branches += control;
+                    }
+                    self.sites.get_mut(&prev.instruction.address).unwrap().control += control;
+                }
+            }
+        }
+
+        let site = self.sites.entry(effects.instruction.address).or_default();
+        site.instruction.get_or_insert_with(|| effects.instruction.clone());
+        site.count += 1;
+        site.load_use += load_use;
+        self.prev[effects.hart] = Some(effects.clone());
+    }
+
+    fn report(&self, m: &Machine) -> String {
+        let mut out = String::new();
+        let cpi = if self.instructions > 0 { self.cycles as f64 / self.instructions as f64 } else { 0.0 };
+        writeln!(out, "pipeline: {} instructions, {} cycles, CPI {:.3}", self.instructions, self.cycles, cpi).unwrap();
+        writeln!(out, "  load-use stalls: {} cycles", self.load_use).unwrap();
+        writeln!(
+            out,
+            "  control stalls:  {} cycles ({} jal, {} taken branch/jalr)",
+            self.jumps + self.branches,
+            self.jumps,
+            self.branches
+        )
+        .unwrap();
+
+        let mut sites: Vec<&Site> = self.sites.values().filter(|site| site.load_use + site.control > 0).collect();
+        if sites.is_empty() {
+            return out;
+        }
+        sites.sort_by_key(|site| {
+            (std::cmp::Reverse(site.load_use + site.control), site.instruction.as_ref().unwrap().address)
+        });
+        writeln!(out, "  stalls by instruction (count, load-use, control):").unwrap();
+        for site in sites {
+            let instruction = site.instruction.as_ref().unwrap();
+            let text = fields_to_string(
+                &instruction.verbose_fields,
+                instruction.address,
+                m.global_pointer,
+                instruction.length == 2,
+                true,
+                false,
+                m.style.registers,
+                true,
+                None,
+                &m.address_symbols,
+            );
+            writeln!(out, "  {:>10} {:>8} {:>8}  {}", site.count, site.load_use, site.control, text.trim_end())
+                .unwrap();
+        }
+        out
+    }
+}
@@ -0,0 +1,203 @@
+use super::*;
+
+// Branch predictors for conditional branches, evaluated over the trace.
+// A predictor is named on the command line, optionally with the number of
+// index bits for its table (default 10), e.g., 2bit or gshare:12:
+//
+//   static  always predict not taken
+//   btfn    backward taken, forward not taken
+//   1bit    a table of last outcomes indexed by pc
+//   2bit    a table of 2-bit saturating counters indexed by pc
+//   gshare  2-bit counters indexed by pc xor the global branch history
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Static,
+    Btfn,
+    OneBit,
+    TwoBit,
+    Gshare,
+}
+
+#[derive(Clone)]
+pub struct Predictor {
+    kind: Kind,
+    bits: usize,
+    table: Vec<u8>,
+    history: usize,
+}
+
+impl Predictor {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, bits) = match spec.split_once(':') {
+            Some((name, bits)) => match bits.parse::<usize>() {
+                Ok(bits @ 1..=24) => (name, bits),
+                _ => return Err(format!("invalid predictor table size {bits}")),
+            },
+            None => (spec, 10),
+        };
+        let kind = match name {
+            "static" => Kind::Static,
+            "btfn" => Kind::Btfn,
+            "1bit" => Kind::OneBit,
+            "2bit" => Kind::TwoBit,
+            "gshare" => Kind::Gshare,
+            _ => return Err(format!("unknown branch predictor {name}")),
+        };
+
+        Ok(Self::new(kind, bits))
+    }
+
+    fn new(kind: Kind, bits: usize) -> Self {
+        // counters start weakly not taken
+        let table = match kind {
+            Kind::Static | Kind::Btfn => Vec::new(),
+            Kind::OneBit => vec![0; 1 << bits],
+            Kind::TwoBit | Kind::Gshare => vec![1; 1 << bits],
+        };
+        Predictor { kind, bits, table, history: 0 }
+    }
+
+    // the same kind of predictor, untrained, with a table of at most 1 << bits entries
+    pub fn with_bits_at_most(&self, bits: usize) -> Self {
+        Self::new(self.kind, self.bits.min(bits))
+    }
+
+    pub fn name(&self) -> String {
+        match self.kind {
+            Kind::Static => "static not-taken".to_string(),
+            Kind::Btfn => "btfn".to_string(),
+            Kind::OneBit => format!("1-bit, {} entries", self.table.len()),
+            Kind::TwoBit => format!("2-bit, {} entries", self.table.len()),
+            Kind::Gshare => format!("gshare, {} entries", self.table.len()),
+        }
+    }
+
+    // predict a conditional branch, then learn its outcome; returns whether the prediction was right
+    pub fn predict(&mut self, pc: i64, target: i64, taken: bool) -> bool {
+        let mask = (1 << self.bits) - 1;
+        let index = match self.kind {
+            Kind::Gshare => ((pc >> 1) as usize ^ self.history) & mask,
+            _ => (pc >> 1) as usize & mask,
+        };
+
+        let prediction = match self.kind {
+            Kind::Static => false,
+            Kind::Btfn => target < pc,
+            Kind::OneBit => self.table[index] != 0,
+            Kind::TwoBit | Kind::Gshare => self.table[index] >= 2,
+        };
+
+        match self.kind {
+            Kind::Static | Kind::Btfn => {}
+            Kind::OneBit => self.table[index] = taken as u8,
+            Kind::TwoBit | Kind::Gshare => {
+                let counter = &mut self.table[index];
+                *counter = if taken { (*counter + 1).min(3) } else { counter.saturating_sub(1) };
+            }
+        }
+        self.history = ((self.history << 1) | taken as usize) & mask;
+
+        prediction == taken
+    }
+
+    // predict the step if it is a conditional branch
+    pub fn step(&mut self, effects: &Effects) -> Option<bool> {
+        let pc = effects.instruction.address;
+        if !is_conditional_branch(&effects.instruction.op) {
+            return None;
+        }
+        let target = effects.instruction.op.branch_target(pc).unwrap();
+        let (old_pc, new_pc) = effects.pc;
+        Some(self.predict(pc, target, new_pc != old_pc + effects.instruction.length))
+    }
+}
+
+pub fn is_conditional_branch(op: &Op) -> bool {
+    matches!(op, Op::Beq { .. } | Op::Bne { .. } | Op::Blt { .. } | Op::Bge { .. } | Op::Bltu { .. } | Op::Bgeu { .. })
+}
+
+struct Site {
+    instruction: Rc<Instruction>,
+    count: usize,
+    taken: usize,
+    correct: usize,
+}
+
+pub struct BranchStats {
+    predictors: Vec<Predictor>, // one per hart
+    sites: HashMap<i64, Site>,
+}
+
+impl BranchStats {
+    pub fn new(predictor: &Predictor, hart_count: usize) -> Self {
+        BranchStats { predictors: vec![predictor.clone(); hart_count], sites: HashMap::new() }
+    }
+}
+
+impl Analysis for BranchStats {
+    fn step(&mut self, effects: &Effects) {
+        let Some(correct) = self.predictors[effects.hart].step(effects) else {
+            return;
+        };
+        let site = self.sites.entry(effects.instruction.address).or_insert_with(|| Site {
+            instruction: effects.instruction.clone(),
+            count: 0,
+            taken: 0,
+            correct: 0,
+        });
+        let (old_pc, new_pc) = effects.pc;
+        site.count += 1;
+        site.taken += (new_pc != old_pc + effects.instruction.length) as usize;
+        site.correct += correct as usize;
+    }
+
+    fn report(&self, m: &Machine) -> String {
+        let percent = |n: usize, total: usize| if total > 0 { n as f64 * 100.0 / total as f64 } else { 0.0 };
+        let count: usize = self.sites.values().map(|site| site.count).sum();
+        let correct: usize = self.sites.values().map(|site| site.correct).sum();
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "branch predictor ({}): {} branches, {} predicted correctly ({:.2}%)",
+            self.predictors[0].name(),
+            count,
+            correct,
+            percent(correct, count)
+        )
+        .unwrap();
+        if self.sites.is_empty() {
+            return out;
+        }
+
+        let mut sites: Vec<&Site> = self.sites.values().collect();
+        sites.sort_by_key(|site| (std::cmp::Reverse(site.count - site.correct), site.instruction.address));
+        writeln!(out, "  by branch (count, taken, accuracy):").unwrap();
+        for site in sites {
+            let instruction = &site.instruction;
+            let text = fields_to_string(
+                &instruction.verbose_fields,
+                instruction.address,
+                m.global_pointer,
+                instruction.length == 2,
+                true,
+                false,
+                m.style.registers,
+                true,
+                None,
+                &m.address_symbols,
+            );
+            writeln!(
+                out,
+                "  {:>10} {:>6.1}% {:>6.1}%  {}",
+                site.count,
+                percent(site.taken, site.count),
+                percent(site.correct, site.count),
+                text.trim_end()
+            )
+            .unwrap();
+        }
+        out
+    }
+}
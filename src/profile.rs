@@ -0,0 +1,192 @@
+use super::*;
+
+// A per-function profile of executed instructions. Calls and returns are
+// recognized the same way the linter does: a call is any jal/jalr that
+// links (is_call), and jalr zero, (ra) is a return to the innermost call
+// with that return address (calls that never returned are dropped). Each
+// step counts toward the function whose code it is in (self) and toward
+// every function with an activation on that hart's call stack (inclusive).
+
+#[derive(Clone, Default)]
+struct FunctionProfile {
+    self_count: usize,
+    inclusive: usize,
+    calls: usize,
+    max_depth: usize,
+}
+
+struct HartProfile {
+    steps: usize,
+    stack: Vec<(usize, i64)>,    // (function, return address) of each activation, innermost last
+    active: Vec<(usize, usize)>, // per function: (activations, step the outermost began)
+}
+
+pub struct Profile {
+    symbols: Symbols, // function index => (address, name); the last is [unknown]
+    functions: Vec<FunctionProfile>,
+    edges: HashMap<(usize, usize), usize>, // (caller, callee) => calls
+    harts: Vec<HartProfile>,
+    total: usize,
+}
+
+impl Profile {
+    pub fn new(m: &Machine) -> Self {
+        let (symbols, _) = m.named_symbols();
+        let n = symbols.len() + 1;
+        let harts =
+            (0..m.harts.len()).map(|_| HartProfile { steps: 0, stack: Vec::new(), active: vec![(0, 0); n] }).collect();
+        Profile { symbols, functions: vec![FunctionProfile::default(); n], edges: HashMap::new(), harts, total: 0 }
+    }
+
+    fn function_of(&self, address: i64) -> usize {
+        match self.symbols.partition_point(|(start, _)| *start <= address) {
+            0 => self.symbols.len(),
+            i => i - 1,
+        }
+    }
+
+    fn name(&self, function: usize) -> &str {
+        self.symbols.get(function).map_or("[unknown]", |(_, name)| name)
+    }
+
+    fn enter(&mut self, hart: usize, function: usize, return_address: i64) {
+        let hart = &mut self.harts[hart];
+        hart.stack.push((function, return_address));
+        let (depth, start) = &mut hart.active[function];
+        if *depth == 0 {
+            *start = hart.steps;
+        }
+        *depth += 1;
+        let profile = &mut self.functions[function];
+        profile.max_depth = profile.max_depth.max(*depth);
+    }
+
+    // return to an address, unwinding calls that never returned (the root is never unwound)
+    fn exit(&mut self, hart: usize, return_address: i64) {
+        let hart = &mut self.harts[hart];
+        let frames = match hart.stack.iter().skip(1).rposition(|&(_, address)| address == return_address) {
+            Some(i) => hart.stack.len() - 1 - i,
+            None => hart.stack.len().min(2) - 1,
+        };
+        for _ in 0..frames {
+            let (function, _) = hart.stack.pop().unwrap();
+            let (depth, start) = &mut hart.active[function];
+            *depth -= 1;
+            if *depth == 0 {
+                self.functions[function].inclusive += hart.steps - *start;
+            }
+        }
+    }
+
+    // inclusive count, including activations that never returned; labels
+    // that are only jumped to, never called, count just their own steps
+    fn inclusive(&self, function: usize) -> usize {
+        let open: usize = self
+            .harts
+            .iter()
+            .filter(|hart| hart.active[function].0 > 0)
+            .map(|hart| hart.steps - hart.active[function].1)
+            .sum();
+        (self.functions[function].inclusive + open).max(self.functions[function].self_count)
+    }
+
+    // functions that ran, most inclusive first
+    fn ranked(&self) -> Vec<usize> {
+        let mut ranked: Vec<usize> = (0..self.functions.len()).filter(|&f| self.inclusive(f) > 0).collect();
+        ranked
+            .sort_by_key(|&f| (std::cmp::Reverse(self.inclusive(f)), std::cmp::Reverse(self.functions[f].self_count)));
+        ranked
+    }
+
+    pub fn flat(&self) -> Vec<String> {
+        let percent = |n: usize| if self.total > 0 { n as f64 * 100.0 / self.total as f64 } else { 0.0 };
+        let mut lines = vec![format!(
+            "{:>10} {:>6} {:>10} {:>6} {:>8} {:>5}  function",
+            "self", "self%", "inclusive", "incl%", "calls", "depth"
+        )];
+        for f in self.ranked() {
+            let profile = &self.functions[f];
+            let inclusive = self.inclusive(f);
+            lines.push(format!(
+                "{:>10} {:>5.1}% {:>10} {:>5.1}% {:>8} {:>5}  {}",
+                profile.self_count,
+                percent(profile.self_count),
+                inclusive,
+                percent(inclusive),
+                profile.calls,
+                profile.max_depth,
+                self.name(f)
+            ));
+        }
+        lines
+    }
+}
+
+impl Analysis for Profile {
+    fn step(&mut self, effects: &Effects) {
+        let pc = effects.instruction.address;
+        let function = self.function_of(pc);
+
+        // the first step on a hart starts its root activation
+        if self.harts[effects.hart].stack.is_empty() && self.harts[effects.hart].steps == 0 {
+            self.enter(effects.hart, function, 0);
+        }
+        self.harts[effects.hart].steps += 1;
+        self.total += 1;
+        self.functions[function].self_count += 1;
+
+        match effects.instruction.op {
+            ref op if is_call(op) => {
+                let callee = self.function_of(effects.pc.1);
+                self.functions[callee].calls += 1;
+                *self.edges.entry((function, callee)).or_default() += 1;
+                self.enter(effects.hart, callee, pc + effects.instruction.length);
+            }
+            Op::Jalr { rd: ZERO, rs1: RA, offset: 0 } => self.exit(effects.hart, effects.pc.1),
+            _ => {}
+        }
+    }
+
+    fn report(&self, _m: &Machine) -> String {
+        let mut out = String::new();
+        writeln!(out, "profile: {} instructions", self.total).unwrap();
+        for line in self.flat() {
+            writeln!(out, "  {}", line).unwrap();
+        }
+
+        if self.edges.is_empty() {
+            return out;
+        }
+        writeln!(out, "call graph:").unwrap();
+        for f in self.ranked() {
+            let mut callers: Vec<(usize, usize)> = self
+                .edges
+                .iter()
+                .filter(|((_, callee), _)| *callee == f)
+                .map(|(&(caller, _), &n)| (caller, n))
+                .collect();
+            let mut callees: Vec<(usize, usize)> = self
+                .edges
+                .iter()
+                .filter(|((caller, _), _)| *caller == f)
+                .map(|(&(_, callee), &n)| (callee, n))
+                .collect();
+            callers.sort_by_key(|&(g, n)| (std::cmp::Reverse(n), g));
+            callees.sort_by_key(|&(g, n)| (std::cmp::Reverse(n), g));
+            let list = |edges: &[(usize, usize)]| {
+                edges.iter().map(|&(g, n)| format!("{} ({})", self.name(g), n)).collect::<Vec<_>>().join(", ")
+            };
+            if callers.is_empty() && callees.is_empty() {
+                continue;
+            }
+            writeln!(out, "  {}", self.name(f)).unwrap();
+            if !callers.is_empty() {
+                writeln!(out, "      called by: {}", list(&callers)).unwrap();
+            }
+            if !callees.is_empty() {
+                writeln!(out, "      calls:     {}", list(&callees)).unwrap();
+            }
+        }
+        out
+    }
+}
@@ -0,0 +1,374 @@
+use super::*;
+
+// A replay file is line-oriented text:
+//
+//   risclet-replay 1
+//   executable <hash of the executable file>
+//   harts <hart count>
+//   stdin <hex bytes consumed from stdin, all the in: fields together>
+//   step <hart> <instruction address> <old pc> <new pc> [fields...]
+//   step ...
+//
+// where the optional fields record the rest of the Effects:
+//
+//   r:<reg>=<value>              register read
+//   w:<reg>=<old>,<new>          register write
+//   mr:<addr>=<hex>              memory read
+//   mw:<addr>=<old hex>,<new hex> memory write
+//   in:<hex>, out:<hex>          stdin and stdout
+//   msg:<hex>                    other message (utf-8)
+//   fs:<sp>, fe:<sp>             function start and end
+//   end                          terminate
+
+const MAGIC: &str = "risclet-replay 1";
+
+pub struct Replay {
+    pub executable_hash: u64,
+    pub hart_count: usize,
+    stdin: Vec<u8>,
+    steps: Vec<String>,
+}
+
+// 64-bit FNV-1a, used to make sure a replay matches its executable
+pub fn hash_executable(raw: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in raw {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn record(
+    filename: &str,
+    executable_hash: u64,
+    hart_count: usize,
+    stdin: &[u8],
+    history: &mut History,
+) -> Result<(), String> {
+    let mut out = String::new();
+    writeln!(out, "{}", MAGIC).unwrap();
+    writeln!(out, "executable {:016x}", executable_hash).unwrap();
+    writeln!(out, "harts {}", hart_count).unwrap();
+    writeln!(out, "stdin {}", to_hex(stdin)).unwrap();
+
+    for i in 0..history.len() {
+        let effects = history.get(i);
+        let (old_pc, new_pc) = effects.pc;
+        write!(out, "step {} {} {} {}", effects.hart, effects.instruction.address, old_pc, new_pc).unwrap();
+        for read in &effects.reg_reads {
+            write!(out, " r:{}={}", read.register, read.value).unwrap();
+        }
+        if let Some((old, new)) = &effects.reg_write {
+            write!(out, " w:{}={},{}", old.register, old.value, new.value).unwrap();
+        }
+        if let Some(read) = &effects.mem_read {
+            write!(out, " mr:{}={}", read.address, to_hex(&read.value)).unwrap();
+        }
+        if let Some((old, new)) = &effects.mem_write {
+            write!(out, " mw:{}={},{}", old.address, to_hex(&old.value), to_hex(&new.value)).unwrap();
+        }
+        if let Some(input) = &effects.stdin {
+            write!(out, " in:{}", to_hex(input)).unwrap();
+        }
+        if let Some(output) = &effects.stdout {
+            write!(out, " out:{}", to_hex(output)).unwrap();
+        }
+        if let Some(msg) = &effects.other_message {
+            write!(out, " msg:{}", to_hex(msg.as_bytes())).unwrap();
+        }
+        if let Some(sp) = effects.function_start {
+            write!(out, " fs:{}", sp).unwrap();
+        }
+        if let Some(sp) = effects.function_end {
+            write!(out, " fe:{}", sp).unwrap();
+        }
+        if effects.terminate {
+            write!(out, " end").unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+
+    std::fs::write(filename, out).map_err(|e| format!("writing {}: {}", filename, e))
+}
+
+impl Replay {
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(filename).map_err(|e| format!("loading {}: {}", filename, e))?;
+        let mut lines = raw.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(format!("{filename} is not a risclet replay file"));
+        }
+
+        let mut header = |key: &str| -> Result<String, String> {
+            match lines.next().and_then(|line| line.strip_prefix(key)) {
+                Some(value) => Ok(value.trim().to_string()),
+                None => Err(format!("{filename}: missing {key} line")),
+            }
+        };
+        let executable_hash = u64::from_str_radix(&header("executable")?, 16)
+            .map_err(|e| format!("{filename}: invalid executable hash: {e}"))?;
+        let hart_count = match header("harts")?.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            _ => return Err(format!("{filename}: invalid hart count")),
+        };
+        let stdin = from_hex(&header("stdin")?)?;
+        let steps: Vec<String> = lines.map(String::from).collect();
+        if steps.is_empty() {
+            return Err(format!("{filename}: no steps recorded"));
+        }
+
+        Ok(Replay { executable_hash, hart_count, stdin, steps })
+    }
+
+    // the recorded steps, checked so that a damaged file cannot send the
+    // machine anywhere it could not have gone when the TUI applies them
+    pub fn effects(
+        &self,
+        m: &Machine,
+        instructions: &[Rc<Instruction>],
+        addresses: &HashMap<i64, usize>,
+    ) -> Result<Vec<Effects>, String> {
+        let mut sequence = Vec::new();
+        let mut stdin = Vec::new();
+        for (n, line) in self.steps.iter().enumerate() {
+            let effects = parse_step(line, instructions, addresses)
+                .and_then(|effects| self.check_step(m, effects))
+                .map_err(|e| format!("replay step {}: {}", n + 1, e))?;
+            if let Some(input) = &effects.stdin {
+                stdin.extend_from_slice(input);
+            }
+            sequence.push(effects);
+        }
+        if stdin != self.stdin {
+            return Err("replay steps do not read the stdin that was recorded".to_string());
+        }
+        Ok(sequence)
+    }
+
+    fn check_step(&self, m: &Machine, effects: Effects) -> Result<Effects, String> {
+        if effects.hart >= self.hart_count {
+            return Err(format!("hart {} out of range", effects.hart));
+        }
+        let (old_pc, new_pc) = effects.pc;
+        if old_pc != effects.instruction.address {
+            return Err(format!("old pc 0x{:x} is not the instruction address", old_pc));
+        }
+        if new_pc & 1 != 0 {
+            return Err(format!("new pc 0x{:x} is not a multiple of 2", new_pc));
+        }
+        if let Some((old, new)) = &effects.mem_write {
+            let size = old.value.len() as i64;
+            if size == 0 || new.value.len() != old.value.len() {
+                return Err("memory write old and new values differ in size".to_string());
+            }
+            if !m.segments.iter().any(|segment| segment.writeable && segment.in_range(old.address, size)) {
+                return Err(format!("memory write to 0x{:x} is outside writeable memory", old.address));
+            }
+        }
+        Ok(effects)
+    }
+}
+
+fn parse_step(
+    line: &str,
+    instructions: &[Rc<Instruction>],
+    addresses: &HashMap<i64, usize>,
+) -> Result<Effects, String> {
+    let mut tokens = line.split(' ');
+    if tokens.next() != Some("step") {
+        return Err("expected step".to_string());
+    }
+    let mut number = || -> Result<i64, String> {
+        let token = tokens.next().ok_or("missing field")?;
+        token.parse::<i64>().map_err(|e| format!("invalid number {token}: {e}"))
+    };
+    let hart = number()? as usize;
+    let address = number()?;
+    let pc = (number()?, number()?);
+    let Some(&index) = addresses.get(&address) else {
+        return Err(format!("no instruction at 0x{:x}", address));
+    };
+
+    let mut effects = Effects::new(&instructions[index], hart);
+    effects.pc = pc;
+
+    for token in tokens {
+        let (tag, value) = token.split_once(':').unwrap_or((token, ""));
+        match tag {
+            "r" => {
+                let (register, value) = parse_register(value)?;
+                effects.reg_reads.push(RegisterValue { register, value: parse_num(value)? });
+            }
+            "w" => {
+                let (register, values) = parse_register(value)?;
+                let (old, new) = values.split_once(',').ok_or("invalid register write")?;
+                effects.reg_write = Some((
+                    RegisterValue { register, value: parse_num(old)? },
+                    RegisterValue { register, value: parse_num(new)? },
+                ));
+            }
+            "mr" => {
+                let (address, value) = parse_pair(value)?;
+                effects.mem_read = Some(MemoryValue { address, value: from_hex(value)? });
+            }
+            "mw" => {
+                let (address, values) = parse_pair(value)?;
+                let (old, new) = values.split_once(',').ok_or("invalid memory write")?;
+                effects.mem_write = Some((
+                    MemoryValue { address, value: from_hex(old)? },
+                    MemoryValue { address, value: from_hex(new)? },
+                ));
+            }
+            "in" => effects.stdin = Some(from_hex(value)?),
+            "out" => effects.stdout = Some(from_hex(value)?),
+            "msg" => effects.other_message = Some(String::from_utf8_lossy(&from_hex(value)?).into_owned()),
+            "fs" => effects.function_start = Some(parse_num(value)?),
+            "fe" => effects.function_end = Some(parse_num(value)?),
+            "end" => effects.terminate = true,
+            _ => return Err(format!("unknown field {token}")),
+        }
+    }
+
+    Ok(effects)
+}
+
+// parse "<number>=<rest>"
+fn parse_register(s: &str) -> Result<(usize, &str), String> {
+    match parse_pair(s)? {
+        (register @ 0..32, value) => Ok((register as usize, value)),
+        _ => Err(format!("invalid register in {s}")),
+    }
+}
+
+fn parse_pair(s: &str) -> Result<(i64, &str), String> {
+    let (key, value) = s.split_once('=').ok_or_else(|| format!("invalid field {s}"))?;
+    Ok((parse_num(key)?, value))
+}
+
+fn parse_num(s: &str) -> Result<i64, String> {
+    s.parse::<i64>().map_err(|e| format!("invalid number {s}: {e}"))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err(format!("invalid hex string {s}"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| format!("invalid hex string {s}: {e}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::tests::{decode, summary, trace_machine};
+
+    fn a_out() -> (Machine, Vec<Rc<Instruction>>, HashMap<i64, usize>) {
+        let mut m = load_elf("test/a.out", 1).unwrap();
+        let (instructions, addresses) = decode(&mut m);
+        (m, instructions, addresses)
+    }
+
+    // replay a single step against a.out
+    fn replay_step(line: &str) -> Result<Effects, String> {
+        let (m, instructions, addresses) = a_out();
+        let replay = Replay { executable_hash: 0, hart_count: 1, stdin: Vec::new(), steps: vec![line.to_string()] };
+        let mut effects = replay.effects(&m, &instructions, &addresses)?;
+        Ok(effects.pop().unwrap())
+    }
+
+    #[test]
+    fn replay_steps_parse_every_field() {
+        let (m, instructions, addresses) = a_out();
+        let pc = m.pc_start;
+        let line = format!(
+            "step 0 {pc} {pc} {} r:10=-5 w:10=1,2 mr:4096=0a0b mw:4096=00ff,1234 in:41 out:4243 msg:6869 fs:16 fe:32 end",
+            pc + 4
+        );
+        let effects = parse_step(&line, &instructions, &addresses).unwrap();
+        assert_eq!(effects.instruction.address, pc);
+        assert_eq!(effects.reg_reads.len(), 1);
+        assert_eq!((effects.reg_reads[0].register, effects.reg_reads[0].value), (10, -5));
+        assert_eq!(
+            effects.mem_read.as_ref().map(|read| (read.address, read.value.clone())),
+            Some((4096, vec![10, 11]))
+        );
+        assert_eq!(
+            summary(&effects),
+            format!(
+                "0 ({pc}, {}) Some((10, 1, 2)) Some((4096, [0, 255], [18, 52])) Some([66, 67]) Some([65]) Some(\"hi\") Some(16) Some(32) true",
+                pc + 4
+            )
+        );
+
+        let pc_line = |fields: &str| format!("step 0 {pc} {pc} {} {fields}", pc + 4);
+        let error = |line: &str| match parse_step(line, &instructions, &addresses) {
+            Ok(_) => panic!("{} should not parse", line),
+            Err(e) => e,
+        };
+        assert_eq!(error("stop 0 1 2 3"), "expected step");
+        assert_eq!(error(&format!("step 0 {pc} {pc}")), "missing field");
+        assert_eq!(error(&format!("step 0 {} {pc} {pc}", pc + 1)), format!("no instruction at 0x{:x}", pc + 1));
+        assert_eq!(error(&pc_line("w:32=1,2")), "invalid register in 32=1,2");
+        assert_eq!(error(&pc_line("w:1=2")), "invalid register write");
+        assert_eq!(error(&pc_line("out:4")), "invalid hex string 4");
+        assert_eq!(error(&pc_line("x:1")), "unknown field x:1");
+    }
+
+    #[test]
+    fn recorded_runs_replay_the_same_effects() {
+        let (m, instructions, addresses) = a_out();
+        let mut history = trace_machine(m.clone(), false, None, 0);
+        let filename = std::env::temp_dir().join(format!("risclet-replay-test-{}", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        record(filename, 0x1234, 1, &[], &mut history).unwrap();
+        let replay = Replay::load(filename);
+        std::fs::remove_file(filename).unwrap();
+
+        let replay = replay.unwrap();
+        assert_eq!((replay.executable_hash, replay.hart_count), (0x1234, 1));
+        let effects = replay.effects(&m, &instructions, &addresses).unwrap();
+        assert_eq!(effects.len(), history.len());
+        for (i, effects) in effects.iter().enumerate() {
+            assert_eq!(summary(effects), summary(history.get(i)), "step {}", i);
+        }
+    }
+
+    #[test]
+    fn tampered_steps_are_rejected() {
+        let m = load_elf("test/a.out", 1).unwrap();
+        let (pc, stack) = (m.pc_start, m.stack_end - 8);
+        let error = |line: String| match replay_step(&line) {
+            Ok(_) => panic!("{} should not replay", line),
+            Err(e) => e,
+        };
+        assert!(replay_step(&format!("step 0 {pc} {pc} {} mw:{stack}=00,01", pc + 4)).is_ok());
+        assert_eq!(error(format!("step 1 {pc} {pc} {}", pc + 4)), "replay step 1: hart 1 out of range");
+        assert_eq!(
+            error(format!("step 0 {pc} {} {}", pc + 4, pc + 8)),
+            format!("replay step 1: old pc 0x{:x} is not the instruction address", pc + 4)
+        );
+        assert_eq!(
+            error(format!("step 0 {pc} {pc} {}", pc + 3)),
+            format!("replay step 1: new pc 0x{:x} is not a multiple of 2", pc + 3)
+        );
+        assert_eq!(
+            error(format!("step 0 {pc} {pc} {} mw:{stack}=00,0102", pc + 4)),
+            "replay step 1: memory write old and new values differ in size"
+        );
+        assert_eq!(
+            error(format!("step 0 {pc} {pc} {} mw:0=00,01", pc + 4)),
+            "replay step 1: memory write to 0x0 is outside writeable memory"
+        );
+        assert_eq!(
+            error(format!("step 0 {pc} {pc} {} in:41", pc + 4)),
+            "replay steps do not read the stdin that was recorded"
+        );
+    }
+}
@@ -250,11 +250,99 @@ fn get_c_slli_srli_srai_imm(inst: i32) -> i64 {
     imm as i64 // unsigned
 }
 
+// the semihosting call sequence is: slli zero, zero, 0x1f; ebreak; srai zero, zero, 7
+pub const SEMIHOSTING_ENTRY: i32 = 0x01f01013;
+pub const SEMIHOSTING_EXIT: i32 = 0x40705013;
+
+// semihosting operation numbers (passed in a0)
+const SYS_OPEN: i64 = 0x01;
+const SYS_CLOSE: i64 = 0x02;
+const SYS_WRITEC: i64 = 0x03;
+const SYS_WRITE0: i64 = 0x04;
+const SYS_WRITE: i64 = 0x05;
+const SYS_READ: i64 = 0x06;
+const SYS_READC: i64 = 0x07;
+const SYS_EXIT: i64 = 0x18;
+const SYS_EXIT_EXTENDED: i64 = 0x20;
+
+const ADP_STOPPED_APPLICATION_EXIT: i64 = 0x20026;
+
 pub const R: [&str; 32] = [
     "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7", "s2",
     "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
 ];
 
+const X: [&str; 32] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14", "x15", "x16", "x17",
+    "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28", "x29", "x30", "x31",
+];
+
+// how registers and instructions are shown everywhere risclet disassembles
+// or names a register, from --style. The machine carries it and it is passed
+// to the formatting functions along with hex and verbose. Traces follow it
+// too; commit logs keep the ABI names that Spike prints.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RegisterNames {
+    Abi,     // a0, s0
+    Fp,      // a0, fp
+    Numeric, // x10, x8
+}
+
+#[derive(Clone, Copy)]
+pub struct DisplayStyle {
+    pub registers: RegisterNames,
+    pub pseudo: bool, // li, mv, call, etc. instead of the instructions behind them
+}
+
+impl DisplayStyle {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut style = DisplayStyle::default();
+        for setting in spec.split(',').filter(|s| !s.is_empty()) {
+            let Some((key, value)) = setting.split_once('=') else {
+                return Err(format!("style setting {setting} should be key=value"));
+            };
+            match key {
+                "regs" => {
+                    style.registers = match value {
+                        "abi" => RegisterNames::Abi,
+                        "fp" => RegisterNames::Fp,
+                        "numeric" => RegisterNames::Numeric,
+                        _ => return Err(format!("unknown register naming {value}")),
+                    }
+                }
+                "pseudo" => {
+                    style.pseudo = match value {
+                        "true" => true,
+                        "false" => false,
+                        _ => return Err(format!("pseudo should be true or false, not {value}")),
+                    }
+                }
+                _ => return Err(format!("unknown style setting {key}")),
+            }
+        }
+        Ok(style)
+    }
+}
+
+impl Default for DisplayStyle {
+    fn default() -> Self {
+        DisplayStyle { registers: RegisterNames::Abi, pseudo: true }
+    }
+}
+
+impl RegisterNames {
+    pub fn name(self, reg: usize) -> &'static str {
+        match self {
+            RegisterNames::Abi => R[reg],
+            RegisterNames::Fp if reg == 8 => "fp",
+            RegisterNames::Fp => R[reg],
+            RegisterNames::Numeric => X[reg],
+        }
+    }
+}
+
+pub const MHARTID: i64 = 0xf14;
+
 pub const ZERO: usize = 0;
 pub const RA: usize = 1;
 pub const SP: usize = 2;
@@ -267,6 +355,7 @@ pub const A_REGS: [usize; 8] = [10, 11, 12, 13, 14, 15, 16, 17];
 pub const T_REGS: [usize; 7] = [5, 6, 7, 28, 29, 30, 31];
 pub const S_REGS: [usize; 12] = [8, 9, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27];
 
+#[derive(PartialEq)]
 pub enum Op {
     // r-type
     Add { rd: usize, rs1: usize, rs2: usize },
@@ -340,6 +429,9 @@ pub enum Op {
     Ecall,
     Ebreak,
 
+    // zicsr (read-only csrs)
+    Csrr { rd: usize, csr: i64 },
+
     // m extension
     Mul { rd: usize, rs1: usize, rs2: usize },
     Mulh { rd: usize, rs1: usize, rs2: usize },
@@ -411,9 +503,12 @@ impl Op {
             0x17 => Op::Auipc { rd: get_rd(inst), imm: get_imm_u(inst) },
 
             // misc
-            0x0f => Self::Fence,
+            0x0f if get_funct3(inst) == 0 => Self::Fence,
             0x73 if inst == 0x00000073 => Self::Ecall,
             0x73 if inst == 0x00100073 => Self::Ebreak,
+            0x73 if get_funct3(inst) == 2 && get_rs1(inst) == 0 => {
+                Op::Csrr { rd: get_rd(inst), csr: get_imm_i(inst) & 0xfff }
+            }
 
             _ => Op::Unimplemented {
                 inst,
@@ -525,10 +620,10 @@ impl Op {
 
         match funct3 {
             0 => Op::Addiw { rd, rs1, imm },
-            1 => Op::Slliw { rd, rs1, shamt },
-            5 => match imm_high {
-                0x00 => Op::Srliw { rd, rs1, shamt },
-                0x20 => Op::Sraiw { rd, rs1, shamt },
+            1 | 5 => match (funct3, imm_high) {
+                (1, 0x00) => Op::Slliw { rd, rs1, shamt },
+                (5, 0x00) => Op::Srliw { rd, rs1, shamt },
+                (5, 0x20) => Op::Sraiw { rd, rs1, shamt },
                 _ => Op::Unimplemented {
                     inst,
                     note: format!(
@@ -1017,64 +1112,64 @@ impl Op {
             Op::Jalr { rd, rs1, offset } => {
                 let rs1_val = m.get(*rs1);
                 m.set(*rd, m.pc + length);
-                m.set_pc((rs1_val + *offset) & !1)?;
+                m.set_pc(rs1_val.wrapping_add(*offset) & !1)?;
             }
 
             // load
             Op::Lb { rd, rs1, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let val = m.load_i8(effective_address)?;
                 m.set(*rd, val);
             }
             Op::Lh { rd, rs1, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let val = m.load_i16(effective_address)?;
                 m.set(*rd, val);
             }
             Op::Lw { rd, rs1, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let val = m.load_i32(effective_address)?;
                 m.set(*rd, val);
             }
             Op::Ld { rd, rs1, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let val = m.load_i64(effective_address)?;
                 m.set(*rd, val);
             }
             Op::Lbu { rd, rs1, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let val = m.load_u8(effective_address)?;
                 m.set(*rd, val);
             }
             Op::Lhu { rd, rs1, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let val = m.load_u16(effective_address)?;
                 m.set(*rd, val);
             }
             Op::Lwu { rd, rs1, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let val = m.load_u32(effective_address)?;
                 m.set(*rd, val);
             }
 
             // store
             Op::Sb { rs1, rs2, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let raw = (m.get(*rs2) as u8).to_le_bytes();
                 m.store(effective_address, &raw)?;
             }
             Op::Sh { rs1, rs2, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let raw = (m.get(*rs2) as u16).to_le_bytes();
                 m.store(effective_address, &raw)?;
             }
             Op::Sw { rs1, rs2, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let raw = (m.get(*rs2) as u32).to_le_bytes();
                 m.store(effective_address, &raw)?;
             }
             Op::Sd { rs1, rs2, offset } => {
-                let effective_address = m.get(*rs1) + *offset;
+                let effective_address = m.get(*rs1).wrapping_add(*offset);
                 let raw = m.get(*rs2).to_le_bytes();
                 m.store(effective_address, &raw)?;
             }
@@ -1084,7 +1179,7 @@ impl Op {
                 m.set(*rd, *imm);
             }
             Op::Auipc { rd, imm } => {
-                m.set(*rd, m.pc + *imm);
+                m.set(*rd, m.pc.wrapping_add(*imm));
             }
 
             // misc
@@ -1095,11 +1190,12 @@ impl Op {
                 match m.get(17) {
                     63 => {
                         // read system call
-                        m.effects.as_mut().unwrap().other_message =
-                            Some(format!("read({}, 0x{:x}, {})", m.get(10), m.get(11), m.get(12)));
                         let fd = m.get(A0);
                         let buf_addr = m.get(A1);
                         let count = m.get(A2);
+                        if let Some(effects) = &mut m.effects {
+                            effects.other_message = Some(format!("read({}, 0x{:x}, {})", fd, buf_addr, count));
+                        }
 
                         if fd != 0 {
                             return Err(format!("read syscall: only stdin (fd 0) supported, not {fd}"));
@@ -1108,26 +1204,24 @@ impl Op {
                             return Err(format!("read syscall: invalid buffer size: {count}"));
                         }
 
-                        // make a buffer and read from stdin
-                        let mut read_buffer = vec![0; count as usize];
-                        let mut handle = io::stdin().lock();
-                        match handle.read(&mut read_buffer) {
-                            Ok(n) => read_buffer.truncate(n),
-                            Err(e) => return Err(format!("read syscall error: {}", e)),
-                        }
+                        let read_buffer =
+                            read_stdin(m, count as usize).map_err(|e| format!("read syscall error: {}", e))?;
 
                         m.store(buf_addr, &read_buffer)?;
                         m.set(A0, read_buffer.len() as i64);
                         m.stdin.extend_from_slice(&read_buffer);
-                        m.effects.as_mut().unwrap().stdin = Some(read_buffer);
+                        if let Some(effects) = &mut m.effects {
+                            effects.stdin = Some(read_buffer);
+                        }
                     }
                     64 => {
                         // write system call
-                        m.effects.as_mut().unwrap().other_message =
-                            Some(format!("write({}, 0x{:x}, {})", m.get(A0), m.get(A1), m.get(A2)));
                         let fd = m.get(A0);
-                        let buf_addr = m.get(11);
-                        let count = m.get(12);
+                        let buf_addr = m.get(A1);
+                        let count = m.get(A2);
+                        if let Some(effects) = &mut m.effects {
+                            effects.other_message = Some(format!("write({}, 0x{:x}, {})", fd, buf_addr, count));
+                        }
 
                         if fd != 1 {
                             return Err(format!("write syscall: only stdout (fd 1) supported, not {fd}"));
@@ -1139,7 +1233,9 @@ impl Op {
                         let write_buffer = m.load(buf_addr, count)?;
                         m.set(A0, write_buffer.len() as i64);
                         m.stdout.extend_from_slice(&write_buffer);
-                        m.effects.as_mut().unwrap().stdout = Some(write_buffer);
+                        if let Some(effects) = &mut m.effects {
+                            effects.stdout = Some(write_buffer);
+                        }
                     }
                     93 => {
                         // exit system call
@@ -1150,7 +1246,23 @@ impl Op {
                 }
             }
             Op::Ebreak => {
-                return Err(String::from("ebreak"));
+                if length == 4 && m.is_semihosting_call() {
+                    return semihosting_call(m);
+                }
+
+                // a plain ebreak is a bookmark for the tui, not a crash
+                if let Some(effects) = &mut m.effects {
+                    effects.other_message = Some(String::from("ebreak"));
+                }
+            }
+
+            // zicsr
+            Op::Csrr { rd, csr } => {
+                let val = match *csr {
+                    MHARTID => m.hart as i64,
+                    _ => return Err(format!("unsupported csr 0x{:x}", csr)),
+                };
+                m.set(*rd, val);
             }
 
             // m extension
@@ -1327,6 +1439,9 @@ impl Op {
             Op::Ecall => vec![Field::Opcode("ecall")],
             Op::Ebreak => vec![Field::Opcode("ebreak")],
 
+            // zicsr
+            Op::Csrr { rd, csr } => vec![Field::Opcode("csrr"), Field::Reg(rd), Field::Csr(csr)],
+
             // m extension
             Op::Mul { rd, rs1, rs2 } => vec![Field::Opcode("mul"), Field::Reg(rd), Field::Reg(rs1), Field::Reg(rs2)],
             Op::Mulh { rd, rs1, rs2 } => vec![Field::Opcode("mulh"), Field::Reg(rd), Field::Reg(rs1), Field::Reg(rs2)],
@@ -1413,12 +1528,13 @@ impl Op {
         is_compressed: bool,
         hex: bool,
         verbose: bool,
+        names: RegisterNames,
         show_addresses: bool,
         arrow: Option<&str>,
         symbols: &HashMap<i64, String>,
     ) -> String {
         let fields = if verbose { self.to_fields() } else { self.to_pseudo_fields() };
-        fields_to_string(&fields, pc, gp, is_compressed, hex, verbose, show_addresses, arrow, symbols)
+        fields_to_string(&fields, pc, gp, is_compressed, hex, verbose, names, show_addresses, arrow, symbols)
     }
 
     pub fn branch_target(&self, pc: i64) -> Option<i64> {
@@ -1435,6 +1551,155 @@ impl Op {
     }
 }
 
+fn semihosting_call(m: &mut Machine) -> Result<(), String> {
+    let op = m.get(A0);
+    let arg = m.get(A1);
+
+    // only the console is available to semihosting programs;
+    // handles are 0 for stdin and 1 or 2 for stdout
+    let result = match op {
+        SYS_OPEN => {
+            let name_addr = m.peek_i64(arg)?;
+            let mode = m.peek_i64(arg + 8)?;
+            let len = m.peek_i64(arg + 16)?;
+            if len < 0 {
+                return Err(format!("SYS_OPEN: invalid name length: {len}"));
+            }
+            let name = m.peek(name_addr, len)?;
+            if let Some(effects) = &mut m.effects {
+                effects.other_message = Some(format!("SYS_OPEN({:?}, {})", String::from_utf8_lossy(&name), mode));
+            }
+
+            match (name.as_slice(), mode) {
+                (b":tt", 0..4) => 0,
+                (b":tt", 4..8) => 1,
+                (b":tt", 8..12) => 2,
+                _ => -1,
+            }
+        }
+        SYS_CLOSE => {
+            let handle = m.peek_i64(arg)?;
+            if let Some(effects) = &mut m.effects {
+                effects.other_message = Some(format!("SYS_CLOSE({})", handle));
+            }
+            if (0..3).contains(&handle) { 0 } else { -1 }
+        }
+        SYS_WRITEC => {
+            if let Some(effects) = &mut m.effects {
+                effects.other_message = Some(format!("SYS_WRITEC(0x{:x})", arg));
+            }
+            let write_buffer = m.load(arg, 1)?;
+            m.stdout.extend_from_slice(&write_buffer);
+            if let Some(effects) = &mut m.effects {
+                effects.stdout = Some(write_buffer);
+            }
+            return Ok(());
+        }
+        SYS_WRITE0 => {
+            if let Some(effects) = &mut m.effects {
+                effects.other_message = Some(format!("SYS_WRITE0(0x{:x})", arg));
+            }
+            let mut count = 0;
+            while m.peek(arg + count, 1)?[0] != 0 {
+                count += 1;
+            }
+            let write_buffer = m.load(arg, count)?;
+            m.stdout.extend_from_slice(&write_buffer);
+            if let Some(effects) = &mut m.effects {
+                effects.stdout = Some(write_buffer);
+            }
+            return Ok(());
+        }
+        SYS_WRITE => {
+            let handle = m.peek_i64(arg)?;
+            let buf_addr = m.peek_i64(arg + 8)?;
+            let count = m.peek_i64(arg + 16)?;
+            if let Some(effects) = &mut m.effects {
+                effects.other_message = Some(format!("SYS_WRITE({}, 0x{:x}, {})", handle, buf_addr, count));
+            }
+
+            if handle != 1 && handle != 2 {
+                return Err(format!("SYS_WRITE: only stdout (handle 1 or 2) supported, not {handle}"));
+            }
+            if count < 0 {
+                return Err(format!("SYS_WRITE: invalid buffer size: {count}"));
+            }
+
+            let write_buffer = m.load(buf_addr, count)?;
+            m.stdout.extend_from_slice(&write_buffer);
+            if let Some(effects) = &mut m.effects {
+                effects.stdout = Some(write_buffer);
+            }
+
+            // the result is the number of bytes not written
+            0
+        }
+        SYS_READ => {
+            let handle = m.peek_i64(arg)?;
+            let buf_addr = m.peek_i64(arg + 8)?;
+            let count = m.peek_i64(arg + 16)?;
+            if let Some(effects) = &mut m.effects {
+                effects.other_message = Some(format!("SYS_READ({}, 0x{:x}, {})", handle, buf_addr, count));
+            }
+
+            if handle != 0 {
+                return Err(format!("SYS_READ: only stdin (handle 0) supported, not {handle}"));
+            }
+            if count < 0 {
+                return Err(format!("SYS_READ: invalid buffer size: {count}"));
+            }
+
+            let read_buffer = read_stdin(m, count as usize).map_err(|e| format!("SYS_READ error: {}", e))?;
+            m.store(buf_addr, &read_buffer)?;
+            m.stdin.extend_from_slice(&read_buffer);
+            let result = count - read_buffer.len() as i64;
+            if let Some(effects) = &mut m.effects {
+                effects.stdin = Some(read_buffer);
+            }
+
+            // the result is the number of bytes not read
+            result
+        }
+        SYS_READC => {
+            if let Some(effects) = &mut m.effects {
+                effects.other_message = Some(String::from("SYS_READC()"));
+            }
+            let read_buffer = read_stdin(m, 1).map_err(|e| format!("SYS_READC error: {}", e))?;
+            m.stdin.extend_from_slice(&read_buffer);
+            let result = read_buffer.first().map_or(-1, |&b| b as i64);
+            if let Some(effects) = &mut m.effects {
+                effects.stdin = Some(read_buffer);
+            }
+            result
+        }
+        SYS_EXIT | SYS_EXIT_EXTENDED => {
+            // rv64 passes a block with the reason and the exit status
+            let reason = m.peek_i64(arg)?;
+            let subcode = m.peek_i64(arg + 8)?;
+            let status = if reason == ADP_STOPPED_APPLICATION_EXIT { subcode & 0xff } else { 1 };
+            return Err(format!("exit({})", status));
+        }
+        _ => return Err(format!("unsupported semihosting operation 0x{:x}", op)),
+    };
+
+    m.set(A0, result);
+    Ok(())
+}
+
+// make a buffer and read up to count bytes from stdin
+fn read_stdin(m: &mut Machine, count: usize) -> io::Result<Vec<u8>> {
+    // re-executing from a checkpoint: return the same reads as the original run
+    if let Some(input) = &mut m.input {
+        let mut read_buffer = input.pop_front().unwrap_or_default();
+        read_buffer.truncate(count);
+        return Ok(read_buffer);
+    }
+    let mut read_buffer = vec![0; count];
+    let n = io::stdin().lock().read(&mut read_buffer)?;
+    read_buffer.truncate(n);
+    Ok(read_buffer)
+}
+
 pub fn get_pseudo_sequence(
     instructions: &[Instruction],
     symbols: &HashMap<i64, String>,
@@ -1449,15 +1714,120 @@ pub fn get_pseudo_sequence(
         return None;
     }
 
+    // global variable access through %pcrel_hi/%pcrel_lo or %hi/%lo,
+    // e.g., lw a0, counter or sw a0, counter, t0
+    if let Some((base, offset, reg, is_store)) = memory_operands(&inst2.op) {
+        let address = match inst1.op {
+            Op::Auipc { rd, imm } if rd == base => Some(imm + offset),
+            Op::Lui { rd, imm } if rd == base && symbols.contains_key(&(imm + offset)) => {
+                Some(imm + offset - inst1.address)
+            }
+            _ => None,
+        };
+        if let Some(address) = address
+            && (reg == base) != is_store
+        {
+            let mut fields = inst2.op.to_fields();
+            fields[2] = Field::PCRelAddr(address);
+            if is_store {
+                fields.push(Field::Reg(base));
+            }
+            return Some((2, fields));
+        }
+    }
+
     match (&inst1.op, &inst2.op) {
         (Op::Auipc { rd: rd1, imm: imm1 }, Op::Addi { rd: rd2, rs1: rs2, imm: imm2 }) if rd1 == rd2 && rd2 == rs2 => {
             Some((2, vec![Field::Opcode("la"), Field::Reg(*rd1), Field::PCRelAddr(imm1 + imm2)]))
         }
 
+        (Op::Lui { rd: rd1, imm: imm1 }, Op::Addi { rd: rd2, rs1: rs2, imm: imm2 })
+            if rd1 == rd2 && rd2 == rs2 && symbols.contains_key(&(imm1 + imm2)) =>
+        {
+            Some((2, vec![Field::Opcode("la"), Field::Reg(*rd1), Field::PCRelAddr(imm1 + imm2 - inst1.address)]))
+        }
+
         (Op::Auipc { rd: RA, imm }, Op::Jalr { rd: RA, rs1: RA, offset }) => {
             Some((2, vec![Field::Opcode("call"), Field::PCRelAddr(imm + offset)]))
         }
 
+        (Op::Auipc { rd, imm }, Op::Jalr { rd: ZERO, rs1, offset }) if rd == rs1 => {
+            Some((2, vec![Field::Opcode("tail"), Field::PCRelAddr(imm + offset)]))
+        }
+
+        _ => get_li_sequence(instructions, symbols),
+    }
+}
+
+// li with a constant too big for addi: lui or li followed by the addi(w) and
+// slli steps the assembler uses to build it, all on the same register
+fn get_li_sequence(instructions: &[Instruction], symbols: &HashMap<i64, String>) -> Option<(usize, Vec<Field>)> {
+    let (rd, mut value, mut can_add, starts_with_lui) = match instructions[0].op {
+        Op::Lui { rd, imm } if rd != ZERO => (rd, imm, true, true),
+        Op::Addi { rd, rs1: ZERO, imm } if rd != ZERO => (rd, imm, false, false),
+        _ => return None,
+    };
+
+    // the assembler only starts with addi when it shifts and then adds, so
+    // a chain that starts with addi must end with an add after a shift;
+    // li t0,1; slli t0,t0,32 is the student's own arithmetic
+    let mut n = 1;
+    let mut folded = if starts_with_lui { Some((1, value)) } else { None };
+    for inst in &instructions[1..] {
+        if symbols.contains_key(&inst.address) {
+            break;
+        }
+        match inst.op {
+            Op::Addi { rd: rd2, rs1, imm } if can_add && rd2 == rd && rs1 == rd => {
+                value = value.wrapping_add(imm);
+                can_add = false;
+            }
+            Op::Addiw { rd: rd2, rs1, imm } if can_add && rd2 == rd && rs1 == rd => {
+                value = value.wrapping_add(imm) as i32 as i64;
+                can_add = false;
+            }
+            // shifts by less than 12 are arithmetic the student wrote, not part of li
+            Op::Slli { rd: rd2, rs1, shamt } if rd2 == rd && rs1 == rd && shamt >= 12 => {
+                value = value.wrapping_shl(shamt as u32);
+                can_add = true;
+            }
+            _ => break,
+        }
+        n += 1;
+        if starts_with_lui || !can_add {
+            folded = Some((n, value));
+        }
+    }
+
+    match folded {
+        Some((n, value)) if n >= 2 => Some((n, vec![Field::Opcode("li"), Field::Reg(rd), Field::Imm(value)])),
+        _ => None,
+    }
+}
+
+// the register a call writes its return address to; any jal or jalr that
+// links is a call, whichever register it links through
+pub fn link_register(op: &Op) -> Option<usize> {
+    match *op {
+        Op::Jal { rd, .. } | Op::Jalr { rd, .. } if rd != ZERO => Some(rd),
+        _ => None,
+    }
+}
+
+// the base register, offset, and data register of a load or store
+pub fn memory_operands(op: &Op) -> Option<(usize, i64, usize, bool)> {
+    match *op {
+        Op::Lb { rd, rs1, offset }
+        | Op::Lh { rd, rs1, offset }
+        | Op::Lw { rd, rs1, offset }
+        | Op::Ld { rd, rs1, offset }
+        | Op::Lbu { rd, rs1, offset }
+        | Op::Lhu { rd, rs1, offset }
+        | Op::Lwu { rd, rs1, offset } => Some((rs1, offset, rd, false)),
+        Op::Sb { rs1, rs2, offset }
+        | Op::Sh { rs1, rs2, offset }
+        | Op::Sw { rs1, rs2, offset }
+        | Op::Sd { rs1, rs2, offset } => Some((rs1, offset, rs2, true)),
         _ => None,
     }
 }
@@ -1470,6 +1840,7 @@ pub fn fields_to_string(
     is_compressed: bool,
     hex: bool,
     verbose: bool,
+    names: RegisterNames,
     show_addresses: bool,
     arrow: Option<&str>,
     symbols: &HashMap<i64, String>,
@@ -1500,16 +1871,33 @@ pub fn fields_to_string(
         label.push(' ');
     }
     let label: String = label.into_iter().collect();
+    let disasm = fields_to_disassembly(fields, pc, gp, is_compressed, hex, verbose, names, symbols);
+
+    format!("{addr_part}{label:<16}{disasm:<48}")
+}
 
-    let mut inst = fields[0].to_string(pc, gp, hex, verbose, symbols);
+// just the instruction and its operands, without the address, label, or padding
+#[allow(clippy::too_many_arguments)]
+pub fn fields_to_disassembly(
+    fields: &[Field],
+    pc: i64,
+    gp: i64,
+    is_compressed: bool,
+    hex: bool,
+    verbose: bool,
+    names: RegisterNames,
+    symbols: &HashMap<i64, String>,
+) -> String {
+    let mut inst = fields[0].to_string(pc, gp, hex, verbose, names, symbols);
     if verbose && is_compressed {
         inst.insert_str(0, "c.");
     }
-    let operands =
-        fields[1..].iter().map(|elt| elt.to_string(pc, gp, hex, verbose, symbols)).collect::<Vec<_>>().join(", ");
-    let disasm = format!("{:<8}{}", inst, operands);
-
-    format!("{addr_part}{label:<16}{disasm:<48}")
+    let operands = fields[1..]
+        .iter()
+        .map(|elt| elt.to_string(pc, gp, hex, verbose, names, symbols))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{:<8}{}", inst, operands).trim_end().to_string()
 }
 
 pub enum Field {
@@ -1519,18 +1907,27 @@ pub enum Field {
     Indirect(i64, usize),
     PCRelAddr(i64),
     GPRelAddr(i64),
+    Csr(i64),
 }
 
 impl Field {
-    pub fn to_string(&self, pc: i64, gp: i64, hex: bool, verbose: bool, symbols: &HashMap<i64, String>) -> String {
+    pub fn to_string(
+        &self,
+        pc: i64,
+        gp: i64,
+        hex: bool,
+        verbose: bool,
+        names: RegisterNames,
+        symbols: &HashMap<i64, String>,
+    ) -> String {
         match self {
             Field::Opcode(inst) => String::from(*inst),
-            Field::Reg(reg) => String::from(R[*reg]),
+            Field::Reg(reg) => String::from(names.name(*reg)),
             Field::Imm(i) if !hex || (0..=9).contains(i) => format!("{}", i),
             Field::Imm(i) => format!("0x{:x}", i),
-            Field::Indirect(0, reg) if !verbose => format!("({})", R[*reg]),
-            Field::Indirect(imm, reg) if hex => format!("0x{:x}({})", imm, R[*reg]),
-            Field::Indirect(imm, reg) => format!("{}({})", imm, R[*reg]),
+            Field::Indirect(0, reg) if !verbose => format!("({})", names.name(*reg)),
+            Field::Indirect(imm, reg) if hex => format!("0x{:x}({})", imm, names.name(*reg)),
+            Field::Indirect(imm, reg) => format!("{}({})", imm, names.name(*reg)),
             Field::PCRelAddr(offset) => {
                 let addr = offset + pc;
                 match symbols.get(&addr) {
@@ -1550,6 +1947,8 @@ impl Field {
                     }
                 }
             }
+            Field::Csr(MHARTID) => String::from("mhartid"),
+            Field::Csr(csr) => format!("0x{:x}", csr),
             Field::GPRelAddr(offset) => {
                 // gp-relative only applies to pseudo-instructions in !verbose mode
                 // i.e., "la"
@@ -1568,3 +1967,53 @@ impl Field {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T0: usize = 5;
+
+    // the number of lines folded into li and the value loaded
+    fn li(lines: &[&str]) -> Option<(usize, i64)> {
+        let instructions: Vec<Instruction> = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| Instruction {
+                address: 0x10000 + 4 * i as i64,
+                op: assemble(line).unwrap(),
+                length: 4,
+                pseudo_index: i,
+                verbose_fields: Vec::new(),
+                pseudo_fields: Vec::new(),
+            })
+            .collect();
+        let (n, fields) = get_li_sequence(&instructions, &HashMap::new())?;
+        match fields[..] {
+            [Field::Opcode("li"), Field::Reg(T0), Field::Imm(value)] => Some((n, value)),
+            _ => panic!("li should load into t0"),
+        }
+    }
+
+    #[test]
+    fn li_folds_the_sequences_the_assembler_emits() {
+        assert_eq!(li(&["lui t0, 0x12345", "addiw t0, t0, 0x678"]), Some((2, 0x12345678)));
+        assert_eq!(li(&["lui t0, 0x1", "slli t0, t0, 20"]), Some((2, 0x100000000)));
+        assert_eq!(li(&["addi t0, zero, 1", "slli t0, t0, 32", "addi t0, t0, -1"]), Some((3, 0xffffffff)));
+        assert_eq!(
+            li(&["lui t0, 0x1234", "addiw t0, t0, 0x567", "slli t0, t0, 12", "addi t0, t0, -0x544"]),
+            Some((4, 0x1234566abc))
+        );
+    }
+
+    #[test]
+    fn li_leaves_the_students_own_shifts_alone() {
+        assert_eq!(li(&["addi t0, zero, 1", "slli t0, t0, 32"]), None);
+        assert_eq!(
+            li(&["addi t0, zero, 1", "slli t0, t0, 32", "addi t0, t0, 5", "slli t0, t0, 12"]),
+            Some((3, 0x100000005))
+        );
+        assert_eq!(li(&["lui t0, 0x1", "slli t0, t0, 3"]), None);
+        assert_eq!(li(&["addi t0, zero, 1", "slli t0, t0, 32", "addi t1, t1, 5"]), None);
+    }
+}
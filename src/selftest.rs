@@ -0,0 +1,717 @@
+use super::*;
+
+// A randomized differential test of the decoder and executor. Each case picks
+// an instruction form (or random bits), fills the free bits of the encoding at
+// random, sets up random registers and memory, and runs the instruction both
+// through Op::new + Op::execute and through the small reference model below,
+// which is written independently from the RISC-V spec. The two must agree on
+// whether the encoding is legal, its mnemonic, whether it faults, and the
+// registers, pc, and memory afterward. Every legal instruction must also
+// survive Op::encode and Op::encode_compressed back through Op::new.
+//
+// ecall and ebreak are only decoded, not executed, since they do I/O.
+// cargo test runs it with two fixed seeds; --selftest runs it on demand.
+
+const TEXT_START: i64 = 0x10000;
+const DATA_START: i64 = 0x20000;
+const REGION_SIZE: usize = 512;
+
+// instruction forms: (name, mask, match, compressed), from the encoding tables in the spec
+const FORMS: &[(&str, u32, u32, bool)] = &[
+    ("add", 0xfe00707f, 0x00000033, false),
+    ("sub", 0xfe00707f, 0x40000033, false),
+    ("sll", 0xfe00707f, 0x00001033, false),
+    ("slt", 0xfe00707f, 0x00002033, false),
+    ("sltu", 0xfe00707f, 0x00003033, false),
+    ("xor", 0xfe00707f, 0x00004033, false),
+    ("srl", 0xfe00707f, 0x00005033, false),
+    ("sra", 0xfe00707f, 0x40005033, false),
+    ("or", 0xfe00707f, 0x00006033, false),
+    ("and", 0xfe00707f, 0x00007033, false),
+    ("addw", 0xfe00707f, 0x0000003b, false),
+    ("subw", 0xfe00707f, 0x4000003b, false),
+    ("sllw", 0xfe00707f, 0x0000103b, false),
+    ("srlw", 0xfe00707f, 0x0000503b, false),
+    ("sraw", 0xfe00707f, 0x4000503b, false),
+    ("addi", 0x0000707f, 0x00000013, false),
+    ("slti", 0x0000707f, 0x00002013, false),
+    ("sltiu", 0x0000707f, 0x00003013, false),
+    ("xori", 0x0000707f, 0x00004013, false),
+    ("ori", 0x0000707f, 0x00006013, false),
+    ("andi", 0x0000707f, 0x00007013, false),
+    ("slli", 0xfc00707f, 0x00001013, false),
+    ("srli", 0xfc00707f, 0x00005013, false),
+    ("srai", 0xfc00707f, 0x40005013, false),
+    ("addiw", 0x0000707f, 0x0000001b, false),
+    ("slliw", 0xfe00707f, 0x0000101b, false),
+    ("srliw", 0xfe00707f, 0x0000501b, false),
+    ("sraiw", 0xfe00707f, 0x4000501b, false),
+    ("beq", 0x0000707f, 0x00000063, false),
+    ("bne", 0x0000707f, 0x00001063, false),
+    ("blt", 0x0000707f, 0x00004063, false),
+    ("bge", 0x0000707f, 0x00005063, false),
+    ("bltu", 0x0000707f, 0x00006063, false),
+    ("bgeu", 0x0000707f, 0x00007063, false),
+    ("jal", 0x0000007f, 0x0000006f, false),
+    ("jalr", 0x0000707f, 0x00000067, false),
+    ("lb", 0x0000707f, 0x00000003, false),
+    ("lh", 0x0000707f, 0x00001003, false),
+    ("lw", 0x0000707f, 0x00002003, false),
+    ("ld", 0x0000707f, 0x00003003, false),
+    ("lbu", 0x0000707f, 0x00004003, false),
+    ("lhu", 0x0000707f, 0x00005003, false),
+    ("lwu", 0x0000707f, 0x00006003, false),
+    ("sb", 0x0000707f, 0x00000023, false),
+    ("sh", 0x0000707f, 0x00001023, false),
+    ("sw", 0x0000707f, 0x00002023, false),
+    ("sd", 0x0000707f, 0x00003023, false),
+    ("lui", 0x0000007f, 0x00000037, false),
+    ("auipc", 0x0000007f, 0x00000017, false),
+    ("fence", 0x0000707f, 0x0000000f, false),
+    ("ecall", 0xffffffff, 0x00000073, false),
+    ("ebreak", 0xffffffff, 0x00100073, false),
+    ("csrr", 0x000ff07f, 0x00002073, false),
+    ("csrr mhartid", 0xfffff07f, 0xf1402073, false),
+    ("mul", 0xfe00707f, 0x02000033, false),
+    ("mulh", 0xfe00707f, 0x02001033, false),
+    ("mulhsu", 0xfe00707f, 0x02002033, false),
+    ("mulhu", 0xfe00707f, 0x02003033, false),
+    ("div", 0xfe00707f, 0x02004033, false),
+    ("divu", 0xfe00707f, 0x02005033, false),
+    ("rem", 0xfe00707f, 0x02006033, false),
+    ("remu", 0xfe00707f, 0x02007033, false),
+    ("mulw", 0xfe00707f, 0x0200003b, false),
+    ("divw", 0xfe00707f, 0x0200403b, false),
+    ("divuw", 0xfe00707f, 0x0200503b, false),
+    ("remw", 0xfe00707f, 0x0200603b, false),
+    ("remuw", 0xfe00707f, 0x0200703b, false),
+    ("c.addi4spn", 0xe003, 0x0000, true),
+    ("c.fld", 0xe003, 0x2000, true),
+    ("c.lw", 0xe003, 0x4000, true),
+    ("c.ld", 0xe003, 0x6000, true),
+    ("c.reserved", 0xe003, 0x8000, true),
+    ("c.fsd", 0xe003, 0xa000, true),
+    ("c.sw", 0xe003, 0xc000, true),
+    ("c.sd", 0xe003, 0xe000, true),
+    ("c.addi", 0xe003, 0x0001, true),
+    ("c.addiw", 0xe003, 0x2001, true),
+    ("c.li", 0xe003, 0x4001, true),
+    ("c.addi16sp", 0xef83, 0x6101, true),
+    ("c.lui", 0xe003, 0x6001, true),
+    ("c.srli", 0xec03, 0x8001, true),
+    ("c.srai", 0xec03, 0x8401, true),
+    ("c.andi", 0xec03, 0x8801, true),
+    ("c.sub", 0xfc63, 0x8c01, true),
+    ("c.xor", 0xfc63, 0x8c21, true),
+    ("c.or", 0xfc63, 0x8c41, true),
+    ("c.and", 0xfc63, 0x8c61, true),
+    ("c.subw", 0xfc63, 0x9c01, true),
+    ("c.addw", 0xfc63, 0x9c21, true),
+    ("c.j", 0xe003, 0xa001, true),
+    ("c.beqz", 0xe003, 0xc001, true),
+    ("c.bnez", 0xe003, 0xe001, true),
+    ("c.slli", 0xe003, 0x0002, true),
+    ("c.fldsp", 0xe003, 0x2002, true),
+    ("c.lwsp", 0xe003, 0x4002, true),
+    ("c.ldsp", 0xe003, 0x6002, true),
+    ("c.jr", 0xf07f, 0x8002, true),
+    ("c.mv", 0xf003, 0x8002, true),
+    ("c.ebreak", 0xffff, 0x9002, true),
+    ("c.jalr", 0xf07f, 0x9002, true),
+    ("c.add", 0xf003, 0x9002, true),
+    ("c.fsdsp", 0xe003, 0xa002, true),
+    ("c.swsp", 0xe003, 0xc002, true),
+    ("c.sdsp", 0xe003, 0xe002, true),
+    ("random", 0x00000003, 0x00000003, false),
+    ("random compressed", 0x0000, 0x0000, true),
+];
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    // register values weighted toward the edge cases
+    fn value(&mut self) -> i64 {
+        const EDGES: [i64; 8] = [0, 1, -1, i64::MIN, i64::MAX, 0x7fffffff, -0x80000000, 0xffffffff];
+        match self.below(4) {
+            0 => EDGES[self.below(EDGES.len() as u64) as usize],
+            1 => self.below(64) as i64 - 32,
+            2 => self.next() as i32 as i64,
+            _ => self.next() as i64,
+        }
+    }
+}
+
+// a decoded instruction in the reference model
+struct Decoded {
+    name: &'static str,
+    rd: usize,
+    rs1: usize,
+    rs2: usize,
+    imm: i64,
+}
+
+fn bit(x: u32, i: u32) -> u32 {
+    (x >> i) & 1
+}
+
+fn bits(x: u32, hi: u32, lo: u32) -> u32 {
+    (x >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+fn sext(x: u32, width: u32) -> i64 {
+    ((x as i64) << (64 - width)) >> (64 - width)
+}
+
+fn decode(x: u32) -> Option<Decoded> {
+    if x & 3 != 3 { decode_compressed(x & 0xffff) } else { decode_full(x) }
+}
+
+fn decode_full(x: u32) -> Option<Decoded> {
+    let rd = bits(x, 11, 7) as usize;
+    let rs1 = bits(x, 19, 15) as usize;
+    let rs2 = bits(x, 24, 20) as usize;
+    let funct3 = bits(x, 14, 12);
+    let funct7 = bits(x, 31, 25);
+    let i_imm = sext(bits(x, 31, 20), 12);
+    let s_imm = sext((bits(x, 31, 25) << 5) | bits(x, 11, 7), 12);
+    let b_imm = sext((bit(x, 31) << 12) | (bit(x, 7) << 11) | (bits(x, 30, 25) << 5) | (bits(x, 11, 8) << 1), 13);
+    let u_imm = (x & 0xfffff000) as i32 as i64;
+    let j_imm = sext((bit(x, 31) << 20) | (bits(x, 19, 12) << 12) | (bit(x, 20) << 11) | (bits(x, 30, 21) << 1), 21);
+    let d = |name, imm| Some(Decoded { name, rd, rs1, rs2, imm });
+
+    match bits(x, 6, 0) {
+        0x33 => {
+            let name = match (funct7, funct3) {
+                (0x00, 0) => "add",
+                (0x20, 0) => "sub",
+                (0x00, 1) => "sll",
+                (0x00, 2) => "slt",
+                (0x00, 3) => "sltu",
+                (0x00, 4) => "xor",
+                (0x00, 5) => "srl",
+                (0x20, 5) => "sra",
+                (0x00, 6) => "or",
+                (0x00, 7) => "and",
+                (0x01, 0) => "mul",
+                (0x01, 1) => "mulh",
+                (0x01, 2) => "mulhsu",
+                (0x01, 3) => "mulhu",
+                (0x01, 4) => "div",
+                (0x01, 5) => "divu",
+                (0x01, 6) => "rem",
+                (0x01, 7) => "remu",
+                _ => return None,
+            };
+            d(name, 0)
+        }
+        0x3b => {
+            let name = match (funct7, funct3) {
+                (0x00, 0) => "addw",
+                (0x20, 0) => "subw",
+                (0x00, 1) => "sllw",
+                (0x00, 5) => "srlw",
+                (0x20, 5) => "sraw",
+                (0x01, 0) => "mulw",
+                (0x01, 4) => "divw",
+                (0x01, 5) => "divuw",
+                (0x01, 6) => "remw",
+                (0x01, 7) => "remuw",
+                _ => return None,
+            };
+            d(name, 0)
+        }
+        0x13 => {
+            let shamt = bits(x, 25, 20) as i64;
+            match (funct3, bits(x, 31, 26)) {
+                (0, _) => d("addi", i_imm),
+                (2, _) => d("slti", i_imm),
+                (3, _) => d("sltiu", i_imm),
+                (4, _) => d("xori", i_imm),
+                (6, _) => d("ori", i_imm),
+                (7, _) => d("andi", i_imm),
+                (1, 0x00) => d("slli", shamt),
+                (5, 0x00) => d("srli", shamt),
+                (5, 0x10) => d("srai", shamt),
+                _ => None,
+            }
+        }
+        0x1b => {
+            let shamt = bits(x, 24, 20) as i64;
+            match (funct3, funct7) {
+                (0, _) => d("addiw", i_imm),
+                (1, 0x00) => d("slliw", shamt),
+                (5, 0x00) => d("srliw", shamt),
+                (5, 0x20) => d("sraiw", shamt),
+                _ => None,
+            }
+        }
+        0x63 => {
+            let name = match funct3 {
+                0 => "beq",
+                1 => "bne",
+                4 => "blt",
+                5 => "bge",
+                6 => "bltu",
+                7 => "bgeu",
+                _ => return None,
+            };
+            d(name, b_imm)
+        }
+        0x6f => d("jal", j_imm),
+        0x67 if funct3 == 0 => d("jalr", i_imm),
+        0x03 => {
+            let name = match funct3 {
+                0 => "lb",
+                1 => "lh",
+                2 => "lw",
+                3 => "ld",
+                4 => "lbu",
+                5 => "lhu",
+                6 => "lwu",
+                _ => return None,
+            };
+            d(name, i_imm)
+        }
+        0x23 => {
+            let name = match funct3 {
+                0 => "sb",
+                1 => "sh",
+                2 => "sw",
+                3 => "sd",
+                _ => return None,
+            };
+            d(name, s_imm)
+        }
+        0x37 => d("lui", u_imm),
+        0x17 => d("auipc", u_imm),
+        0x0f if funct3 == 0 => d("fence", 0),
+        0x73 if x == 0x00000073 => d("ecall", 0),
+        0x73 if x == 0x00100073 => d("ebreak", 0),
+        0x73 if funct3 == 2 && rs1 == 0 => d("csrr", bits(x, 31, 20) as i64),
+        _ => None,
+    }
+}
+
+fn decode_compressed(x: u32) -> Option<Decoded> {
+    let rd = bits(x, 11, 7) as usize;
+    let rs2 = bits(x, 6, 2) as usize;
+    let rd_prime = 8 + bits(x, 4, 2) as usize; // rd' and rs2'
+    let rs1_prime = 8 + bits(x, 9, 7) as usize;
+    let imm6 = sext((bit(x, 12) << 5) | bits(x, 6, 2), 6);
+    let shamt = ((bit(x, 12) << 5) | bits(x, 6, 2)) as i64;
+    let lw_imm = ((bits(x, 12, 10) << 3) | (bit(x, 6) << 2) | (bit(x, 5) << 6)) as i64;
+    let ld_imm = ((bits(x, 12, 10) << 3) | (bits(x, 6, 5) << 6)) as i64;
+    let d = |name, rd, rs1, rs2, imm| Some(Decoded { name, rd, rs1, rs2, imm });
+
+    match (bits(x, 1, 0), bits(x, 15, 13)) {
+        (0, 0) => {
+            let imm = ((bits(x, 12, 11) << 4) | (bits(x, 10, 7) << 6) | (bit(x, 6) << 2) | (bit(x, 5) << 3)) as i64;
+            if imm == 0 { None } else { d("addi", rd_prime, SP, 0, imm) }
+        }
+        (0, 2) => d("lw", rd_prime, rs1_prime, 0, lw_imm),
+        (0, 3) => d("ld", rd_prime, rs1_prime, 0, ld_imm),
+        (0, 6) => d("sw", 0, rs1_prime, rd_prime, lw_imm),
+        (0, 7) => d("sd", 0, rs1_prime, rd_prime, ld_imm),
+        (0, _) => None, // c.fld, c.fsd, and reserved
+
+        (1, 0) => d("addi", rd, rd, 0, imm6),
+        (1, 1) if rd != 0 => d("addiw", rd, rd, 0, imm6),
+        (1, 1) => None,
+        (1, 2) => d("addi", rd, ZERO, 0, imm6),
+        (1, 3) if rd == SP => {
+            let imm = sext(
+                (bit(x, 12) << 9) | (bit(x, 6) << 4) | (bit(x, 5) << 6) | (bits(x, 4, 3) << 7) | (bit(x, 2) << 5),
+                10,
+            );
+            if imm == 0 { None } else { d("addi", SP, SP, 0, imm) }
+        }
+        (1, 3) => {
+            let imm = sext((bit(x, 12) << 17) | (bits(x, 6, 2) << 12), 18);
+            if imm == 0 { None } else { d("lui", rd, 0, 0, imm) }
+        }
+        (1, 4) => {
+            let (rd, rs2) = (rs1_prime, rd_prime);
+            match (bits(x, 11, 10), bit(x, 12), bits(x, 6, 5)) {
+                (0, _, _) => d("srli", rd, rd, 0, shamt),
+                (1, _, _) => d("srai", rd, rd, 0, shamt),
+                (2, _, _) => d("andi", rd, rd, 0, imm6),
+                (3, 0, 0) => d("sub", rd, rd, rs2, 0),
+                (3, 0, 1) => d("xor", rd, rd, rs2, 0),
+                (3, 0, 2) => d("or", rd, rd, rs2, 0),
+                (3, 0, 3) => d("and", rd, rd, rs2, 0),
+                (3, 1, 0) => d("subw", rd, rd, rs2, 0),
+                (3, 1, 1) => d("addw", rd, rd, rs2, 0),
+                _ => None,
+            }
+        }
+        (1, 5) => {
+            let imm = (bit(x, 12) << 11)
+                | (bit(x, 11) << 4)
+                | (bits(x, 10, 9) << 8)
+                | (bit(x, 8) << 10)
+                | (bit(x, 7) << 6)
+                | (bit(x, 6) << 7)
+                | (bits(x, 5, 3) << 1)
+                | (bit(x, 2) << 5);
+            d("jal", ZERO, 0, 0, sext(imm, 12))
+        }
+        (1, 6 | 7) => {
+            let imm = (bit(x, 12) << 8)
+                | (bits(x, 11, 10) << 3)
+                | (bits(x, 6, 5) << 6)
+                | (bits(x, 4, 3) << 1)
+                | (bit(x, 2) << 5);
+            let name = if bits(x, 15, 13) == 6 { "beq" } else { "bne" };
+            d(name, 0, rs1_prime, ZERO, sext(imm, 9))
+        }
+
+        (2, 0) => d("slli", rd, rd, 0, shamt),
+        (2, 2) if rd != 0 => {
+            d("lw", rd, SP, 0, ((bit(x, 12) << 5) | (bits(x, 6, 4) << 2) | (bits(x, 3, 2) << 6)) as i64)
+        }
+        (2, 3) if rd != 0 => {
+            d("ld", rd, SP, 0, ((bit(x, 12) << 5) | (bits(x, 6, 5) << 3) | (bits(x, 4, 2) << 6)) as i64)
+        }
+        (2, 4) => match (bit(x, 12), rd, rs2) {
+            (0, 0, 0) => None,
+            (0, _, 0) => d("jalr", ZERO, rd, 0, 0),
+            (0, _, _) => d("add", rd, ZERO, rs2, 0),
+            (_, 0, 0) => d("ebreak", 0, 0, 0, 0),
+            (_, _, 0) => d("jalr", RA, rd, 0, 0),
+            (_, _, _) => d("add", rd, rd, rs2, 0),
+        },
+        (2, 6) => d("sw", 0, SP, rs2, ((bits(x, 12, 9) << 2) | (bits(x, 8, 7) << 6)) as i64),
+        (2, 7) => d("sd", 0, SP, rs2, ((bits(x, 12, 10) << 3) | (bits(x, 9, 7) << 6)) as i64),
+        _ => None, // c.fldsp, c.fsdsp, and the reserved c.lwsp/c.ldsp
+    }
+}
+
+// the reference machine: registers, pc, and a copy of every segment
+struct Reference {
+    x: [i64; 32],
+    pc: i64,
+    segments: Vec<(i64, bool, Vec<u8>)>, // (start, writeable, bytes)
+}
+
+impl Reference {
+    fn segment(&mut self, addr: i64, size: usize, write: bool) -> Result<&mut [u8], String> {
+        for (start, writeable, bytes) in &mut self.segments {
+            let offset = addr.wrapping_sub(*start);
+            if offset >= 0 && offset as usize + size <= bytes.len() && (*writeable || !write) {
+                return Ok(&mut bytes[offset as usize..offset as usize + size]);
+            }
+        }
+        Err(format!("access fault at 0x{:x}", addr))
+    }
+
+    fn load(&mut self, addr: i64, size: usize, signed: bool) -> Result<i64, String> {
+        let bytes = self.segment(addr, size, false)?;
+        let mut value = 0u64;
+        for (i, &b) in bytes.iter().enumerate() {
+            value |= (b as u64) << (8 * i);
+        }
+        let shift = 64 - 8 * size as u32;
+        Ok(if signed { ((value << shift) as i64) >> shift } else { value as i64 })
+    }
+
+    fn store(&mut self, addr: i64, size: usize, value: i64) -> Result<(), String> {
+        let bytes = self.segment(addr, size, true)?;
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = (value >> (8 * i)) as u8;
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, inst: &Decoded, length: i64) -> Result<(), String> {
+        let a = self.x[inst.rs1];
+        let b = self.x[inst.rs2];
+        let imm = inst.imm;
+        let (a32, b32) = (a as i32, b as i32);
+        let w = |v: i32| v as i64;
+        let addr = a.wrapping_add(imm);
+        let mut next_pc = self.pc + length;
+
+        let result: Option<i64> = match inst.name {
+            "add" => Some(a.wrapping_add(b)),
+            "sub" => Some(a.wrapping_sub(b)),
+            "sll" => Some(a.wrapping_shl(b as u32 & 63)),
+            "slt" => Some((a < b) as i64),
+            "sltu" => Some(((a as u64) < (b as u64)) as i64),
+            "xor" => Some(a ^ b),
+            "srl" => Some(((a as u64) >> (b & 63)) as i64),
+            "sra" => Some(a >> (b & 63)),
+            "or" => Some(a | b),
+            "and" => Some(a & b),
+            "addw" => Some(w(a32.wrapping_add(b32))),
+            "subw" => Some(w(a32.wrapping_sub(b32))),
+            "sllw" => Some(w(a32.wrapping_shl(b as u32 & 31))),
+            "srlw" => Some(w(((a32 as u32) >> (b & 31)) as i32)),
+            "sraw" => Some(w(a32 >> (b & 31))),
+            "addi" => Some(a.wrapping_add(imm)),
+            "slti" => Some((a < imm) as i64),
+            "sltiu" => Some(((a as u64) < (imm as u64)) as i64),
+            "xori" => Some(a ^ imm),
+            "ori" => Some(a | imm),
+            "andi" => Some(a & imm),
+            "slli" => Some(a.wrapping_shl(imm as u32)),
+            "srli" => Some(((a as u64) >> imm) as i64),
+            "srai" => Some(a >> imm),
+            "addiw" => Some(w(a32.wrapping_add(imm as i32))),
+            "slliw" => Some(w(a32.wrapping_shl(imm as u32))),
+            "srliw" => Some(w(((a32 as u32) >> imm) as i32)),
+            "sraiw" => Some(w(a32 >> imm)),
+            "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" => {
+                let taken = match inst.name {
+                    "beq" => a == b,
+                    "bne" => a != b,
+                    "blt" => a < b,
+                    "bge" => a >= b,
+                    "bltu" => (a as u64) < (b as u64),
+                    _ => (a as u64) >= (b as u64),
+                };
+                if taken {
+                    next_pc = self.pc + imm;
+                }
+                None
+            }
+            "jal" => {
+                next_pc = self.pc + imm;
+                Some(self.pc + length)
+            }
+            "jalr" => {
+                next_pc = addr & !1;
+                Some(self.pc + length)
+            }
+            "lb" => Some(self.load(addr, 1, true)?),
+            "lh" => Some(self.load(addr, 2, true)?),
+            "lw" => Some(self.load(addr, 4, true)?),
+            "ld" => Some(self.load(addr, 8, true)?),
+            "lbu" => Some(self.load(addr, 1, false)?),
+            "lhu" => Some(self.load(addr, 2, false)?),
+            "lwu" => Some(self.load(addr, 4, false)?),
+            "sb" | "sh" | "sw" | "sd" => {
+                let size = match inst.name {
+                    "sb" => 1,
+                    "sh" => 2,
+                    "sw" => 4,
+                    _ => 8,
+                };
+                self.store(addr, size, b)?;
+                None
+            }
+            "lui" => Some(imm),
+            "auipc" => Some(self.pc.wrapping_add(imm)),
+            "fence" => None,
+            "csrr" if imm == 0xf14 => Some(0),
+            "csrr" => return Err(format!("unsupported csr 0x{:x}", imm)),
+            "mul" => Some(a.wrapping_mul(b)),
+            "mulh" => Some(((a as i128 * b as i128) >> 64) as i64),
+            "mulhsu" => Some(((a as i128 * b as u64 as i128) >> 64) as i64),
+            "mulhu" => Some(((a as u64 as u128 * b as u64 as u128) >> 64) as i64),
+            "div" => Some(if b == 0 { -1 } else { a.wrapping_div(b) }),
+            "divu" => Some(if b == 0 { -1 } else { ((a as u64) / (b as u64)) as i64 }),
+            "rem" => Some(if b == 0 { a } else { a.wrapping_rem(b) }),
+            "remu" => Some(if b == 0 { a } else { ((a as u64) % (b as u64)) as i64 }),
+            "mulw" => Some(w(a32.wrapping_mul(b32))),
+            "divw" => Some(w(if b32 == 0 { -1 } else { a32.wrapping_div(b32) })),
+            "divuw" => Some(w(if b32 == 0 { -1 } else { ((a32 as u32) / (b32 as u32)) as i32 })),
+            "remw" => Some(w(if b32 == 0 { a32 } else { a32.wrapping_rem(b32) })),
+            "remuw" => Some(w(if b32 == 0 { a32 } else { ((a32 as u32) % (b32 as u32)) as i32 })),
+            name => unreachable!("{name} is decoded but not executed"),
+        };
+
+        if let Some(value) = result
+            && inst.rd != 0
+        {
+            self.x[inst.rd] = value;
+        }
+        self.pc = next_pc;
+        Ok(())
+    }
+}
+
+fn mnemonic(op: &Op) -> String {
+    match op.to_fields().first() {
+        Some(Field::Opcode(name)) => name.to_string(),
+        _ => String::new(),
+    }
+}
+
+// run one case; returns a description of any disagreement
+fn run_case(m: &mut Machine, rng: &mut Rng, form: usize) -> Result<(), String> {
+    let (_, mask, matches, compressed) = FORMS[form];
+    let mut inst = (rng.next() as u32 & !mask) | matches;
+    if compressed {
+        inst &= 0xffff;
+        if inst & 3 == 3 {
+            inst &= !1; // random compressed bits must stay compressed
+        }
+    }
+    let raw = if compressed { inst as u16 as i16 as i32 } else { inst as i32 };
+    let length = if compressed { 2 } else { 4 };
+    let op = Op::new(raw);
+    let expected = decode(inst);
+    let shown = if compressed { format!("0x{:04x}", inst) } else { format!("0x{:08x}", inst) };
+
+    // decoding
+    let decoded = match (&op, &expected) {
+        (Op::Unimplemented { .. }, None) => return Ok(()),
+        (Op::Unimplemented { note, .. }, Some(d)) => {
+            return Err(format!("{shown}: expected {} but decoded as unimplemented ({note})", d.name));
+        }
+        (_, None) => return Err(format!("{shown}: expected an illegal instruction but decoded as {}", mnemonic(&op))),
+        (_, Some(d)) if mnemonic(&op) != d.name => {
+            return Err(format!("{shown}: expected {} but decoded as {}", d.name, mnemonic(&op)));
+        }
+        (_, Some(d)) => d,
+    };
+
+    // encoding must round-trip through the decoder, in both sizes
+    match op.encode() {
+        Ok(bits) if Op::new(bits) == op => {}
+        Ok(bits) => {
+            return Err(format!("{shown} ({}): encodes as 0x{:08x}, which decodes differently", decoded.name, bits));
+        }
+        Err(e) => return Err(format!("{shown} ({}): does not encode: {e}", decoded.name)),
+    }
+    if let Some(bits) = op.encode_compressed()
+        && Op::new(bits) != op
+    {
+        return Err(format!("{shown} ({}): compresses to 0x{:04x}, which decodes differently", decoded.name, bits));
+    }
+
+    if matches!(decoded.name, "ecall" | "ebreak") {
+        return Ok(());
+    }
+
+    // random machine state, with memory accesses usually landing in the data segment
+    m.reset();
+    for segment in &mut m.segments {
+        if segment.writeable {
+            segment.mem.iter_mut().for_each(|b| *b = rng.next() as u8);
+        }
+    }
+    for reg in 1..32 {
+        m.x[reg] = rng.value();
+    }
+    let is_memory =
+        matches!(decoded.name, "lb" | "lh" | "lw" | "ld" | "lbu" | "lhu" | "lwu" | "sb" | "sh" | "sw" | "sd");
+    if is_memory && decoded.rs1 != 0 && rng.below(8) != 0 {
+        let target = DATA_START + rng.below(REGION_SIZE as u64 - 8) as i64;
+        m.x[decoded.rs1] = target.wrapping_sub(decoded.imm);
+    }
+    m.pc = TEXT_START + 2 * rng.below(REGION_SIZE as u64 / 2) as i64;
+
+    let mut reference = Reference {
+        x: m.x,
+        pc: m.pc,
+        segments: m.segments.iter().map(|segment| (segment.start, segment.writeable, segment.mem.clone())).collect(),
+    };
+    let before = m.x;
+
+    let expected_result = reference.execute(decoded, length);
+    m.pc_written = false;
+    let result = op.execute(m, length);
+    if !m.pc_written {
+        m.pc += length;
+    }
+
+    let state = || {
+        let regs: Vec<String> = (1..32).map(|r| format!("{}=0x{:x}", R[r], before[r])).collect();
+        regs.join(" ")
+    };
+    match (&expected_result, &result) {
+        (Err(_), Err(_)) => return Ok(()),
+        (Ok(()), Err(e)) => return Err(format!("{shown} ({}): unexpected fault: {e}\n    {}", decoded.name, state())),
+        (Err(e), Ok(())) => return Err(format!("{shown} ({}): expected fault: {e}\n    {}", decoded.name, state())),
+        (Ok(()), Ok(())) => {}
+    }
+
+    if let Some(reg) = (0..32).find(|&reg| m.x[reg] != reference.x[reg]) {
+        return Err(format!(
+            "{shown} ({}): {} is 0x{:x}, expected 0x{:x}\n    {}",
+            decoded.name,
+            R[reg],
+            m.x[reg],
+            reference.x[reg],
+            state()
+        ));
+    }
+    if m.pc != reference.pc {
+        return Err(format!("{shown} ({}): pc is 0x{:x}, expected 0x{:x}", decoded.name, m.pc, reference.pc));
+    }
+    for (segment, (_, _, bytes)) in m.segments.iter().zip(&reference.segments) {
+        if segment.mem != *bytes {
+            return Err(format!("{shown} ({}): memory differs at segment 0x{:x}", decoded.name, segment.start));
+        }
+    }
+    Ok(())
+}
+
+pub fn selftest(cases: usize, seed: Option<u64>) -> Result<(), String> {
+    let segments = vec![
+        Segment::new(".text", TEXT_START, TEXT_START + REGION_SIZE as i64, false, true, Vec::new()),
+        Segment::new(".data", DATA_START, DATA_START + REGION_SIZE as i64, true, false, Vec::new()),
+    ];
+    let mut m = Machine::new(
+        segments,
+        TEXT_START,
+        0,
+        HashMap::new(),
+        HashMap::new(),
+        HashMap::new(),
+        HashSet::new(),
+        HashSet::new(),
+        1,
+    );
+    let mut rng = Rng(seed.unwrap_or(1).wrapping_mul(0x9e3779b97f4a7c15) | 1);
+
+    let mut failures = vec![0; FORMS.len()];
+    let mut shown = 0;
+    for case in 0..cases {
+        let form = case % FORMS.len();
+        if let Err(msg) = run_case(&mut m, &mut rng, form) {
+            failures[form] += 1;
+            if shown < 20 {
+                eprintln!("{}: {}", FORMS[form].0, msg);
+                shown += 1;
+            }
+        }
+    }
+
+    let failed: usize = failures.iter().sum();
+    for (form, &n) in failures.iter().enumerate().filter(|(_, n)| **n > 0) {
+        eprintln!("  {:<20} {} failed", FORMS[form].0, n);
+    }
+    if failed > 0 {
+        return Err(format!("selftest: {} of {} cases failed", failed, cases));
+    }
+    eprintln!("selftest: {} cases over {} instruction forms passed", cases, FORMS.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoder_and_executor_match_reference() {
+        assert_eq!(selftest(20000, None), Ok(()));
+    }
+
+    #[test]
+    fn decoder_and_executor_match_reference_with_another_seed() {
+        assert_eq!(selftest(20000, Some(0x5eed)), Ok(()));
+    }
+}
@@ -0,0 +1,248 @@
+use super::*;
+
+// A lint pass over the code without running it, so it also covers the paths
+// a run never takes. It walks the control-flow graph of each function (see
+// cfg.rs) and reports:
+//
+// *   code that runs off the end of a function into one that is called
+// *   s registers written before they are saved on the stack
+// *   sp differing between paths that meet, or not restored at a ret
+// *   ret after a call without reloading ra
+// *   code that no path from the start of its function reaches
+//
+// Only functions that something calls are held to the calling
+// convention; the entry point and .globl labels that are only jumped to are
+// checked for unreachable code alone.
+
+// what holds on every path to a point in a called function
+#[derive(Clone, PartialEq)]
+struct State {
+    saved: u32,      // s registers stored to the stack, by register number
+    ra_valid: bool,  // ra still holds (or again holds) the return address
+    sp: Option<i64>, // sp relative to its value at entry, if known
+}
+
+impl State {
+    // combine the states of two paths, returning the two sp values if they disagree
+    fn merge(&self, other: &State) -> (State, Option<(i64, i64)>) {
+        let (sp, conflict) = match (self.sp, other.sp) {
+            (Some(a), Some(b)) if a == b => (Some(a), None),
+            (Some(a), Some(b)) => (None, Some((a, b))),
+            _ => (None, None),
+        };
+        (State { saved: self.saved & other.saved, ra_valid: self.ra_valid && other.ra_valid, sp }, conflict)
+    }
+}
+
+// the register an instruction writes; for every instruction that has a
+// destination register, it is the first operand
+fn destination(op: &Op) -> Option<usize> {
+    if is_conditional_branch(op) || memory_operands(op).is_some_and(|(_, _, _, is_store)| is_store) {
+        return None;
+    }
+    match op.to_fields().get(1) {
+        Some(&Field::Reg(rd)) if rd != ZERO => Some(rd),
+        _ => None,
+    }
+}
+
+// a block that ends with the exit system call
+fn exits(lines: &[Vec<Rc<Instruction>>]) -> bool {
+    let mut instructions = lines.iter().flatten().rev();
+    matches!(instructions.next().map(|inst| &inst.op), Some(Op::Ecall))
+        && instructions
+            .find(|inst| destination(&inst.op) == Some(17))
+            .is_some_and(|inst| inst.op == Op::Addi { rd: 17, rs1: ZERO, imm: 93 })
+}
+
+// nops or zeros between functions for alignment
+fn is_padding(lines: &[Vec<Rc<Instruction>>]) -> bool {
+    lines
+        .iter()
+        .flatten()
+        .all(|inst| matches!(inst.op, Op::Addi { rd: ZERO, rs1: ZERO, imm: 0 } | Op::Unimplemented { .. }))
+}
+
+fn offset(sp: i64) -> String {
+    match sp {
+        0 => "where it started".to_string(),
+        n if n < 0 => format!("{} bytes below where it started", -n),
+        n => format!("{} bytes above where it started", n),
+    }
+}
+
+// apply a block to the state at its start, noting problems along the way
+fn transfer(
+    lines: &[Vec<Rc<Instruction>>],
+    state: &State,
+    names: RegisterNames,
+    problems: &mut Vec<(i64, String)>,
+) -> State {
+    let mut state = state.clone();
+    for inst in lines.iter().flatten() {
+        if let Some((base, _, reg, true)) = memory_operands(&inst.op)
+            && (base == SP || base == 8)
+            && S_REGS.contains(&reg)
+        {
+            state.saved |= 1 << reg;
+        }
+        if is_return(&inst.op) {
+            if !state.ra_valid {
+                problems.push((inst.address, "ret after a call without restoring ra".to_string()));
+            }
+            if let Some(sp) = state.sp
+                && sp != 0
+            {
+                problems.push((inst.address, format!("ret with sp {}", offset(sp))));
+            }
+        }
+        match destination(&inst.op) {
+            Some(rd) if S_REGS.contains(&rd) && state.saved & (1 << rd) == 0 => {
+                problems.push((inst.address, format!("writes {} without saving it first", names.name(rd))));
+                state.saved |= 1 << rd; // once per path is enough
+            }
+            Some(SP) => {
+                state.sp = match inst.op {
+                    Op::Addi { rd: SP, rs1: SP, imm } | Op::Addiw { rd: SP, rs1: SP, imm } => {
+                        state.sp.map(|sp| sp + imm)
+                    }
+                    _ => None,
+                }
+            }
+            Some(RA) => state.ra_valid = !is_call(&inst.op),
+            _ => {}
+        }
+    }
+    state
+}
+
+pub fn static_lint(m: &Machine, instructions: &[Rc<Instruction>]) -> Vec<String> {
+    let (functions, _) = m.named_symbols();
+    let graph = ControlFlowGraph::new(instructions, "", None);
+    let blocks = graph.blocks(&functions);
+    let edges = graph.edges(&blocks);
+    let start = |b: usize| graph.lines(&blocks[b])[0][0].address;
+
+    // functions with a call that links through ra; other link registers are
+    // just a way to read the pc
+    let mut called = HashSet::new();
+    for line in instructions.chunk_by(|a, b| a.pseudo_index == b.pseudo_index) {
+        if link_register(&line.last().unwrap().op) == Some(RA)
+            && let Some(target) = static_target(line)
+            && let Some(f) = functions.iter().position(|&(address, _)| address == target)
+        {
+            called.insert(f);
+        }
+    }
+
+    // edges within each function, minus running on after exit
+    let mut successors = vec![Vec::new(); blocks.len()];
+    for &(from, to, kind) in edges.keys() {
+        if kind != EdgeKind::Call
+            && blocks[from].function == blocks[to].function
+            && !(kind == EdgeKind::Fallthrough && exits(graph.lines(&blocks[from])))
+        {
+            successors[from].push(to);
+        }
+    }
+
+    let mut problems = Vec::new();
+    for f in 0..functions.len() {
+        let members: Vec<usize> = (0..blocks.len()).filter(|&b| blocks[b].function == f).collect();
+        let (Some(&entry), Some(&end)) = (members.first(), members.last()) else { continue };
+
+        // unreachable code, unless a jump through a register could go anywhere
+        let indirect = members.iter().flat_map(|&b| graph.lines(&blocks[b])).any(|line| {
+            let last = line.last().unwrap();
+            matches!(last.op, Op::Jalr { rd: ZERO, .. }) && !is_return(&last.op) && static_target(line).is_none()
+        });
+        let mut reached: HashSet<usize> =
+            if indirect { members.iter().copied().collect() } else { HashSet::from([entry]) };
+        let mut pending = vec![entry];
+        while let Some(b) = pending.pop() {
+            for &to in &successors[b] {
+                if reached.insert(to) {
+                    pending.push(to);
+                }
+            }
+        }
+        let mut previous_reached = true;
+        for &b in &members {
+            let padding = is_padding(graph.lines(&blocks[b]));
+            if !reached.contains(&b) && !padding && previous_reached {
+                problems.push((start(b), "unreachable code".to_string()));
+            }
+            previous_reached = reached.contains(&b) || padding;
+        }
+
+        // falling off the end, past any padding, into a function that is called
+        let code_end = members.iter().rev().find(|&&b| !is_padding(graph.lines(&blocks[b]))).copied().unwrap_or(end);
+        let last = graph.lines(&blocks[code_end]).last().unwrap().last().unwrap();
+        if let Some(next) = blocks.get(end + 1)
+            && called.contains(&next.function)
+            && reached.contains(&code_end)
+            && (!is_jump(&last.op) || is_call(&last.op))
+            && !exits(graph.lines(&blocks[code_end]))
+        {
+            problems.push((last.address, format!("falls through into {}", functions[next.function].1)));
+        }
+
+        // the calling convention, for functions that are called
+        if !called.contains(&f) {
+            continue;
+        }
+        let mut states: Vec<Option<State>> = vec![None; blocks.len()];
+        let mut conflicts: BTreeMap<usize, (i64, i64)> = BTreeMap::new();
+        states[entry] = Some(State { saved: 0, ra_valid: true, sp: Some(0) });
+        let mut pending = vec![entry];
+        while let Some(b) = pending.pop() {
+            let out =
+                transfer(graph.lines(&blocks[b]), states[b].as_ref().unwrap(), m.style.registers, &mut Vec::new());
+            for &to in &successors[b] {
+                let merged = match &states[to] {
+                    None => out.clone(),
+                    Some(old) => {
+                        let (merged, conflict) = old.merge(&out);
+                        if let Some(conflict) = conflict {
+                            conflicts.entry(to).or_insert(conflict);
+                        }
+                        merged
+                    }
+                };
+                if states[to].as_ref() != Some(&merged) {
+                    states[to] = Some(merged);
+                    pending.push(to);
+                }
+            }
+        }
+
+        let mut found = Vec::new();
+        for &b in &members {
+            if let Some(state) = &states[b] {
+                transfer(graph.lines(&blocks[b]), state, m.style.registers, &mut found);
+            }
+        }
+        for (b, (x, y)) in conflicts {
+            found.push((start(b), format!("paths meet here with sp moved by different amounts ({:+} and {:+})", x, y)));
+        }
+
+        // each s register only once per function
+        let mut reported = HashSet::new();
+        found.sort_by_key(|&(address, _)| address);
+        found.dedup();
+        for (address, message) in found {
+            if !message.starts_with("writes ") || reported.insert(message.clone()) {
+                problems.push((address, message));
+            }
+        }
+    }
+
+    problems.sort_by_key(|&(address, _)| address);
+    problems
+        .into_iter()
+        .map(|(address, message)| match m.function_at(address) {
+            Some(function) => format!("0x{:x} {}: {}", address, function.name, message),
+            None => format!("0x{:x}: {}", address, message),
+        })
+        .collect()
+}
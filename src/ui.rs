@@ -22,7 +22,7 @@ pub struct Tui {
     instructions: Vec<Rc<Instruction>>,
     addresses: HashMap<i64, usize>,
     pseudo_addresses: HashMap<usize, usize>,
-    sequence: Vec<Effects>,
+    history: History,
     sequence_index: usize,
     cursor_index: usize,
 
@@ -30,6 +30,7 @@ pub struct Tui {
     inactive_stack_color: Colors,
     current_pc_color: Colors,
     cursor_color: Colors,
+    heat_colors: Vec<Colors>,
     data_colors: Vec<(i64, Colors)>,
     pastels: Vec<Colors>,
 
@@ -41,6 +42,18 @@ pub struct Tui {
     show_help: bool,
     verbose: bool,
     show_addresses: bool,
+    show_pipeline: bool,
+    show_profile: bool,
+    show_heat: bool,
+    show_encoding: bool,
+    explain: bool,
+    selected_hart: Option<usize>,
+
+    // simulations shown alongside the trace
+    caches: Option<Replayed<Caches, Hits>>,
+    predictors: Option<Replayed<Vec<Predictor>, Option<bool>>>,
+    profile: Option<Vec<String>>, // flat profile of the whole run, computed when first shown
+    heat: Option<Vec<usize>>,     // times each instruction ran, computed when first shown
 }
 
 impl Tui {
@@ -49,8 +62,11 @@ impl Tui {
         instructions: Vec<Rc<Instruction>>,
         addresses: HashMap<i64, usize>,
         pseudo_addresses: HashMap<usize, usize>,
-        sequence: Vec<Effects>,
+        history: History,
+        caches: Caches,
+        predictor: Option<Predictor>,
     ) -> Result<Self, String> {
+        let hart_count = machine.harts.len();
         // make sure stdout is connected to a tty
         if !io::stdout().is_tty() {
             return Err("debug mode only works in an interactive terminal/tty".to_string());
@@ -72,6 +88,17 @@ impl Tui {
         // cursor if != pc (black text on Grey53)
         let cursor_color = Colors::new(black, Color::AnsiValue(102));
 
+        // execution heat map: never run (grey text on black), then white text
+        // on darkest to brightest red as lines run more often
+        let heat_colors = vec![
+            Colors::new(Color::AnsiValue(240), black),
+            Colors::new(white, Color::AnsiValue(52)),
+            Colors::new(white, Color::AnsiValue(88)),
+            Colors::new(white, Color::AnsiValue(124)),
+            Colors::new(white, Color::AnsiValue(160)),
+            Colors::new(white, Color::AnsiValue(196)),
+        ];
+
         // memory chunks, with pastel foreground colors
         // the are all saturation 20%, lightness 60%, with various hues
         let pastels = vec![
@@ -107,7 +134,7 @@ impl Tui {
             instructions,
             addresses,
             pseudo_addresses,
-            sequence,
+            history,
             sequence_index: 0,
             cursor_index: 0,
 
@@ -115,6 +142,7 @@ impl Tui {
             inactive_stack_color,
             current_pc_color,
             cursor_color,
+            heat_colors,
             data_colors,
             pastels,
 
@@ -126,6 +154,17 @@ impl Tui {
             show_help: false,
             verbose: false,
             show_addresses: false,
+            show_pipeline: false,
+            show_profile: false,
+            show_heat: false,
+            show_encoding: false,
+            explain: false,
+            selected_hart: None,
+            caches: if caches.is_enabled() { Some(Replayed::new(caches)) } else { None },
+            predictors: predictor
+                .map(|predictor| Replayed::new(vec![predictor.with_bits_at_most(TUI_PREDICTOR_BITS); hart_count])),
+            profile: None,
+            heat: None,
         })
     }
 
@@ -133,10 +172,10 @@ impl Tui {
         loop {
             // Draw the current state
             let source_height = self.draw()?;
-            if let Event::Key(key_event) = serr!(event::read())? {
-                if self.handle_key(key_event, source_height)? {
-                    break;
-                }
+            if let Event::Key(key_event) = serr!(event::read())?
+                && self.handle_key(key_event, source_height)?
+            {
+                break;
             }
         }
         Ok(())
@@ -147,6 +186,9 @@ impl Tui {
             _ if self.show_help => {
                 self.show_help = false;
             }
+            _ if self.show_profile => {
+                self.show_profile = false;
+            }
 
             // cursor motion
             KeyCode::Up => {
@@ -186,101 +228,91 @@ impl Tui {
             }
 
             // stepping and jumping
-            KeyCode::Left => {
-                if self.sequence_index > 0 {
-                    self.sequence_index -= 1;
-                    self.machine.apply(&self.sequence[self.sequence_index], false);
-                    self.machine.set_most_recent_memory(&self.sequence, self.sequence_index);
-                    self.set_cursor_to_current();
-                }
+            KeyCode::Left if self.sequence_index > 0 => {
+                self.jump_to(self.sequence_index - 1);
             }
-            KeyCode::Right => {
-                if self.sequence_index + 1 < self.sequence.len() {
-                    self.machine.apply(&self.sequence[self.sequence_index], true);
-                    self.sequence_index += 1;
-                    self.machine.set_most_recent_memory(&self.sequence, self.sequence_index);
-                    self.set_cursor_to_current();
-                }
+            KeyCode::Right if self.sequence_index + 1 < self.history.len() => {
+                self.jump_to(self.sequence_index + 1);
             }
             KeyCode::Home => {
                 // jump back to where the current function was entered
                 self.set_cursor_to_current();
-                let (func_start_pc, func_end_pc) =
-                    find_function_bounds(&self.machine.address_symbols, &self.instructions, self.cursor_index);
-                let mut first = true;
-                while self.sequence_index > 0 {
-                    let effects = &self.sequence[self.sequence_index];
-                    if first {
-                        first = false;
-                    } else {
-                        self.machine.apply(effects, false);
-                    }
-
+                let (func_start_pc, func_end_pc) = self.function_bounds();
+                let hart = self.history.get(self.sequence_index).hart;
+                let mut target = self.sequence_index;
+                while target > 0 {
+                    // with multiple harts, only consider the steps of the current one
+                    let effects = self.history.get(target);
                     let pc = effects.instruction.address;
-                    if func_start_pc <= pc && pc < func_end_pc && self.sequence_index > 0 {
-                        let prev_effects = &self.sequence[self.sequence_index - 1];
+                    if effects.hart == hart
+                        && func_start_pc <= pc
+                        && pc < func_end_pc
+                        && let Some(prev) = (0..target).rev().find(|&i| self.history.get(i).hart == hart)
+                    {
+                        let prev_effects = self.history.get(prev);
                         let (prev_pc, _) = prev_effects.pc;
-                        if prev_pc < func_start_pc || prev_pc > func_end_pc {
+                        if prev_pc < func_start_pc || prev_pc >= func_end_pc {
                             match prev_effects.instruction.op {
                                 Op::Jal { rd, .. } | Op::Jalr { rd, .. } if rd != ZERO => break,
                                 _ => {}
                             }
                         }
                     }
-                    self.sequence_index -= 1;
+                    target -= 1;
                 }
-                self.machine.set_most_recent_memory(&self.sequence, self.sequence_index);
-                self.set_cursor_to_current();
+                self.jump_to(target);
             }
             KeyCode::End => {
                 // jump forward to where the current function is about to exit
                 self.set_cursor_to_current();
-                let (func_start_pc, func_end_pc) =
-                    find_function_bounds(&self.machine.address_symbols, &self.instructions, self.cursor_index);
-                while self.sequence_index < self.sequence.len() - 1 {
-                    let effects = &self.sequence[self.sequence_index];
+                let (func_start_pc, func_end_pc) = self.function_bounds();
+                let hart = self.history.get(self.sequence_index).hart;
+                let mut target = self.sequence_index;
+                while target < self.history.len() - 1 {
+                    let effects = self.history.get(target);
                     let pc = effects.instruction.address;
-                    if let Op::Jalr { rd: ZERO, rs1: RA, offset: 0 } = effects.instruction.op {
-                        if func_start_pc <= pc && pc < func_end_pc {
-                            break;
-                        }
+                    if let Op::Jalr { rd: ZERO, rs1: RA, offset: 0 } = effects.instruction.op
+                        && effects.hart == hart
+                        && func_start_pc <= pc
+                        && pc < func_end_pc
+                    {
+                        break;
                     };
-                    self.machine.apply(effects, true);
-                    self.sequence_index += 1;
+                    target += 1;
                 }
-                self.machine.set_most_recent_memory(&self.sequence, self.sequence_index);
-                self.set_cursor_to_current();
+                self.jump_to(target);
             }
             KeyCode::Enter => {
                 // jump forward to the next time this line is reached
                 let target_pc = self.instructions[self.cursor_index].address;
-                for peek in self.sequence_index + 1..self.sequence.len() {
-                    if self.sequence[peek].instruction.address == target_pc {
-                        // play the sequence forward to this point
-                        while self.sequence_index < peek {
-                            self.machine.apply(&self.sequence[self.sequence_index], true);
-                            self.sequence_index += 1;
-                        }
-                        self.machine.set_most_recent_memory(&self.sequence, self.sequence_index);
-                        self.set_cursor_to_current();
-                        break;
-                    }
+                if let Some(peek) = (self.sequence_index + 1..self.history.len())
+                    .find(|&peek| self.history.get(peek).instruction.address == target_pc)
+                {
+                    self.jump_to(peek);
                 }
             }
             KeyCode::Backspace => {
                 // find the previous time the pc was at this address
                 let target_pc = self.instructions[self.cursor_index].address;
-                for peek in (0..self.sequence_index).rev() {
-                    if self.sequence[peek].instruction.address == target_pc {
-                        // play the sequence backward to this point
-                        while self.sequence_index > peek {
-                            self.sequence_index -= 1;
-                            self.machine.apply(&self.sequence[self.sequence_index], false);
-                        }
-                        self.machine.set_most_recent_memory(&self.sequence, self.sequence_index);
-                        self.set_cursor_to_current();
-                        break;
-                    }
+                if let Some(peek) =
+                    (0..self.sequence_index).rev().find(|&peek| self.history.get(peek).instruction.address == target_pc)
+                {
+                    self.jump_to(peek);
+                }
+            }
+
+            KeyCode::Char('b') => {
+                // fast forward to the next ebreak bookmark
+                if let Some(peek) =
+                    (self.sequence_index + 1..self.history.len()).find(|&peek| is_bookmark(self.history.get(peek)))
+                {
+                    self.jump_to(peek);
+                }
+            }
+            KeyCode::Char('B') => {
+                // rewind to the previous ebreak bookmark
+                if let Some(peek) = (0..self.sequence_index).rev().find(|&peek| is_bookmark(self.history.get(peek))) {
+                    self.jump_to(peek);
                 }
             }
 
@@ -316,6 +348,49 @@ impl Tui {
                 self.show_addresses = !self.show_addresses;
             }
 
+            KeyCode::Char('p') => {
+                self.show_pipeline = !self.show_pipeline;
+            }
+
+            KeyCode::Char('c') => {
+                if self.heat.is_none() {
+                    let mut heat = vec![0; self.instructions.len()];
+                    for i in 0..self.history.len() {
+                        heat[self.addresses[&self.history.get(i).instruction.address]] += 1;
+                    }
+                    self.heat = Some(heat);
+                }
+                self.show_heat = !self.show_heat;
+            }
+
+            KeyCode::Char('e') => {
+                self.show_encoding = !self.show_encoding;
+            }
+
+            KeyCode::Char('i') => {
+                self.explain = !self.explain;
+            }
+
+            KeyCode::Char('f') => {
+                if self.profile.is_none() {
+                    let mut profile = Profile::new(&self.machine);
+                    for i in 0..self.history.len() {
+                        profile.step(self.history.get(i));
+                    }
+                    self.profile = Some(profile.flat());
+                }
+                self.show_profile = true;
+            }
+
+            KeyCode::Char('h') => {
+                // cycle through following the running hart and pinning each hart
+                self.selected_hart = match self.selected_hart {
+                    None => Some(0),
+                    Some(hart) if hart + 1 < self.machine.harts.len() => Some(hart + 1),
+                    Some(_) => None,
+                };
+            }
+
             KeyCode::Char('q') => {
                 return Ok(true);
             }
@@ -326,8 +401,27 @@ impl Tui {
         Ok(false)
     }
 
+    // move the machine to the state before the given step
+    fn jump_to(&mut self, target: usize) {
+        self.history.seek(&mut self.machine, self.sequence_index, target);
+        self.sequence_index = target;
+        self.machine.set_most_recent_memory(&mut self.history, self.sequence_index);
+        self.set_cursor_to_current();
+    }
+
     fn set_cursor_to_current(&mut self) {
-        self.cursor_index = self.addresses[&self.sequence[self.sequence_index].instruction.address];
+        self.cursor_index = self.addresses[&self.history.get(self.sequence_index).instruction.address];
+    }
+
+    // the function under the cursor, or all of the code outside any function
+    fn function_bounds(&self) -> (i64, i64) {
+        match self.machine.function_at(self.instructions[self.cursor_index].address) {
+            Some(function) => (function.start, function.end),
+            None => {
+                let last = self.instructions.last().unwrap();
+                (self.instructions[0].address, last.address + last.length)
+            }
+        }
     }
 
     fn draw(&mut self) -> Result<u16, String> {
@@ -336,6 +430,13 @@ impl Tui {
             return Ok(0);
         }
 
+        // show the state of the selected hart, or else the one about to run
+        let hart = self.selected_hart.unwrap_or(self.history.get(self.sequence_index).hart);
+        if hart != self.machine.hart {
+            self.machine.switch_hart(hart);
+            self.machine.set_most_recent_memory(&mut self.history, self.sequence_index);
+        }
+
         // build the screen layout
         let mut out = Vec::new();
         for _ in 0..size_y {
@@ -344,6 +445,17 @@ impl Tui {
         let mut corners = Vec::new();
         let mut source = Pane::new(out, self.normal_color, 0, 0, size_x, size_y, true);
 
+        // the encoding pane spans the full width so the widest formats fit;
+        // it is taller when there are compressed instructions to expand
+        let encoding_lines = if self.instructions.iter().any(|inst| inst.length == 2) { 6 } else { 3 };
+        let encoding = if self.show_encoding && source.height > 12 + encoding_lines {
+            let mut encoding = source.split_bottom(encoding_lines, true, &mut corners);
+            source.out = take(&mut encoding.out);
+            Some(encoding)
+        } else {
+            None
+        };
+
         // an 80-column terminal gets source and memory views, narrower does not
         let (stack, data, out) = if size_x >= 80 && (self.show_stack || self.show_data && self.machine.data_start > 0) {
             let mut mem = source.split_right(39, false, &mut corners);
@@ -382,7 +494,7 @@ impl Tui {
                 // so min source height is 12 before allowing other panes
                 let surplus = source.height.saturating_sub(12).saturating_sub(10);
                 let output_min = 4;
-                let natural_size = output_min + (surplus + 2) / 3;
+                let natural_size = output_min + surplus.div_ceil(3);
 
                 // keep at least 4 lines but otherwise don't grow beyond the amount of output
                 let lines = output_lines.max(output_min).min(natural_size);
@@ -396,7 +508,7 @@ impl Tui {
                 // output claims any extra lines that would have gone to registers
                 let output_min = 4 + surplus.min(5);
                 let surplus = surplus.saturating_sub(5);
-                let natural_size = output_min + (surplus + 2) / 3;
+                let natural_size = output_min + surplus.div_ceil(3);
                 let lines = output_lines.max(4).min(natural_size);
                 let mut output = source.split_bottom(lines, true, &mut corners);
                 let out = take(&mut output.out);
@@ -441,9 +553,14 @@ impl Tui {
             self.render_output(&mut output);
             out = take(&mut output.out);
         }
+        if let Some(mut encoding) = encoding {
+            encoding.out = out;
+            self.render_encoding(&mut encoding);
+            out = take(&mut encoding.out);
+        }
 
         if self.show_help {
-            let (help_x, help_y) = (63, 17);
+            let (help_x, help_y) = (63, 24);
             let (left, width) = if size_x >= help_x + 2 {
                 let space = (size_x - (help_x + 2)) / 2;
                 (space, help_x + 2)
@@ -461,6 +578,19 @@ impl Tui {
             out = take(&mut help.out);
         }
 
+        if self.show_profile {
+            let lines = self.profile.as_ref().unwrap();
+            let profile_x = lines.iter().map(|line| line.len() as u16 + 2).max().unwrap_or(0);
+            let profile_y = lines.len() as u16;
+            let (left, width) =
+                if size_x >= profile_x + 2 { ((size_x - (profile_x + 2)) / 2, profile_x + 2) } else { (0, size_x) };
+            let (top, height) =
+                if size_y >= profile_y + 2 { ((size_y - (profile_y + 2)) / 2, profile_y + 2) } else { (0, size_y) };
+            let mut profile = Pane::new(out, self.normal_color, left, top, width, height, true);
+            self.render_profile(&mut profile);
+            out = take(&mut profile.out);
+        }
+
         let mut stdout = io::stdout();
         for (y, row) in out.iter().enumerate() {
             serr!(queue!(stdout, MoveTo(0, y as u16)))?;
@@ -478,18 +608,46 @@ impl Tui {
         Ok(source.height)
     }
 
-    fn render_source(&self, pane: &mut Pane) -> String {
+    fn render_source(&mut self, pane: &mut Pane) -> String {
+        // did the previous step hit in the simulated caches?
+        let mut cache_label = String::new();
+        if let Some(caches) = &mut self.caches
+            && self.sequence_index > 0
+        {
+            let (fetch, data) =
+                caches.outcome(&mut self.history, self.sequence_index - 1, |caches, effects| caches.step(effects));
+            let outcome = |hit| if hit { "hit" } else { "miss" };
+            if let Some(hit) = fetch {
+                write!(cache_label, " I$:{}", outcome(hit)).unwrap();
+            }
+            if let Some(hit) = data {
+                write!(cache_label, " D$:{}", outcome(hit)).unwrap();
+            }
+        }
+
+        // did the branch predictor get the current step right?
+        let arrow_color = self.predictors.as_mut().and_then(|predictors| {
+            predictors.outcome(&mut self.history, self.sequence_index, |predictors, effects| {
+                predictors[effects.hart].step(effects)
+            })
+        });
+
         // find the instruction
-        let effects = &self.sequence[self.sequence_index];
+        let steps = self.history.len();
+        let effects = self.history.get(self.sequence_index);
         let pc = effects.instruction.address;
         let pc_i = self.addresses[&effects.instruction.address];
 
         // set the top label/status line
-        let label = if self.hex_mode {
-            format!("Step {}/{} PC:0x{:x}", self.sequence_index + 1, self.sequence.len(), pc)
+        let mut label = if self.hex_mode {
+            format!("Step {}/{} PC:0x{:x}", self.sequence_index + 1, steps, pc)
         } else {
-            format!("Step {}/{} PC:{}", self.sequence_index + 1, self.sequence.len(), pc)
+            format!("Step {}/{} PC:{}", self.sequence_index + 1, steps, pc)
         };
+        if self.machine.harts.len() > 1 {
+            write!(label, " Hart:{}", effects.hart).unwrap();
+        }
+        label.push_str(&cache_label);
 
         pane.label(&label);
 
@@ -515,6 +673,9 @@ impl Tui {
             )
         };
         let (start, end) = calc_range(length, cursor_index, pane.height);
+        let stages = if self.show_pipeline { self.pipeline_stages() } else { HashMap::new() };
+        let heat = if self.show_heat { self.heat.as_ref() } else { None };
+        let hottest = heat.and_then(|heat| heat.iter().max().copied()).unwrap_or(0);
 
         for i in start..end {
             // handle out-of-range lines
@@ -549,6 +710,7 @@ impl Tui {
                 inst.length == 2,
                 self.hex_mode,
                 self.verbose,
+                self.machine.style.registers,
                 self.show_addresses,
                 arrow,
                 &self.machine.address_symbols,
@@ -556,26 +718,73 @@ impl Tui {
             .chars()
             .collect();
 
+            // how many times the line ran, in a gutter on the left
+            let count = heat.map(|heat| {
+                let last = if self.verbose {
+                    index
+                } else {
+                    (index..self.instructions.len())
+                        .take_while(|&j| self.instructions[j].pseudo_index == i as usize)
+                        .last()
+                        .unwrap()
+                };
+                (index..=last).map(|j| heat[j]).max().unwrap()
+            });
+            if let Some(count) = count {
+                let gutter = if count == 0 { String::new() } else { compact_count(count) };
+                line.splice(0..0, format!("{:>5} ", gutter).chars());
+            }
+
             // render as chars to we can reliably pad to full width
             while line.len() < pane.width as usize || line.len() < 15 {
                 line.push(' ');
             }
 
+            // tag instructions in flight with their pipeline stage
+            if let Some(stage) = stages.get(&i) {
+                let tag: Vec<char> = format!(" {:<3} ", stage).chars().collect();
+                let at = line.len().min(pane.width as usize).saturating_sub(tag.len());
+                line.splice(at..at + tag.len(), tag);
+            }
+
             // draw the line in the correct color
             let line: String = line.iter().collect();
             if i as usize == pc_index {
                 pane.color = self.current_pc_color;
             } else if i as usize == cursor_index {
                 pane.color = self.cursor_color;
+            } else if let Some(count) = count {
+                pane.color = self.heat_colors[heat_level(count, hottest, self.heat_colors.len() - 1)];
             } else {
                 pane.color = self.normal_color;
             }
-            writeln!(pane, "{}", line).unwrap();
+
+            // color the arrow green or red when the branch predictor was right or wrong
+            let arrow_at = if arrow_color.is_some() { line.find(['┌', '└', '│']) } else { None };
+            if let (Some(correct), Some(at)) = (arrow_color, arrow_at) {
+                let line_color = pane.color;
+                let (before, rest) = line.split_at(at);
+                let (arrow, after) = rest.split_at(rest.char_indices().nth(3).map_or(rest.len(), |(n, _)| n));
+                write!(pane, "{}", before).unwrap();
+                pane.color = Colors {
+                    foreground: Some(if correct { Color::AnsiValue(71) } else { Color::AnsiValue(160) }),
+                    background: line_color.background,
+                };
+                write!(pane, "{}", arrow).unwrap();
+                pane.color = line_color;
+                writeln!(pane, "{}", after).unwrap();
+            } else {
+                writeln!(pane, "{}", line).unwrap();
+            }
             pane.color = self.normal_color;
         }
 
         // draw the side-effects label
-        let mut side_effects = self.sequence[self.sequence_index].report(self.hex_mode);
+        let effects = self.history.get(self.sequence_index);
+        let mut side_effects = effects.report(self.hex_mode, self.machine.style.registers);
+        if self.explain {
+            side_effects[0] = effects.explain(self.hex_mode, self.machine.style.registers);
+        }
         side_effects.truncate(2);
         if side_effects[0].is_empty() {
             side_effects.remove(0);
@@ -583,18 +792,46 @@ impl Tui {
         side_effects.join(", ")
     }
 
+    // the pipeline stage of each instruction in flight as the current one is
+    // fetched, keyed by source line (instruction index or pseudo index)
+    fn pipeline_stages(&mut self) -> HashMap<i64, &'static str> {
+        let mut stages = HashMap::new();
+        let hart = self.history.get(self.sequence_index).hart;
+        let oldest = self.history.chunk_start(self.sequence_index).max(self.sequence_index.saturating_sub(64));
+
+        // walk back through the hart's recent steps, counting cycles between them
+        let mut next = self.sequence_index;
+        let mut age = 0;
+        loop {
+            let inst = &self.history.get(next).instruction;
+            let line = if self.verbose { self.addresses[&inst.address] } else { inst.pseudo_index };
+            stages.entry(line as i64).or_insert(STAGES[age]);
+
+            let Some(prev) = (oldest..next).rev().find(|&i| self.history.get(i).hart == hart) else {
+                break;
+            };
+            let (load_use, control) = hazards(&self.history.get(prev).clone(), self.history.get(next));
+            age += 1 + load_use + control;
+            if age >= STAGES.len() {
+                break;
+            }
+            next = prev;
+        }
+        stages
+    }
+
     fn render_registers(&mut self, pane: &mut Pane) {
-        pane.label("Registers");
+        if self.machine.harts.len() > 1 {
+            pane.label(&format!("Registers (hart {})", self.machine.hart));
+        } else {
+            pane.label("Registers");
+        }
 
-        let lines = vec![
-            vec!["ra", "sp", "gp", "tp"],
-            vec!["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"],
-            vec!["t0", "t1", "t2", "t3", "t4", "t5", "t6"],
-            vec!["s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11"],
-        ];
+        let lines: [&[usize]; 4] = [&[RA, SP, GP, 4], &A_REGS, &T_REGS, &S_REGS];
         for line in lines {
-            for reg in line {
-                let val = self.machine.get(R.iter().position(|&r_str| r_str == reg).unwrap());
+            for &x in line {
+                let reg = self.machine.reg_name(x);
+                let val = self.machine.get(x);
                 if self.hex_mode && !(0..=9).contains(&val) {
                     write!(pane, "{}:0x{:x} ", reg, val).unwrap();
                 } else {
@@ -779,10 +1016,139 @@ impl Tui {
         writeln!(pane, "   home key/Fn-←    : rewind to start of current function      ").unwrap();
         writeln!(pane, "   enter/return     : fast forward to instruction under cursor ").unwrap();
         writeln!(pane, "   backspace/delete : rewind to instruction under cursor       ").unwrap();
+        writeln!(pane, "   b/B              : fast forward/rewind to next/prev ebreak  ").unwrap();
         writeln!(pane, "                                                               ").unwrap();
         writeln!(pane, " To toggle what is displayed:                                  ").unwrap();
         writeln!(pane, "   (r)egister pane, (o)utput pane, (s)tack pane, (d)ata pane   ").unwrap();
         writeln!(pane, "   (v)erbose mode, show (a)ddresses, use he(x)adecimal         ").unwrap();
+        writeln!(pane, "   cycle which (h)art is shown when running multiple harts     ").unwrap();
+        writeln!(pane, "   show 5-stage (p)ipeline stages of instructions in flight    ").unwrap();
+        writeln!(pane, "   show the (f)unction profile of the whole run                ").unwrap();
+        writeln!(pane, "   shade lines by execution (c)ount over the whole run         ").unwrap();
+        writeln!(pane, "   show the (e)ncoding of the instruction under the cursor     ").unwrap();
+        writeln!(pane, "   explain what each (i)nstruction does in plain English       ").unwrap();
+    }
+
+    fn render_encoding(&mut self, pane: &mut Pane) {
+        pane.label("Encoding");
+
+        let inst = &self.instructions[self.cursor_index];
+        let Ok((bits, length)) = self.machine.load_instruction(inst.address) else {
+            return;
+        };
+        let imm = inst.op.to_fields().iter().find_map(|field| match *field {
+            Field::Imm(imm) | Field::Indirect(imm, _) | Field::PCRelAddr(imm) => Some(imm),
+            _ => None,
+        });
+        let imm = match imm {
+            Some(imm) if self.hex_mode && !(0..=9).contains(&imm) => format!("  imm = 0x{:x}", imm),
+            Some(imm) => format!("  imm = {}", imm),
+            None => String::new(),
+        };
+
+        let (mnemonic, format, fields) = bit_fields(bits, length);
+        if length == 2 {
+            writeln!(pane, "0x{:04x}  {}, {}{}", bits & 0xffff, mnemonic, format, imm).unwrap();
+        } else {
+            writeln!(pane, "0x{:08x}  {}, {}{}", bits, mnemonic, format, imm).unwrap();
+        }
+        let (labels, values) = bit_field_rows(bits, &fields);
+        writeln!(pane, "{}", labels).unwrap();
+        writeln!(pane, "{}", values).unwrap();
+
+        // a compressed instruction is shorthand for a 32-bit one
+        if length == 2
+            && !matches!(inst.op, Op::Unimplemented { .. })
+            && let Ok(expanded) = inst.op.encode()
+        {
+            let (mnemonic, format, fields) = bit_fields(expanded, 4);
+            writeln!(pane, "expands to 0x{:08x}  {}, {}", expanded, mnemonic, format).unwrap();
+            let (labels, values) = bit_field_rows(expanded, &fields);
+            writeln!(pane, "{}", labels).unwrap();
+            writeln!(pane, "{}", values).unwrap();
+        }
+    }
+
+    fn render_profile(&mut self, pane: &mut Pane) {
+        pane.label("Profile");
+
+        let lines = self.profile.as_ref().unwrap();
+        let width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+        for line in lines {
+            writeln!(pane, " {:<width$} ", line).unwrap();
+        }
+    }
+}
+
+// a count in at most 5 characters
+fn compact_count(count: usize) -> String {
+    if count < 100_000 {
+        count.to_string()
+    } else if count < 10_000_000 {
+        format!("{}k", count / 1000)
+    } else {
+        format!("{}M", count / 1_000_000)
+    }
+}
+
+// heat level 0 for lines that never ran, else 1..=levels on a log scale up to the hottest line
+fn heat_level(count: usize, hottest: usize, levels: usize) -> usize {
+    if count == 0 {
+        0
+    } else if hottest <= 1 {
+        1
+    } else {
+        let scaled = (count as f64).ln() / (hottest as f64).ln() * levels as f64;
+        (scaled.ceil() as usize).clamp(1, levels)
+    }
+}
+
+// a simulation (caches, branch predictors) replayed over the history so the TUI
+// can show its outcome at any step; the state is saved every SAVE_STEPS steps
+// so moving backward only replays from the nearest saved state, and the live
+// state is kept so stepping forward and redrawing do not copy a saved one
+const SAVE_STEPS: usize = 16384;
+
+// the TUI saves a copy of the predictors every SAVE_STEPS steps, so it colors
+// branches with a table of at most this many index bits
+const TUI_PREDICTOR_BITS: usize = 16;
+
+struct Replayed<T, R> {
+    saved: Vec<T>,
+    current: Option<(usize, T, R)>, // the state after a step, and that step's outcome
+}
+
+impl<T: Clone, R: Clone> Replayed<T, R> {
+    fn new(initial: T) -> Self {
+        Replayed { saved: vec![initial], current: None }
+    }
+
+    fn outcome(&mut self, history: &mut History, step: usize, mut simulate: impl FnMut(&mut T, &Effects) -> R) -> R {
+        while self.saved.len() <= step / SAVE_STEPS {
+            let start = (self.saved.len() - 1) * SAVE_STEPS;
+            let mut state = self.saved.last().unwrap().clone();
+            for i in start..start + SAVE_STEPS {
+                simulate(&mut state, history.get(i));
+            }
+            self.saved.push(state);
+        }
+
+        let start = step - step % SAVE_STEPS;
+        let (mut next, mut state) = match self.current.take() {
+            Some((at, state, outcome)) if at == step => {
+                self.current = Some((at, state, outcome.clone()));
+                return outcome;
+            }
+            Some((at, state, _)) if at + 1 >= start && at < step => (at + 1, state),
+            _ => (start, self.saved[step / SAVE_STEPS].clone()),
+        };
+        while next < step {
+            simulate(&mut state, history.get(next));
+            next += 1;
+        }
+        let outcome = simulate(&mut state, history.get(step));
+        self.current = Some((step, state, outcome.clone()));
+        outcome
     }
 }
 
@@ -1001,27 +1367,36 @@ fn calc_range(length: usize, cursor: usize, window_size: u16) -> (i64, i64) {
     (start, end)
 }
 
-fn find_function_bounds(
-    symbols: &HashMap<i64, String>,
-    instructions: &[Rc<Instruction>],
-    current: usize,
-) -> (i64, i64) {
-    let (mut start_pc, mut end_pc) = (instructions[0].address, instructions.last().unwrap().address);
-    for instruction in instructions[0..=current].iter().rev() {
-        if let Some(label) = symbols.get(&instruction.address) {
-            if label.parse::<usize>().is_err() {
-                start_pc = instruction.address;
-                break;
-            }
-        }
-    }
-    for instruction in instructions[current + 1..].iter() {
-        if let Some(label) = symbols.get(&instruction.address) {
-            if label.parse::<usize>().is_err() {
-                end_pc = instruction.address;
-                break;
-            }
+// a plain ebreak (not a semihosting call) marks a spot to jump to
+fn is_bookmark(effects: &Effects) -> bool {
+    matches!(effects.instruction.op, Op::Ebreak) && effects.other_message.as_deref() == Some("ebreak")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replayed_outcome_matches_replaying_from_the_start() {
+        let instruction = Rc::new(Instruction {
+            address: 0x10000,
+            op: Op::Ecall,
+            length: 4,
+            pseudo_index: 0,
+            verbose_fields: Vec::new(),
+            pseudo_fields: Vec::new(),
+        });
+        let steps = 2 * SAVE_STEPS + 10;
+        let mut history = History::from_effects((0..steps).map(|_| Effects::new(&instruction, 0)).collect());
+
+        // the state counts the steps simulated, so the outcome of step i is i + 1
+        let mut replayed = Replayed::new(0);
+        for step in [0, 1, 1, 5, 3, 2 * SAVE_STEPS + 9, SAVE_STEPS, SAVE_STEPS - 1, SAVE_STEPS, 7, 2 * SAVE_STEPS] {
+            let outcome = replayed.outcome(&mut history, step, |count, _| {
+                *count += 1;
+                *count
+            });
+            assert_eq!(outcome, step + 1, "step {}", step);
         }
     }
-    (start_pc, end_pc)
 }